@@ -0,0 +1,766 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::instruction::{Extension, Instruction::{self, *}};
+use crate::sourcemap::{Location, SourceMap};
+
+/// Where ROMs are loaded.
+const START: u16 = 0x200;
+
+/// How deeply includes, macros and constants may nest, which also catches
+/// them referring to themselves.
+const MAX_DEPTH: usize = 16;
+
+const MNEMONICS: &[&str] = &[
+    "sys", "scd", "cls", "ret", "scr", "scl", "exit", "low", "high", "jp", "call", "se", "sne",
+    "save", "load", "ld", "add", "or", "and", "xor", "sub", "shr", "subn", "shl", "rnd", "drw",
+    "skp", "sknp", "plane", "audio", "pitch"
+];
+
+/// An assembled ROM, and the source line of each instruction in it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Assembly {
+    pub rom: Vec<u8>,
+    pub map: SourceMap
+}
+
+/// Assembles the file at <path>, allowing instructions up to <extension>.
+pub fn assemble_file(path: &Path, extension: Extension) -> Result<Assembly, String> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| format!("can't read '{}': {}", path.display(), e))?;
+    assemble(&source, path, extension)
+}
+
+/// Assembles Cowgod-syntax <source>, as written by `disasm`, with labels,
+/// `equ` constants, `db` and `dw` data, `include "file"` and macros. <path>
+/// names the source in errors and anchors relative includes. Every error
+/// found is reported, one per line, as `<file>:<line>: <message>`.
+pub fn assemble(source: &str, path: &Path, extension: Extension) -> Result<Assembly, String> {
+    let mut assembler = Assembler {
+        extension,
+        symbols: HashMap::new(),
+        macros: HashMap::new(),
+        defining: None,
+        statements: Vec::new(),
+        addr: START as usize,
+        errors: Vec::new()
+    };
+    assembler.source(path, source, 0);
+    if let Some((name, _, location)) = assembler.defining.take() {
+        assembler.error(&location, format!("macro '{}' has no endm", name));
+    }
+    let mut rom = Vec::new();
+    let mut map = SourceMap::new();
+    for (n, statement) in assembler.statements.iter().enumerate() {
+        match assembler.encode(statement) {
+            Ok((bytes, instruction)) => {
+                if instruction {
+                    map.add(statement.addr, &statement.location.file, statement.location.line);
+                }
+                rom.extend(bytes);
+            },
+            Err(e) => {
+                let message = format!("{}:{}: {}", statement.location.file.display(), statement.location.line, e);
+                assembler.errors.push((n, 1, message));
+            }
+        }
+    }
+    // report errors in source order, whichever pass found them
+    assembler.errors.sort_by_key(|&(n, pass, _)| (n, pass));
+    match assembler.errors.is_empty() {
+        true => Ok(Assembly { rom, map }),
+        false => Err(assembler.errors.into_iter().map(|(_, _, message)| message).collect::<Vec<_>>().join("\n"))
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Symbol {
+    Label(u16),
+    /// An `equ` expression, evaluated when used.
+    Constant(String)
+}
+
+#[derive(Clone, Debug)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>
+}
+
+/// A line that produces bytes, after includes and macros are expanded.
+struct Statement {
+    location: Location,
+    addr: u16,
+    mnemonic: String,
+    operands: String
+}
+
+/// An operand, told apart by its syntax alone.
+#[derive(Clone, Debug, PartialEq)]
+enum Operand {
+    V(u8),
+    /// `v<x>-v<y>`, for `save` and `load`.
+    Range(u8, u8),
+    I,
+    /// `[i]`
+    Indirect,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    /// `long <expr>`
+    Long(String),
+    Expression(String)
+}
+
+struct Assembler {
+    extension: Extension,
+    symbols: HashMap<String, Symbol>,
+    macros: HashMap<String, Macro>,
+    /// The macro being defined, and where.
+    defining: Option<(String, Macro, Location)>,
+    statements: Vec<Statement>,
+    addr: usize,
+    /// Messages, with the statement they come before or belong to and the
+    /// pass that found them.
+    errors: Vec<(usize, u8, String)>
+}
+
+impl Assembler {
+
+    fn error(&mut self, location: &Location, message: String) {
+        let message = format!("{}:{}: {}", location.file.display(), location.line, message);
+        self.errors.push((self.statements.len(), 0, message));
+    }
+
+    fn source(&mut self, path: &Path, text: &str, depth: usize) {
+        for (n, line) in text.lines().enumerate() {
+            let location = Location { file: path.to_path_buf(), line: n as u32 + 1 };
+            self.line(&location, line, depth);
+        }
+    }
+
+    /// The first pass over a line: defines its labels, constants and
+    /// macros, expands includes and macro uses, and lays out the rest.
+    fn line(&mut self, location: &Location, line: &str, depth: usize) {
+        let text = strip_comment(line).trim();
+        if let Some((_, definition, _)) = &mut self.defining {
+            match first_word(text).0.eq_ignore_ascii_case("endm") {
+                true => {
+                    let (name, definition, _) = self.defining.take().unwrap();
+                    self.macros.insert(name, definition);
+                },
+                false => definition.body.push(String::from(text))
+            }
+            return;
+        }
+        let text = match split_label(text) {
+            Some((label, rest)) => {
+                if self.addr > 0xffff {
+                    self.error(location, String::from("the program is too large"));
+                }
+                self.define(location, label, Symbol::Label(self.addr as u16));
+                rest
+            },
+            None => text
+        };
+        if text.is_empty() {
+            return;
+        }
+        let (word, rest) = first_word(text);
+        let (second, expression) = first_word(rest);
+        if second.eq_ignore_ascii_case("equ") {
+            self.define(location, word, Symbol::Constant(String::from(expression)));
+            return;
+        }
+        match word.to_ascii_lowercase().as_str() {
+            "macro" => {
+                let (name, params) = first_word(rest);
+                if !is_identifier(name) {
+                    return self.error(location, format!("invalid macro name '{}'", name));
+                }
+                let params = split_operands(params).into_iter().map(String::from).collect();
+                self.defining = Some((String::from(name), Macro { params, body: Vec::new() }, location.clone()));
+            },
+            "endm" => self.error(location, String::from("endm without macro")),
+            "include" => {
+                let name = match string(rest) {
+                    Some(name) => name,
+                    None => return self.error(location, String::from("expected include \"<file>\""))
+                };
+                if depth >= MAX_DEPTH {
+                    return self.error(location, String::from("includes nest too deeply"));
+                }
+                let path = location.file.parent().unwrap_or_else(|| Path::new("")).join(name);
+                match fs::read_to_string(&path) {
+                    Ok(text) => self.source(&path, &text, depth + 1),
+                    Err(e) => self.error(location, format!("can't read '{}': {}", path.display(), e))
+                }
+            },
+            _ => match self.macros.get(word).cloned() {
+                Some(definition) => self.expand(location, word, &definition, rest, depth),
+                None => {
+                    let mnemonic = word.to_ascii_lowercase();
+                    match size(&mnemonic, rest) {
+                        Ok(size) => {
+                            self.statements.push(Statement {
+                                location: location.clone(),
+                                addr: self.addr as u16,
+                                mnemonic,
+                                operands: String::from(rest)
+                            });
+                            self.addr += size;
+                        },
+                        Err(e) => self.error(location, e)
+                    }
+                }
+            }
+        }
+    }
+
+    fn define(&mut self, location: &Location, name: &str, symbol: Symbol) {
+        if !is_identifier(name) || operand(name) != Operand::Expression(String::from(name)) {
+            return self.error(location, format!("invalid name '{}'", name));
+        }
+        match self.symbols.contains_key(name) {
+            true => self.error(location, format!("'{}' is already defined", name)),
+            false => {
+                self.symbols.insert(String::from(name), symbol);
+            }
+        }
+    }
+
+    /// Assembles the body of <definition> with its parameters replaced by
+    /// <arguments>. Its lines are reported at <location>, where it's used.
+    fn expand(&mut self, location: &Location, name: &str, definition: &Macro, arguments: &str, depth: usize) {
+        let arguments = split_operands(arguments);
+        if arguments.len() != definition.params.len() {
+            let message = format!("macro '{}' takes {} arguments, got {}", name, definition.params.len(), arguments.len());
+            return self.error(location, message);
+        }
+        if depth >= MAX_DEPTH {
+            return self.error(location, format!("macro '{}' nests too deeply", name));
+        }
+        for line in definition.body.iter() {
+            let line = substitute(line, &definition.params, &arguments);
+            self.line(location, &line, depth + 1);
+        }
+    }
+
+    /// The second pass over a statement, returning its bytes and whether
+    /// they are an instruction.
+    fn encode(&self, statement: &Statement) -> Result<(Vec<u8>, bool), String> {
+        let here = statement.addr;
+        let operands = split_operands(&statement.operands);
+        match statement.mnemonic.as_str() {
+            "db" => {
+                let mut bytes = Vec::new();
+                for item in operands {
+                    match string(item) {
+                        Some(text) => bytes.extend(text.bytes()),
+                        None => bytes.push(self.byte(item, here)?)
+                    }
+                }
+                Ok((bytes, false))
+            },
+            "dw" => {
+                let mut bytes = Vec::new();
+                for item in operands {
+                    let value = self.range(item, here, -0x8000, 0xffff)?;
+                    bytes.extend((value as u16).to_be_bytes().iter());
+                }
+                Ok((bytes, false))
+            },
+            mnemonic => {
+                let operands: Vec<Operand> = operands.into_iter().map(operand).collect();
+                let instruction = self.instruction(mnemonic, &operands, here)?;
+                if instruction.extension() > self.extension {
+                    return Err(format!("{} is a {} instruction", mnemonic, instruction.extension().name()));
+                }
+                Ok((instruction.to_bytes(), true))
+            }
+        }
+    }
+
+    fn instruction(&self, mnemonic: &str, operands: &[Operand], here: u16) -> Result<Instruction, String> {
+        use Operand::*;
+        let addr = |e: &str| self.range(e, here, 0, 0xfff).map(|value| value as u16);
+        let byte = |e: &str| self.byte(e, here);
+        let nibble = |e: &str| self.range(e, here, 0, 0xf).map(|value| value as u8);
+        let instruction = match (mnemonic, operands) {
+            ("cls", []) => Cls,
+            ("ret", []) => Ret,
+            ("scr", []) => Scr,
+            ("scl", []) => Scl,
+            ("exit", []) => Exit,
+            ("low", []) => Low,
+            ("high", []) => High,
+            ("audio", []) => Audio,
+            ("sys", [Expression(e)]) => Sys(addr(e)?),
+            ("scd", [Expression(e)]) => Scd(nibble(e)?),
+            ("jp", [Expression(e)]) => Jp(addr(e)?),
+            ("jp", [V(0), Expression(e)]) => JpV0(addr(e)?),
+            ("call", [Expression(e)]) => Call(addr(e)?),
+            ("se", [V(x), V(y)]) => SeVxVy { x: *x, y: *y },
+            ("se", [V(x), Expression(e)]) => SeVxKk { x: *x, kk: byte(e)? },
+            ("sne", [V(x), V(y)]) => SneVxVy { x: *x, y: *y },
+            ("sne", [V(x), Expression(e)]) => SneVxKk { x: *x, kk: byte(e)? },
+            ("save", [Range(x, y)]) | ("save", [V(x), V(y)]) => Save { x: *x, y: *y },
+            ("load", [Range(x, y)]) | ("load", [V(x), V(y)]) => Load { x: *x, y: *y },
+            ("ld", [V(x), V(y)]) => LdVxVy { x: *x, y: *y },
+            ("ld", [V(x), Expression(e)]) => LdVxKk { x: *x, kk: byte(e)? },
+            ("ld", [V(x), Dt]) => LdVxDt(*x),
+            ("ld", [V(x), K]) => LdVxK(*x),
+            ("ld", [V(x), Indirect]) => LdVxI(*x),
+            ("ld", [V(x), R]) => LdVxR(*x),
+            ("ld", [I, Expression(e)]) => LdI(addr(e)?),
+            ("ld", [I, Long(e)]) => LdILong(self.range(e, here, 0, 0xffff)? as u16),
+            ("ld", [Dt, V(x)]) => LdDtVx(*x),
+            ("ld", [St, V(x)]) => LdStVx(*x),
+            ("ld", [F, V(x)]) => LdFVx(*x),
+            ("ld", [Hf, V(x)]) => LdHfVx(*x),
+            ("ld", [B, V(x)]) => LdBVx(*x),
+            ("ld", [Indirect, V(x)]) => LdIVx(*x),
+            ("ld", [R, V(x)]) => LdRVx(*x),
+            ("add", [V(x), V(y)]) => AddVxVy { x: *x, y: *y },
+            ("add", [V(x), Expression(e)]) => AddVxKk { x: *x, kk: byte(e)? },
+            ("add", [I, V(x)]) => AddIVx(*x),
+            ("or", [V(x), V(y)]) => Or { x: *x, y: *y },
+            ("and", [V(x), V(y)]) => And { x: *x, y: *y },
+            ("xor", [V(x), V(y)]) => Xor { x: *x, y: *y },
+            ("sub", [V(x), V(y)]) => SubVxVy { x: *x, y: *y },
+            ("subn", [V(x), V(y)]) => Subn { x: *x, y: *y },
+            // shifting a register by itself works whichever register the
+            // interpreter shifts
+            ("shr", [V(x)]) => Shr { x: *x, y: *x },
+            ("shr", [V(x), V(y)]) => Shr { x: *x, y: *y },
+            ("shl", [V(x)]) => Shl { x: *x, y: *x },
+            ("shl", [V(x), V(y)]) => Shl { x: *x, y: *y },
+            ("rnd", [V(x), Expression(e)]) => Rnd { x: *x, kk: byte(e)? },
+            ("drw", [V(x), V(y), Expression(e)]) => Drw { x: *x, y: *y, n: nibble(e)? },
+            ("skp", [V(x)]) => Skp(*x),
+            ("sknp", [V(x)]) => Sknp(*x),
+            ("plane", [Expression(e)]) => Plane(nibble(e)?),
+            ("pitch", [V(x)]) => Pitch(*x),
+            _ => return Err(format!("invalid operands for {}", mnemonic))
+        };
+        Ok(instruction)
+    }
+
+    /// A byte, which may be written as a negative number.
+    fn byte(&self, expression: &str, here: u16) -> Result<u8, String> {
+        self.range(expression, here, -0x80, 0xff).map(|value| value as u8)
+    }
+
+    fn range(&self, expression: &str, here: u16, min: i64, max: i64) -> Result<i64, String> {
+        let value = self.value(expression, here, 0)?;
+        match (min..=max).contains(&value) {
+            true => Ok(value),
+            false => {
+                let min = match min < 0 {
+                    true => format!("-{:#x}", -min),
+                    false => format!("{:#x}", min)
+                };
+                Err(format!("{} is out of range ({} to {:#x})", expression.trim(), min, max))
+            }
+        }
+    }
+
+    fn value(&self, expression: &str, here: u16, depth: usize) -> Result<i64, String> {
+        let tokens = tokens(expression)?;
+        let mut parser = Parser { assembler: self, tokens: &tokens, at: 0, here, depth };
+        let value = parser.or()?;
+        match tokens.get(parser.at) {
+            Some(token) => Err(format!("unexpected '{}' in '{}'", token, expression.trim())),
+            None => Ok(value)
+        }
+    }
+
+    fn symbol(&self, name: &str, here: u16, depth: usize) -> Result<i64, String> {
+        match self.symbols.get(name) {
+            Some(Symbol::Label(addr)) => Ok(*addr as i64),
+            Some(Symbol::Constant(_)) if depth >= MAX_DEPTH => Err(format!("'{}' is defined in terms of itself", name)),
+            Some(Symbol::Constant(expression)) => self.value(expression, here, depth + 1),
+            None => Err(format!("undefined symbol '{}'", name))
+        }
+    }
+
+}
+
+/// The bytes a statement lays out, worked out from its syntax so that the
+/// first pass can place labels.
+fn size(mnemonic: &str, operands: &str) -> Result<usize, String> {
+    let items = split_operands(operands);
+    match mnemonic {
+        "db" | "dw" if items.is_empty() => Err(format!("{} needs at least one value", mnemonic)),
+        "db" => Ok(items.iter().map(|item| string(item).map_or(1, |text| text.len())).sum()),
+        "dw" => Ok(items.len() * 2),
+        "ld" if matches!(items.as_slice(), [i, long] if operand(i) == Operand::I && matches!(operand(long), Operand::Long(_))) => Ok(4),
+        _ if MNEMONICS.contains(&mnemonic) => Ok(2),
+        _ => Err(format!("unknown instruction '{}'", mnemonic))
+    }
+}
+
+fn operand(text: &str) -> Operand {
+    let lower = text.to_ascii_lowercase();
+    let register = |name: &str| match name.trim().as_bytes() {
+        [b'v', digit] => (*digit as char).to_digit(16).map(|x| x as u8),
+        _ => None
+    };
+    match lower.as_str() {
+        "i" => Operand::I,
+        "[i]" => Operand::Indirect,
+        "dt" => Operand::Dt,
+        "st" => Operand::St,
+        "k" => Operand::K,
+        "f" => Operand::F,
+        "hf" => Operand::Hf,
+        "b" => Operand::B,
+        "r" => Operand::R,
+        _ => {
+            if let Some(x) = register(&lower) {
+                return Operand::V(x);
+            }
+            if let Some((x, y)) = lower.split_once('-').and_then(|(x, y)| Some((register(x)?, register(y)?))) {
+                return Operand::Range(x, y);
+            }
+            match first_word(text) {
+                (word, rest) if word.eq_ignore_ascii_case("long") && !rest.is_empty() => Operand::Long(String::from(rest)),
+                _ => Operand::Expression(String::from(text))
+            }
+        }
+    }
+}
+
+/// <line> up to any `;` comment outside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (at, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..at],
+            _ => ()
+        }
+    }
+    line
+}
+
+/// The first whitespace-separated word of <text> and the trimmed rest.
+fn first_word(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (text, "")
+    }
+}
+
+/// Splits `<label>: <rest>`.
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let end = text.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))?;
+    match text[end..].strip_prefix(':') {
+        Some(rest) if end > 0 => Some((&text[..end], rest.trim())),
+        _ => None
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.') &&
+        chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Splits operands at commas outside strings and parentheses.
+fn split_operands(text: &str) -> Vec<&str> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Vec::new();
+    }
+    let mut operands = Vec::new();
+    let (mut start, mut depth, mut quoted, mut escaped) = (0, 0, false, false);
+    for (at, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                operands.push(text[start..at].trim());
+                start = at + 1;
+            },
+            _ => ()
+        }
+    }
+    operands.push(text[start..].trim());
+    operands
+}
+
+/// The contents of a `"` quoted string, with `\"`, `\\` and `\n` escapes.
+fn string(text: &str) -> Option<String> {
+    let inner = text.trim().strip_prefix('"')?.strip_suffix('"')?;
+    let mut result = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next()? {
+                'n' => result.push('\n'),
+                c => result.push(c)
+            },
+            '"' => return None,
+            c => result.push(c)
+        }
+    }
+    Some(result)
+}
+
+/// Replaces whole words of <line> that name a parameter with the argument.
+fn substitute(line: &str, params: &[String], arguments: &[&str]) -> String {
+    let mut result = String::new();
+    let mut word = String::new();
+    let flush = |word: &mut String, result: &mut String| {
+        match params.iter().position(|param| param == word) {
+            Some(n) => result.push_str(arguments[n]),
+            None => result.push_str(word)
+        }
+        word.clear();
+    };
+    for c in line.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+            word.push(c);
+        } else {
+            flush(&mut word, &mut result);
+            result.push(c);
+        }
+    }
+    flush(&mut word, &mut result);
+    result
+}
+
+fn tokens(expression: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$' {
+            let mut token = String::new();
+            while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '.' || *c == '$') {
+                token.push(c);
+            }
+            tokens.push(token);
+        } else if c == '<' || c == '>' {
+            chars.next();
+            match chars.next() {
+                Some(next) if next == c => tokens.push(format!("{}{}", c, c)),
+                _ => return Err(format!("expected '{}{}' in '{}'", c, c, expression.trim()))
+            }
+        } else if "+-*/%&|^~()".contains(c) {
+            chars.next();
+            tokens.push(c.to_string());
+        } else {
+            return Err(format!("unexpected '{}' in '{}'", c, expression.trim()));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Evaluates an expression with C's operators and precedence, from `|`
+/// down to unary `-` and `~`.
+struct Parser<'a> {
+    assembler: &'a Assembler,
+    tokens: &'a [String],
+    at: usize,
+    here: u16,
+    depth: usize
+}
+
+impl<'a> Parser<'a> {
+
+    fn eat(&mut self, token: &str) -> bool {
+        let found = self.tokens.get(self.at).is_some_and(|next| next == token);
+        if found {
+            self.at += 1;
+        }
+        found
+    }
+
+    /// Parses operands of the <operators> joined by <next>.
+    fn binary(&mut self, operators: &[&str], next: fn(&mut Self) -> Result<i64, String>) -> Result<i64, String> {
+        let mut value = next(self)?;
+        while let Some(&operator) = operators.iter().find(|operator| self.tokens.get(self.at).is_some_and(|token| token == *operator)) {
+            self.at += 1;
+            let right = next(self)?;
+            value = match operator {
+                "|" => value | right,
+                "^" => value ^ right,
+                "&" => value & right,
+                "<<" => value.checked_shl(right as u32).unwrap_or(0),
+                ">>" => value.checked_shr(right as u32).unwrap_or(0),
+                "+" => value.wrapping_add(right),
+                "-" => value.wrapping_sub(right),
+                "*" => value.wrapping_mul(right),
+                _ if right == 0 => return Err(String::from("division by zero")),
+                "/" => value.checked_div(right).ok_or("division overflows")?,
+                _ => value.checked_rem(right).ok_or("division overflows")?
+            };
+        }
+        Ok(value)
+    }
+
+    fn or(&mut self) -> Result<i64, String> {
+        self.binary(&["|"], Self::xor)
+    }
+
+    fn xor(&mut self) -> Result<i64, String> {
+        self.binary(&["^"], Self::and)
+    }
+
+    fn and(&mut self) -> Result<i64, String> {
+        self.binary(&["&"], Self::shift)
+    }
+
+    fn shift(&mut self) -> Result<i64, String> {
+        self.binary(&["<<", ">>"], Self::sum)
+    }
+
+    fn sum(&mut self) -> Result<i64, String> {
+        self.binary(&["+", "-"], Self::product)
+    }
+
+    fn product(&mut self) -> Result<i64, String> {
+        self.binary(&["*", "/", "%"], Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        if self.eat("-") {
+            return Ok(self.unary()?.wrapping_neg());
+        }
+        if self.eat("~") {
+            return Ok(!self.unary()?);
+        }
+        if self.eat("(") {
+            let value = self.or()?;
+            return match self.eat(")") {
+                true => Ok(value),
+                false => Err(String::from("expected ')'"))
+            };
+        }
+        let token = self.tokens.get(self.at).ok_or("expected a value")?;
+        self.at += 1;
+        let number = |digits: &str, radix| i64::from_str_radix(&digits.replace('_', ""), radix).ok();
+        let value = match token.as_str() {
+            "$" => Some(self.here as i64),
+            _ if token.starts_with("0x") || token.starts_with("0X") => number(&token[2..], 16),
+            _ if token.starts_with("0b") || token.starts_with("0B") => number(&token[2..], 2),
+            _ if token.starts_with(|c: char| c.is_ascii_digit()) => number(token, 10),
+            _ => return self.assembler.symbol(token, self.here, self.depth)
+        };
+        value.ok_or(format!("invalid number '{}'", token))
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::{self, Syntax};
+
+    fn rom(source: &str) -> Result<Vec<u8>, String> {
+        assemble(source, Path::new("test.asm"), Extension::Xochip).map(|assembly| assembly.rom)
+    }
+
+    #[test]
+    fn program() {
+        let source = "\
+size equ 5 ; a constant
+macro draw x, y
+    ld i, sprite
+    drw x, y, size
+endm
+
+start:
+    ld v0, size * 2 - 1
+    draw v0, v1
+    shr v3
+    save v0-v2
+    ld i, long end
+loop: jp loop
+sprite: db 0x3c, \"a;\", -1
+end: dw $, 0x1234
+";
+        assert_eq!(rom(source), Ok(vec![
+            0x60, 0x09, 0xa2, 0x10, 0xd0, 0x15, 0x83, 0x36, 0x50, 0x22, 0xf0, 0x00, 0x02, 0x14,
+            0x12, 0x0e, 0x3c, 0x61, 0x3b, 0xff, 0x02, 0x14, 0x12, 0x34
+        ]));
+
+        let assembly = assemble(source, Path::new("test.asm"), Extension::Xochip).unwrap();
+        let location = assembly.map.location(0x20e).unwrap();
+        assert_eq!((location.file.as_path(), location.line), (Path::new("test.asm"), 13));
+        assert_eq!(assembly.map.location(0x204).unwrap().line, 9);
+    }
+
+    #[test]
+    fn errors() {
+        let source = "\
+    ld v0, 256
+    jp nowhere
+    frob v1
+    plane 1
+x: cls
+x: cls
+    ld v0, k, 1
+    db
+    include \"missing.asm\"
+";
+        let errors = assemble(source, Path::new("test.asm"), Extension::Schip).unwrap_err();
+        let lines: Vec<&str> = errors.lines().collect();
+        assert_eq!(lines, [
+            "test.asm:1: 256 is out of range (-0x80 to 0xff)",
+            "test.asm:2: undefined symbol 'nowhere'",
+            "test.asm:3: unknown instruction 'frob'",
+            "test.asm:4: plane is a XO-CHIP instruction",
+            "test.asm:6: 'x' is already defined",
+            "test.asm:7: invalid operands for ld",
+            "test.asm:8: db needs at least one value",
+            "test.asm:9: can't read 'missing.asm': No such file or directory (os error 2)"
+        ]);
+        assert!(rom("x equ y\ny equ x\n    ld v0, x").unwrap_err().contains("is defined in terms of itself"));
+        assert!(rom("macro m\n    m\nendm\n    m").unwrap_err().contains("nests too deeply"));
+        assert!(rom("    ld v0, (1<<63)/-1").unwrap_err().contains("division overflows"));
+        assert!(rom("    ld v0, (1<<63)%-1").unwrap_err().contains("division overflows"));
+        assert!(rom("    ld v0, 1/0").unwrap_err().contains("division by zero"));
+        assert!(rom("    ld v0, -(1<<63)").unwrap_err().contains("out of range"));
+    }
+
+    #[test]
+    fn include() {
+        let dir = std::env::temp_dir().join(format!("chip8-asm-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.asm"), "    call sub\n    include \"lib.asm\"\n").unwrap();
+        fs::write(dir.join("lib.asm"), "sub:\n    ret\n").unwrap();
+        let assembly = assemble_file(&dir.join("main.asm"), Extension::Chip8).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(assembly.rom, [0x22, 0x02, 0x00, 0xee]);
+        assert_eq!(assembly.map.location(0x202).unwrap().file, dir.join("lib.asm"));
+    }
+
+    #[test]
+    fn round_trip() {
+        // every instruction, reached by falling through, then data
+        let mut rom: Vec<u8> = (0..=0xffffu32).step_by(0x0101)
+            .map(|opcode| Instruction::decode(opcode as u16))
+            .filter(|instruction| !matches!(instruction, Invalid(_) | Jp(_) | JpV0(_) | Ret | Exit | LdILong(_)))
+            .flat_map(|instruction| instruction.to_bytes())
+            .collect();
+        rom.extend([0xf0, 0x00, 0x02, 0x00, 0x22, 0x00, 0x00, 0xfd, 0x01, 0x02, 0xff]);
+        let listing = disasm::disassemble(&rom, Syntax::Cowgod);
+        assert_eq!(self::rom(&listing), Ok(rom), "{}", listing);
+    }
+
+}
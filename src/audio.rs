@@ -0,0 +1,295 @@
+#[cfg(any(feature = "audio", test))]
+use std::f32::consts::PI;
+#[cfg(any(feature = "audio", test))]
+use std::sync::{Arc, Mutex};
+#[cfg(any(feature = "audio", test))]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+#[cfg(feature = "audio")]
+use rodio::{Device, Source};
+
+#[cfg(any(feature = "audio", test))]
+const SAMPLE_RATE: u32 = 44100;
+const DEFAULT_FREQUENCY: f32 = 440.0;
+const DEFAULT_VOLUME: f32 = 0.25;
+const DEFAULT_FADE_MS: u32 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine
+}
+
+impl Waveform {
+
+    pub fn parse(name: &str) -> Option<Waveform> {
+        match name {
+            "square" => Some(Waveform::Square),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" | "saw" => Some(Waveform::Sawtooth),
+            "sine" => Some(Waveform::Sine),
+            _ => None
+        }
+    }
+
+    /// Samples the waveform at <phase>, in the range [0, 1), returning a
+    /// value in the range [-1, 1].
+    #[cfg(any(feature = "audio", test))]
+    fn sample(&self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Sine => (2.0 * PI * phase).sin()
+        }
+    }
+
+}
+
+/// The beeper's tone. <volume> is in the range [0, 1], and <fade> is the
+/// time taken to ramp between silence and full volume.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tone {
+    pub frequency: f32,
+    pub volume: f32,
+    pub waveform: Waveform,
+    pub fade: Duration
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone {
+            frequency: DEFAULT_FREQUENCY,
+            volume: DEFAULT_VOLUME,
+            waveform: Waveform::Square,
+            fade: Duration::from_millis(DEFAULT_FADE_MS as u64)
+        }
+    }
+}
+
+/// An XO-CHIP audio pattern: 128 1-bit samples played in a loop.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pattern {
+    pub bits: [u8; 16],
+    pub pitch: u8
+}
+
+impl Pattern {
+
+    /// The playback rate in bits per second, `4000 * 2 ^ ((pitch - 64) / 48)`.
+    pub fn rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    /// Samples the pattern at <phase>, in the range [0, 1) across all 128
+    /// bits, returning 1 for a set bit and -1 for a clear one.
+    #[cfg(any(feature = "audio", test))]
+    fn sample(&self, phase: f32) -> f32 {
+        let bit = (phase * 128.0) as usize % 128;
+        match (self.bits[bit / 8] >> (7 - bit % 8)) & 1 {
+            1 => 1.0,
+            _ => -1.0
+        }
+    }
+
+}
+
+/// Something that can sound the beeper.
+pub trait Beeper {
+    /// Called once per frame with whether the sound timer is active.
+    fn set_active(&mut self, active: bool);
+    /// Called once per frame with the XO-CHIP pattern to play in place of
+    /// the tone, or None to play the tone.
+    fn set_pattern(&mut self, pattern: Option<Pattern>);
+}
+
+/// A beeper that makes no sound, for headless runs and machines without
+/// an audio device.
+pub struct NullBeeper;
+
+impl Beeper for NullBeeper {
+    fn set_active(&mut self, _active: bool) {}
+    fn set_pattern(&mut self, _pattern: Option<Pattern>) {}
+}
+
+/// A beeper that plays through the default rodio output device.
+#[cfg(feature = "audio")]
+pub struct RodioBeeper {
+    _device: Device,
+    _sink: rodio::Sink,
+    gate: Arc<AtomicBool>,
+    pattern: Arc<Mutex<Option<Pattern>>>
+}
+
+#[cfg(feature = "audio")]
+impl RodioBeeper {
+
+    /// Opens the default output device, or returns None if there isn't one.
+    pub fn new(tone: Tone) -> Option<Self> {
+        let device = rodio::default_output_device()?;
+        let sink = rodio::Sink::new(&device);
+        let gate = Arc::new(AtomicBool::new(false));
+        let pattern = Arc::new(Mutex::new(None));
+        sink.append(Oscillator::new(tone, gate.clone(), pattern.clone()));
+        Some(RodioBeeper {
+            _device: device,
+            _sink: sink,
+            gate,
+            pattern
+        })
+    }
+
+}
+
+#[cfg(feature = "audio")]
+impl Beeper for RodioBeeper {
+    fn set_active(&mut self, active: bool) {
+        self.gate.store(active, Ordering::Relaxed);
+    }
+
+    fn set_pattern(&mut self, pattern: Option<Pattern>) {
+        if let Ok(mut current) = self.pattern.lock() {
+            *current = pattern;
+        }
+    }
+}
+
+/// Returns a rodio beeper if an output device is available, otherwise a
+/// null beeper.
+#[cfg(feature = "audio")]
+pub fn beeper(tone: Tone) -> Box<dyn Beeper> {
+    match RodioBeeper::new(tone) {
+        Some(beeper) => Box::new(beeper),
+        None => Box::new(NullBeeper)
+    }
+}
+
+/// Returns a null beeper, as there's no audio support in this build.
+#[cfg(not(feature = "audio"))]
+pub fn beeper(_tone: Tone) -> Box<dyn Beeper> {
+    Box::new(NullBeeper)
+}
+
+/// An endless tone whose amplitude ramps towards the tone's volume while
+/// the gate is open, and towards silence while it is closed. While a
+/// pattern is set, it's played in place of the tone's waveform.
+#[cfg(any(feature = "audio", test))]
+struct Oscillator {
+    tone: Tone,
+    gate: Arc<AtomicBool>,
+    pattern: Arc<Mutex<Option<Pattern>>>,
+    current: Option<Pattern>,
+    phase: f32,
+    amplitude: f32,
+    ramp: f32
+}
+
+#[cfg(any(feature = "audio", test))]
+impl Oscillator {
+
+    fn new(tone: Tone, gate: Arc<AtomicBool>, pattern: Arc<Mutex<Option<Pattern>>>) -> Self {
+        let samples = tone.fade.as_secs_f32() * SAMPLE_RATE as f32;
+        Oscillator {
+            tone,
+            gate,
+            pattern,
+            current: None,
+            phase: 0.0,
+            amplitude: 0.0,
+            ramp: tone.volume / samples.max(1.0)
+        }
+    }
+
+}
+
+#[cfg(any(feature = "audio", test))]
+impl Iterator for Oscillator {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if let Ok(pattern) = self.pattern.try_lock() {
+            if *pattern != self.current {
+                self.current = *pattern;
+                self.phase = 0.0;
+            }
+        }
+        let target = if self.gate.load(Ordering::Relaxed) { self.tone.volume } else { 0.0 };
+        if self.amplitude < target {
+            self.amplitude = (self.amplitude + self.ramp).min(target);
+        } else if self.amplitude > target {
+            self.amplitude = (self.amplitude - self.ramp).max(target);
+        }
+        let (sample, frequency) = match &self.current {
+            Some(pattern) => (pattern.sample(self.phase), pattern.rate() / 128.0),
+            None => (self.tone.waveform.sample(self.phase), self.tone.frequency)
+        };
+        self.phase = (self.phase + frequency / SAMPLE_RATE as f32).fract();
+        Some(sample * self.amplitude)
+    }
+}
+
+#[cfg(feature = "audio")]
+impl Source for Oscillator {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn oscillator(pattern: Option<Pattern>) -> Oscillator {
+        let tone = Tone { volume: 1.0, fade: Duration::from_millis(0), ..Tone::default() };
+        let gate = Arc::new(AtomicBool::new(true));
+        Oscillator::new(tone, gate, Arc::new(Mutex::new(pattern)))
+    }
+
+    #[test]
+    fn rate() {
+        let pattern = Pattern { bits: [0; 16], pitch: 64 };
+        assert_eq!(pattern.rate(), 4000.0);
+        assert_eq!(Pattern { pitch: 112, ..pattern }.rate(), 8000.0);
+        assert_eq!(Pattern { pitch: 16, ..pattern }.rate(), 2000.0);
+    }
+
+    #[test]
+    fn pattern() {
+        // 4000 bits per second is 11.025 samples per bit, so the first byte
+        // lasts about 88 samples and the whole pattern about 1411
+        let mut bits = [0; 16];
+        bits[0] = 0xff;
+        let samples: Vec<f32> = oscillator(Some(Pattern { bits, pitch: 64 })).take(1500).collect();
+        assert!(samples[0..88].iter().all(|&sample| sample == 1.0));
+        assert!(samples[89..1411].iter().all(|&sample| sample == -1.0));
+        assert!(samples[1412..1499].iter().all(|&sample| sample == 1.0));
+        // an octave up halves the length of each bit
+        let samples: Vec<f32> = oscillator(Some(Pattern { bits, pitch: 112 })).take(100).collect();
+        assert!(samples[0..44].iter().all(|&sample| sample == 1.0));
+        assert!(samples[45..100].iter().all(|&sample| sample == -1.0));
+    }
+
+    #[test]
+    fn tone() {
+        // 440 Hz is just over 100 samples per cycle, high for the first half
+        let samples: Vec<f32> = oscillator(None).take(100).collect();
+        assert!(samples[0..50].iter().all(|&sample| sample == 1.0));
+        assert!(samples[51..100].iter().all(|&sample| sample == -1.0));
+    }
+
+}
@@ -1,27 +1,52 @@
-use crate::cpu::{Cpu, CpuContext};
-use crate::gpu::Gpu;
-use crate::timer::Timer;
-use crate::keypad::Keypad;
+use chip8::machine::Machine;
+use chip8::gpu::Framebuffer;
+use chip8::timer::{Timer, FRAME_RATE};
+use chip8::audio::{Beeper, NullBeeper, Pattern};
+use chip8::rewind::{self, Rewind};
+use chip8::movie::{Player, Recorder};
+use chip8::debugger::{Debugger, Stop};
+use chip8::console::{self, Action, Console};
+use chip8::octo::Monitor;
+use crate::keymap::Keymap;
+use crate::overlay;
 
-use std::collections::HashSet;
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
 
 use coffee::{Game, Result};
 use coffee::load::{Task};
-use coffee::input::{Input};
 use coffee::input::keyboard::{KeyCode};
-use coffee::graphics::{Frame, Window, WindowSettings};
+use coffee::graphics::{Color, Frame, Mesh, Rectangle, Shape, Window, WindowSettings};
 
-const DEFAULT_CLOCK_RATE: u32 = 166666667;
 const DEFAULT_WIDTH: u32 = 64;
 const DEFAULT_HEIGHT: u32 = 32;
-const SCALE: u32 = 10;
+
+thread_local! {
+    /// The machine handed to `Game::load`, which can't take arguments.
+    static PENDING: RefCell<Option<Chip>> = const { RefCell::new(None) };
+}
 
 pub struct Chip {
-    sound_timer: Timer,
-    delay_timer: Timer,
-    gpu: Gpu,
-    cpu: Cpu,
-    keypad: Keypad,
+    clock: Timer,
+    machine: Machine,
+    keymap: Keymap,
+    beeper: Box<dyn Beeper>,
+    patterns: bool,
+    state: PathBuf,
+    rewind: Option<Rewind>,
+    rewinding: bool,
+    recorder: Option<(Recorder, PathBuf)>,
+    player: Option<Player>,
+    debugger: Debugger,
+    console: Option<(Console, Receiver<String>)>,
+    monitors: Vec<Monitor>,
+    overlay: bool,
     autorun: bool,
     step: bool
 }
@@ -33,52 +58,114 @@ impl Game for Chip {
     type LoadingScreen = ();
 
     fn load(_window: &Window) -> Task<Chip> {
-        let rom = std::fs::read("main.ch8").unwrap();
-        let mut chip = Chip::new();
-        chip.load(&rom[0..]);
+        let chip = PENDING.with(|pending| pending.borrow_mut().take())
+            .unwrap_or_else(|| Chip::new(Machine::new()));
         Task::succeed(|| chip)
     }
 
     fn interact(&mut self, input: &mut Self::Input, _window: &mut Window) {
-        let mapping = vec![
-            KeyCode::Q, KeyCode::W, KeyCode::E,
-            KeyCode::A, KeyCode::A, KeyCode::D, 
-            KeyCode::Z, KeyCode::X, KeyCode::C,
-        ];
         let keyboard = input.keyboard();
-        for x in 0..mapping.len() {
-            let key = x as usize;
-            let pressed = keyboard.is_key_pressed(mapping[key]);
-            self.keypad.set(key, pressed);
-        }
-        if keyboard.was_key_released(KeyCode::F6) {
-            self.step = true;
+        for key in 0..16 {
+            let pressed = self.keymap.get(key).iter()
+                .any(|&code| keyboard.is_key_pressed(code));
+            self.machine.keypad.set(key, pressed);
         }
+        self.rewinding = keyboard.is_key_pressed(KeyCode::Back) && self.player.is_none();
         if keyboard.was_key_released(KeyCode::F1) {
             self.autorun = !self.autorun;
+            self.debugger.resume();
+            self.clock.reset();
+        }
+        if keyboard.was_key_released(KeyCode::F3) {
+            self.overlay = !self.overlay;
+        }
+        if keyboard.was_key_released(KeyCode::F5) {
+            self.save_state();
+        }
+        // stepping, resetting and loading states would desync a movie
+        let movie = self.recorder.is_some() || self.player.is_some();
+        let released = [KeyCode::F2, KeyCode::F6, KeyCode::F9].iter()
+            .any(|&code| keyboard.was_key_released(code));
+        if movie && released {
+            eprintln!("chip8: F2, F6 and F9 are disabled while a movie is recording or playing");
+            return;
+        }
+        if keyboard.was_key_released(KeyCode::F6) {
+            self.debugger.step();
+            self.step = true;
         }
         if keyboard.was_key_released(KeyCode::F2) {
-            self.gpu.reset();
-            self.cpu.reset();
+            self.machine.reset();
+            self.debugger.restart_frame();
+        }
+        if keyboard.was_key_released(KeyCode::F9) {
+            self.load_state();
         }
     }
 
+    fn on_close_request(&mut self) -> bool {
+        if let Some((recorder, path)) = &self.recorder {
+            match recorder.movie().save(path) {
+                Ok(()) => println!("saved movie to {}", path.display()),
+                Err(e) => eprintln!("chip8: {}", e)
+            }
+        }
+        if let Err(e) = self.machine.finish_trace() {
+            eprintln!("chip8: {}", e);
+        }
+        true
+    }
+
     fn draw(&mut self, frame: &mut Frame, _timer: &coffee::Timer) {
-        if self.step || self.autorun {
-//            self.dump();
-            self.cycle(frame);
+        self.read_console();
+        if self.rewinding {
+            for _ in 0..self.clock.tick() {
+                self.step_back();
+            }
+        } else if self.autorun {
+            for _ in 0..self.clock.tick() {
+                if let Err(e) = self.frame() {
+                    eprintln!("chip8: {}", e);
+                    self.pause();
+                    break;
+                }
+                self.save_history();
+            }
+        } else if self.step {
+            match self.debugger.cycle(&mut self.machine) {
+                Some(Stop::Step) | None => (),
+                Some(stop) => {
+                    eprintln!("chip8: {}", stop);
+                    self.show_location();
+                }
+            }
             self.step = false;
         }
+        self.beeper.set_active(self.autorun && !self.rewinding && self.machine.cpu.sound());
+        self.beeper.set_pattern(match self.patterns {
+            true => Some(Pattern { bits: *self.machine.cpu.pattern(), pitch: self.machine.cpu.pitch() }),
+            false => None
+        });
+        if self.overlay {
+            let width = frame.width() - overlay::width(frame.height());
+            render(&self.machine.framebuffer(), frame, width);
+            overlay::draw(&self.machine, frame);
+        } else {
+            let width = frame.width();
+            render(&self.machine.framebuffer(), frame, width);
+        }
     }
 }
 
 impl Chip {
 
-    pub fn execute() -> Result<()> {
-        let width = DEFAULT_WIDTH * SCALE;
-        let height = DEFAULT_HEIGHT * SCALE;
+    /// Opens a window and runs <chip> in it until the window is closed.
+    pub fn execute(chip: Chip, title: &str, scale: u32) -> Result<()> {
+        let width = DEFAULT_WIDTH * scale;
+        let height = DEFAULT_HEIGHT * scale;
+        PENDING.with(|pending| *pending.borrow_mut() = Some(chip));
         Chip::run(WindowSettings {
-            title: String::from("chip-8"),
+            title: format!("chip-8 - {}", title),
             size: (width, height),
             resizable: false,
             fullscreen: false,
@@ -86,45 +173,245 @@ impl Chip {
         })
     }
 
-    pub fn new() -> Self {
+    pub fn new(machine: Machine) -> Self {
         Chip {
-            sound_timer: Timer::new(DEFAULT_CLOCK_RATE),
-            delay_timer: Timer::new(DEFAULT_CLOCK_RATE),
-            cpu: Cpu::new(),
-            gpu: Gpu::new(),
-            keypad: Keypad::new(),
+            clock: Timer::new(FRAME_RATE),
+            machine,
+            keymap: Keymap::default(),
+            beeper: Box::new(NullBeeper),
+            patterns: false,
+            state: PathBuf::from("chip8.state"),
+            rewind: Some(Rewind::new(rewind::DEFAULT_BUDGET)),
+            rewinding: false,
+            recorder: None,
+            player: None,
+            debugger: Debugger::new(),
+            console: None,
+            monitors: Vec::new(),
+            overlay: false,
             step: false,
             autorun: true
         }
     }
-    
+
     pub fn dump(&self) {
-        self.cpu.dump();
+        self.machine.cpu.dump();
     }
 
-    pub fn load(&mut self, rom: &[u8]) {
-        self.reset();
-        self.cpu.load(rom);
+    /// Stops running and dumps the cpu state for inspection.
+    fn pause(&mut self) {
+        self.autorun = false;
+        self.show_location();
     }
 
-    pub fn reset(&mut self) {
-        self.cpu.reset();
-        self.gpu.reset();
+    /// Dumps the cpu state and any monitored memory, or shows the current
+    /// instruction and prompts for a command if the console is attached.
+    fn show_location(&self) {
+        match self.console {
+            Some(_) => prompt(&console::location(&self.machine)),
+            None => {
+                self.dump();
+                for monitor in self.monitors.iter() {
+                    println!("{}", monitor.show(&self.machine));
+                }
+            }
+        }
+    }
+
+    /// Takes debugger commands from stdin, as `chip8 headless --console`
+    /// does, while the window runs.
+    pub fn attach_console(&mut self) {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines().map_while(|line| line.ok()) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut console = Console::new();
+        console.set_monitors(self.monitors.clone());
+        self.console = Some((console, receiver));
+        prompt(&console::location(&self.machine));
     }
-    
-    pub fn cycle(&mut self, frame: &mut Frame) {        
-        self.sound_timer.tick();
-        self.delay_timer.tick();
 
-        let mut ctx = CpuContext {
-            opcode: 0,
-            sound_timer: &mut self.sound_timer,
-            delay_timer: &mut self.delay_timer,
-            gpu: &mut self.gpu
+    /// Runs the console commands typed since the last frame.
+    fn read_console(&mut self) {
+        let (console, receiver) = match &mut self.console {
+            Some(console) => console,
+            None => return
         };
+        let mut quit = false;
+        while let Ok(line) = receiver.try_recv() {
+            match console.execute(&mut self.debugger, &mut self.machine, &line) {
+                Ok(Action::Print(text)) => prompt(&text),
+                Ok(Action::Run) => {
+                    self.autorun = true;
+                    self.clock.reset();
+                },
+                Ok(Action::Quit) => {
+                    quit = true;
+                    break;
+                },
+                Err(e) => prompt(&format!("error: {}", e))
+            }
+        }
+        if quit {
+            self.on_close_request();
+            process::exit(0);
+        }
+    }
 
-        self.cpu.cycle(&mut ctx);
-        self.gpu.render(frame);
+    /// Runs a frame, with input from the movie being replayed, if any, and
+    /// recording it to the movie being recorded, if any. Otherwise runs it
+    /// under the debugger, which may stop partway through.
+    fn frame(&mut self) -> std::result::Result<(), String> {
+        if let Some(player) = &mut self.player {
+            match player.frame(&mut self.machine) {
+                Ok(true) => return Ok(()),
+                Ok(false) => println!("replay finished after {} frames", player.position()),
+                Err(e) => {
+                    self.player = None;
+                    return Err(e);
+                }
+            }
+            self.player = None;
+        }
+        if let Some((recorder, _)) = &mut self.recorder {
+            return recorder.frame(&mut self.machine)
+                .map_err(|fault| format!("{} at {:#06x}", fault, self.machine.cpu.pc()));
+        }
+        match self.debugger.frame(&mut self.machine) {
+            Some(stop) => Err(format!("{} (pc {:#06x})", stop, self.machine.cpu.pc())),
+            None => Ok(())
+        }
+    }
+
+    /// Adds the current frame to the rewind history.
+    fn save_history(&mut self) {
+        if let Some(rewind) = &mut self.rewind {
+            rewind.push(self.machine.save_state(Duration::from_secs(0)));
+        }
+    }
+
+    /// Restores the frame before the current one from the rewind history.
+    fn step_back(&mut self) {
+        let state = match self.rewind.as_mut().and_then(|rewind| rewind.pop()) {
+            Some(state) => state,
+            None => return
+        };
+        if let Err(e) = self.machine.load_state(state) {
+            eprintln!("chip8: can't rewind: {}", e);
+            return;
+        }
+        self.debugger.restart_frame();
+        if let Some((recorder, _)) = &mut self.recorder {
+            recorder.rewind();
+        }
     }
 
-}
\ No newline at end of file
+    /// Writes the machine state to the save state file.
+    fn save_state(&mut self) {
+        let state = self.machine.save_state(self.clock.phase());
+        match fs::write(&self.state, state) {
+            Ok(()) => println!("saved state to {}", self.state.display()),
+            Err(e) => eprintln!("chip8: can't write save state '{}': {}", self.state.display(), e)
+        }
+    }
+
+    /// Restores the machine state from the save state file.
+    fn load_state(&mut self) {
+        let result = fs::read(&self.state)
+            .map_err(|e| e.to_string())
+            .and_then(|state| self.machine.load_state(&state));
+        match result {
+            Ok(phase) => {
+                self.clock.set_phase(phase);
+                self.debugger.restart_frame();
+            },
+            Err(e) => eprintln!("chip8: can't load save state '{}': {}", self.state.display(), e)
+        }
+    }
+
+    /// Runs under <debugger>, such as one with an Octo program's
+    /// breakpoints set, and shows <monitors> whenever it stops.
+    pub fn set_debugger(&mut self, debugger: Debugger, monitors: Vec<Monitor>) {
+        self.debugger = debugger;
+        self.monitors = monitors;
+    }
+
+    /// Shows or hides the debugger panel, which F3 toggles.
+    pub fn set_overlay(&mut self, overlay: bool) {
+        self.overlay = overlay;
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.autorun = !paused;
+    }
+
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
+    pub fn set_state_path(&mut self, path: PathBuf) {
+        self.state = path;
+    }
+
+    /// Records a movie of the session from now on, written to <path> when
+    /// the window is closed.
+    pub fn record(&mut self, path: PathBuf, seed: u64) {
+        self.recorder = Some((Recorder::start(&mut self.machine, seed), path));
+    }
+
+    /// Replays <player>'s movie, handing control back when it ends.
+    pub fn replay(&mut self, player: Player) {
+        self.player = Some(player);
+    }
+
+    /// Keeps up to <budget> bytes of rewind history, or none if zero.
+    pub fn set_rewind_budget(&mut self, budget: usize) {
+        self.rewind = if budget > 0 { Some(Rewind::new(budget)) } else { None };
+    }
+
+    pub fn set_beeper(&mut self, beeper: Box<dyn Beeper>) {
+        self.beeper = beeper;
+    }
+
+    /// Plays the XO-CHIP audio pattern rather than the beeper's tone.
+    pub fn set_patterns(&mut self, patterns: bool) {
+        self.patterns = patterns;
+    }
+
+}
+
+/// Prints <text>, if any, followed by the console prompt.
+fn prompt(text: &str) {
+    if !text.is_empty() {
+        println!("{}", text);
+    }
+    print!("{}", console::PROMPT);
+    let _ = io::stdout().flush();
+}
+
+/// Draws <framebuffer> scaled to fill <width> pixels across the left of
+/// <frame>.
+fn render(framebuffer: &Framebuffer, frame: &mut Frame, width: f32) {
+    frame.clear(Color::from_rgb_u32(framebuffer.palette[0]));
+    let scale = width / framebuffer.width as f32;
+    let mut mesh = Mesh::new();
+    for y in 0..framebuffer.height {
+        for x in 0..framebuffer.width {
+            if framebuffer.pixel(x, y) == 0 {
+                continue;
+            }
+            let rectangle = Rectangle {
+                x: x as f32 * scale,
+                y: y as f32 * scale,
+                width: scale,
+                height: scale
+            };
+            mesh.fill(Shape::Rectangle(rectangle), Color::from_rgb_u32(framebuffer.color(x, y)));
+        }
+    }
+    mesh.draw(&mut frame.as_target());
+}
@@ -0,0 +1,386 @@
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Write};
+
+use crate::debugger::{self, Access, Condition, Debugger, Register, Stop, Trigger};
+use crate::image;
+use crate::machine::Machine;
+use crate::octo::Monitor;
+
+pub const PROMPT: &str = "(chip8) ";
+
+pub const HELP: &str = "\
+break <addr> [if <condition>]  stop before the instruction at <addr>, when
+                               <condition> such as 'v3 == 0x10' holds
+watch <register>               stop when <register> changes
+watch <addr> [<len>]           stop after a write to memory (rwatch for
+                               reads, awatch for both)
+delete <id>                    remove a breakpoint or watch
+info                           list breakpoints and watches
+step [<n>]                     run <n> instructions (default 1)
+next                           step, running a call through to its return
+finish                         run until the current subroutine returns
+until <addr>                   run until execution reaches <addr>
+continue                       run until something stops execution
+regs                           print the registers
+bt                             print the call stack
+x/<n> <addr>                   print <n> bytes of memory (default 16)
+set <register> <value>         change a register
+set [<addr>] <value>           change a byte of memory
+disasm [<addr>] [<n>]          disassemble <n> instructions (default 10)
+screen                         print the screen
+monitors                       print the memory an Octo program's :monitor
+                               directives name
+quit                           leave the debugger
+Addresses and values are decimal or 0x-prefixed hex. An empty line repeats
+the last command.";
+
+/// What the frontend should do after a command.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    /// Print the text, if any, and prompt for another command.
+    Print(String),
+    /// Run the debugger until it stops, then report why.
+    Run,
+    Quit
+}
+
+/// A gdb-style command interpreter driving a `Debugger`. Frontends feed it
+/// lines and act on what it returns, so it works the same with or without
+/// a window.
+#[derive(Default)]
+pub struct Console {
+    last: String,
+    monitors: Vec<Monitor>
+}
+
+impl Console {
+
+    pub fn new() -> Self {
+        Console { last: String::new(), monitors: Vec::new() }
+    }
+
+    /// Sets the memory the `monitors` command shows, from an Octo
+    /// program's `:monitor` directives.
+    pub fn set_monitors(&mut self, monitors: Vec<Monitor>) {
+        self.monitors = monitors;
+    }
+
+    pub fn execute(&mut self, debugger: &mut Debugger, machine: &mut Machine, line: &str) -> Result<Action, String> {
+        let line = match line.trim() {
+            "" => self.last.clone(),
+            line => String::from(line)
+        };
+        self.last = line.clone();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Ok(Action::Print(String::new()))
+        };
+        match command {
+            "b" | "break" => {
+                let addr = address(args.first().copied())?;
+                let condition = match args.get(1) {
+                    Some(&"if") => Some(Condition::parse(&args[2..].join(" "))?),
+                    Some(word) => return Err(format!("expected 'if', got '{}'", word)),
+                    None => None
+                };
+                let trigger = Trigger::Breakpoint { addr, condition };
+                let text = format!("{}: {}", debugger.add(trigger.clone()), trigger);
+                Ok(Action::Print(text))
+            },
+            "watch" | "rwatch" | "awatch" => {
+                let access = match command {
+                    "watch" => Access::Write,
+                    "rwatch" => Access::Read,
+                    _ => Access::Any
+                };
+                let target = args.first().ok_or("expected a register or address")?;
+                let trigger = match Register::parse(target) {
+                    Some(register) if access == Access::Write => Trigger::Watch(register),
+                    Some(_) => return Err(format!("{} only watches memory", command)),
+                    None => {
+                        let addr = address(Some(target))?;
+                        let len = match args.get(1) {
+                            Some(len) => value(len).filter(|&len| len > 0)
+                                .ok_or(format!("invalid length '{}'", len))?,
+                            None => 1
+                        };
+                        Trigger::Watchpoint { addr, len, access }
+                    }
+                };
+                let text = format!("{}: {}", debugger.add(trigger.clone()), trigger);
+                Ok(Action::Print(text))
+            },
+            "d" | "delete" => {
+                let id = args.first().and_then(|id| id.parse().ok()).ok_or("expected an id")?;
+                match debugger.remove(id) {
+                    true => Ok(Action::Print(String::new())),
+                    false => Err(format!("no breakpoint or watch {}", id))
+                }
+            },
+            "info" => {
+                let lines: Vec<String> = debugger.triggers().iter()
+                    .map(|(id, trigger)| format!("{}: {}", id, trigger))
+                    .collect();
+                match lines.is_empty() {
+                    true => Ok(Action::Print(String::from("no breakpoints or watches"))),
+                    false => Ok(Action::Print(lines.join("\n")))
+                }
+            },
+            "s" | "step" => {
+                let count = match args.first() {
+                    Some(count) => count.parse().map_err(|_| format!("invalid count '{}'", count))?,
+                    None => 1
+                };
+                for _ in 0..count {
+                    debugger.step();
+                    match debugger.cycle(machine) {
+                        Some(Stop::Step) | None => (),
+                        Some(stop) => return Ok(Action::Print(stopped(&stop, machine)))
+                    }
+                }
+                Ok(Action::Print(location(machine)))
+            },
+            "n" | "next" => {
+                debugger.step_over(machine);
+                Ok(Action::Run)
+            },
+            "finish" => {
+                if machine.cpu.sp() == 0 {
+                    return Err(String::from("not in a subroutine"));
+                }
+                debugger.step_out(machine);
+                Ok(Action::Run)
+            },
+            "u" | "until" => {
+                debugger.run_to(address(args.first().copied())?);
+                Ok(Action::Run)
+            },
+            "c" | "continue" => {
+                debugger.resume();
+                Ok(Action::Run)
+            },
+            "regs" => Ok(Action::Print(registers(machine))),
+            "bt" => {
+                let mut lines = vec![format!("#0 {:#06x}", machine.cpu.pc())];
+                for (n, addr) in machine.cpu.stack().iter().rev().enumerate() {
+                    lines.push(format!("#{} {:#06x}", n + 1, addr));
+                }
+                Ok(Action::Print(lines.join("\n")))
+            },
+            "set" => {
+                let (target, value) = match args {
+                    [target, value] => (*target, self::value(value).ok_or(format!("invalid value '{}'", value))?),
+                    _ => return Err(String::from("expected set <register> <value> or set [<addr>] <value>"))
+                };
+                match target.strip_prefix('[').and_then(|target| target.strip_suffix(']')) {
+                    Some(addr) => {
+                        let addr = address(Some(addr))?;
+                        let byte = u8::try_from(value).map_err(|_| format!("{:#x} doesn't fit in a byte", value))?;
+                        machine.cpu.memory_mut()[addr as usize] = byte;
+                    },
+                    None => {
+                        let register = Register::parse(target).ok_or(format!("unknown register '{}'", target))?;
+                        register.write(machine, value)?;
+                    }
+                }
+                Ok(Action::Print(String::new()))
+            },
+            "disasm" => {
+                let addr = match args.first() {
+                    Some(addr) => address(Some(addr))?,
+                    None => machine.cpu.pc()
+                };
+                let count = match args.get(1) {
+                    Some(count) => count.parse().map_err(|_| format!("invalid count '{}'", count))?,
+                    None => 10
+                };
+                Ok(Action::Print(disassemble(machine, addr, count)))
+            },
+            "screen" => Ok(Action::Print(image::ascii(&machine.framebuffer()).trim_end().to_string())),
+            "monitors" => match self.monitors.is_empty() {
+                true => Ok(Action::Print(String::from("no monitors"))),
+                false => {
+                    let lines: Vec<String> = self.monitors.iter().map(|monitor| monitor.show(machine)).collect();
+                    Ok(Action::Print(lines.join("\n")))
+                }
+            },
+            "h" | "help" => Ok(Action::Print(String::from(HELP))),
+            "q" | "quit" => Ok(Action::Quit),
+            _ if command.starts_with("x") => {
+                let count = match command.strip_prefix("x/") {
+                    Some(count) => count.parse().map_err(|_| format!("invalid count '{}'", count))?,
+                    None if command == "x" => 16,
+                    None => return Err(format!("unknown command '{}', try help", command))
+                };
+                examine(machine, address(args.first().copied())?, count)
+            },
+            _ => Err(format!("unknown command '{}', try help", command))
+        }
+    }
+
+}
+
+fn address(word: Option<&str>) -> Result<u16, String> {
+    let word = word.ok_or("expected an address")?;
+    value(word).ok_or(format!("invalid address '{}'", word))
+}
+
+fn value(word: &str) -> Option<u16> {
+    debugger::number(word)
+}
+
+/// The instruction at <pc>, as `=> 0x0204: 7001      add v0, 0x01`.
+pub fn location(machine: &Machine) -> String {
+    disassemble(machine, machine.cpu.pc(), 1)
+}
+
+/// Why the debugger stopped, and where.
+pub fn stopped(stop: &Stop, machine: &Machine) -> String {
+    format!("stopped: {}\n{}", stop, location(machine))
+}
+
+fn registers(machine: &Machine) -> String {
+    let cpu = &machine.cpu;
+    let mut lines: Vec<String> = cpu.v().chunks(8).enumerate().map(|(row, values)| {
+        let values: Vec<String> = values.iter().enumerate()
+            .map(|(n, value)| format!("v{:x} {:02x}", row * 8 + n, value))
+            .collect();
+        values.join("  ")
+    }).collect();
+    lines.push(format!("i {:#06x}  pc {:#06x}  sp {}  dt {:02x}  st {:02x}",
+        cpu.i(), cpu.pc(), cpu.sp(), cpu.dt(), cpu.st()));
+    lines.join("\n")
+}
+
+fn examine(machine: &Machine, addr: u16, count: usize) -> Result<Action, String> {
+    let memory = machine.cpu.memory();
+    let start = addr as usize;
+    let end = start.checked_add(count).filter(|&end| end <= memory.len())
+        .ok_or(format!("{:#06x} bytes from {:#06x} run past the end of memory", count, addr))?;
+    let lines: Vec<String> = memory[start..end].chunks(8).enumerate().map(|(row, bytes)| {
+        let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        format!("{:#06x}: {}", start + row * 8, bytes.join(" "))
+    }).collect();
+    Ok(Action::Print(lines.join("\n")))
+}
+
+fn disassemble(machine: &Machine, mut addr: u16, count: usize) -> String {
+    let cpu = &machine.cpu;
+    let mut lines = Vec::new();
+    for _ in 0..count {
+        let marker = if addr == cpu.pc() { "=>" } else { "  " };
+        match cpu.instruction_at(addr) {
+            Ok(instruction) => {
+                let opcode: String = instruction.to_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+                lines.push(format!("{} {:#06x}: {:<8}  {}", marker, addr, opcode, instruction));
+                addr = addr.wrapping_add(instruction.size());
+            },
+            Err(fault) => {
+                lines.push(format!("{} {:#06x}: {}", marker, addr, fault));
+                break;
+            }
+        }
+    }
+    lines.join("\n")
+}
+
+/// Reads commands from <input> until `quit` or the end of input, without a
+/// window. Commands that run the machine give up after <frames> frames, so
+/// a program that never stops doesn't hang the console.
+pub fn run(console: &mut Console, debugger: &mut Debugger, machine: &mut Machine, input: &mut dyn BufRead, output: &mut dyn Write, frames: u32) -> io::Result<()> {
+    writeln!(output, "{}", location(machine))?;
+    loop {
+        write!(output, "{}", PROMPT)?;
+        output.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(output)?;
+            return Ok(());
+        }
+        match console.execute(debugger, machine, &line) {
+            Ok(Action::Print(text)) if text.is_empty() => (),
+            Ok(Action::Print(text)) => writeln!(output, "{}", text)?,
+            Ok(Action::Run) => match (0..frames).find_map(|_| debugger.frame(machine)) {
+                Some(stop) => writeln!(output, "{}", stopped(&stop, machine))?,
+                None => writeln!(output, "still running after {} frames\n{}", frames, location(machine))?
+            },
+            Ok(Action::Quit) => return Ok(()),
+            Err(e) => writeln!(output, "error: {}", e)?
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0200: ld v0, 0     0202: call 020a    0204: add v0, 1
+    // 0206: ld [i], v0   0208: jp 0202      020a: ld i, 0x300
+    // 020c: ret
+    const ROM: [u8; 14] = [
+        0x60, 0x00, 0x22, 0x0a, 0x70, 0x01, 0xf0, 0x55, 0x12, 0x02, 0xa3, 0x00, 0x00, 0xee
+    ];
+
+    fn session(commands: &str) -> String {
+        let mut machine = Machine::new();
+        machine.load(&ROM);
+        let mut output = Vec::new();
+        run(&mut Console::new(), &mut Debugger::new(), &mut machine, &mut commands.as_bytes(), &mut output, 10).unwrap();
+        String::from_utf8(output).unwrap().replace(PROMPT, "")
+    }
+
+    #[test]
+    fn stepping() {
+        let output = session("step 2\nbt\nfinish\nnext\n\nquit\n");
+        assert_eq!(output, "\
+=> 0x0200: 6000      ld v0, 0x00
+=> 0x020a: a300      ld i, 0x300
+#0 0x020a
+#1 0x0204
+stopped: returned to 0x0204
+=> 0x0204: 7001      add v0, 0x01
+stopped: stepped
+=> 0x0206: f055      ld [i], v0
+stopped: stepped
+=> 0x0208: 1202      jp 0x202
+");
+    }
+
+    #[test]
+    fn breakpoints() {
+        let output = session("break 0x204 if v0 == 2\nwatch 0x300\ninfo\ncontinue\ndelete 2\nc\nregs\n");
+        assert_eq!(output, "\
+=> 0x0200: 6000      ld v0, 0x00
+1: breakpoint at 0x0204 if v0 == 0x2
+2: write watchpoint at 0x0300
+1: breakpoint at 0x0204 if v0 == 0x2
+2: write watchpoint at 0x0300
+stopped: watchpoint 2: write at 0x0300
+=> 0x0208: 1202      jp 0x202
+stopped: breakpoint 1 at 0x0204
+=> 0x0204: 7001      add v0, 0x01
+v0 02  v1 00  v2 00  v3 00  v4 00  v5 00  v6 00  v7 00
+v8 00  v9 00  va 00  vb 00  vc 00  vd 00  ve 00  vf 00
+i 0x0300  pc 0x0204  sp 0  dt 00  st 00
+
+");
+    }
+
+    #[test]
+    fn memory() {
+        let output = session("set [0x300] 0xab\nset v3 300\nset i 0x300\nx/4 0x300\ndisasm 0x20a 2\nuntil 0x20e\nfoo\n");
+        assert_eq!(output, "\
+=> 0x0200: 6000      ld v0, 0x00
+error: 0x12c doesn't fit in v3
+0x0300: ab 00 00 00
+   0x020a: a300      ld i, 0x300
+   0x020c: 00ee      ret
+still running after 10 frames
+=> 0x0204: 7001      add v0, 0x01
+error: unknown command 'foo', try help
+
+");
+    }
+
+}
@@ -1,7 +1,12 @@
 use std::io::Write;
-use rand::Rng;
-use crate::timer::Timer;
 use crate::gpu::Gpu;
+use crate::keypad::Keypad;
+use crate::quirks::Quirks;
+use crate::fault::CpuFault;
+use crate::instruction::Instruction;
+use crate::instruction::Instruction::*;
+use crate::state::{Reader, Writer};
+use crate::rng::{Random, SplitMix};
 
 static BOOTROM: &'static [u8] = &[
     0xf0, 0x90, 0x90, 0x90, 0xf0,
@@ -19,122 +24,290 @@ static BOOTROM: &'static [u8] = &[
     0xf0, 0x80, 0x80, 0x80, 0xf0,
     0xe0, 0x90, 0x90, 0x90, 0xe0,
     0xf0, 0x80, 0xf0, 0x80, 0xf0,
-    0xf0, 0x80, 0xf0, 0x80, 0x80
+    0xf0, 0x80, 0xf0, 0x80, 0x80,
+    // SUPER-CHIP 8x10 font
+    0xff, 0xff, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xff, 0xff,
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xff, 0xff,
+    0xff, 0xff, 0x03, 0x03, 0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff,
+    0xff, 0xff, 0x03, 0x03, 0xff, 0xff, 0x03, 0x03, 0xff, 0xff,
+    0xc3, 0xc3, 0xc3, 0xc3, 0xff, 0xff, 0x03, 0x03, 0x03, 0x03,
+    0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0x03, 0x03, 0xff, 0xff,
+    0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff,
+    0xff, 0xff, 0x03, 0x03, 0x06, 0x0c, 0x18, 0x18, 0x18, 0x18,
+    0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff,
+    0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff, 0x03, 0x03, 0xff, 0xff,
+    0x7e, 0xff, 0xc3, 0xc3, 0xc3, 0xff, 0xff, 0xc3, 0xc3, 0xc3,
+    0xfc, 0xfc, 0xc3, 0xc3, 0xfc, 0xfc, 0xc3, 0xc3, 0xfc, 0xfc,
+    0x3c, 0xff, 0xc3, 0xc0, 0xc0, 0xc0, 0xc0, 0xc3, 0xff, 0x3c,
+    0xfc, 0xfe, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xfe, 0xfc,
+    0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff,
+    0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0xc0, 0xc0, 0xc0, 0xc0
 ];
 
 const CARRY: usize = 0x0f;
+const BIGFONT: u16 = 0x50;
+const MEMORY_SIZE: usize = 0x10000;
 
 pub struct CpuContext<'a> {
-    pub opcode: u16,
     pub gpu: &'a mut Gpu,
-    pub sound_timer: &'a mut Timer,
-    pub delay_timer: &'a mut Timer
-}
-
-impl<'a> CpuContext<'a> {
-    pub fn vx(&self) -> usize {
-        ((self.opcode & 0x0f00) >> 8) as usize
-    }
-    pub fn vy(&self) -> usize {
-        ((self.opcode & 0x00f0) >> 4) as usize
-    }
-    pub fn nnn(&self) -> u16 {
-        self.opcode & 0x0fff
-    }
-    pub fn nn(&self) -> u8 {
-        (self.opcode & 0x00ff) as u8
-    }
-    pub fn n(&self) -> u8 {
-        (self.opcode & 0x000f) as u8
-    }
-    pub fn msb(&self, v: u8) -> u8 {
-        (v & 0x80) >> 7
-    }
-}
-
-#[cfg(test)]
-impl<'a> CpuContext<'a> {
-    pub fn op(&mut self, opcode: u16) -> &mut Self {
-        self.opcode = opcode;
-        self
-    }
+    pub keypad: &'a mut Keypad
 }
 
 pub struct Cpu {
     halted: bool,
-    memory: [u8; 4096],
+    memory: [u8; MEMORY_SIZE],
     stack: [u16; 16],
     v: [u8; 16],
     i: u16,
     pc: u16,
+    /// Set when <pc> ran past the end of memory and wrapped to 0, so the
+    /// next fetch faults instead of running from the start.
+    overrun: bool,
     sp: u8,
     dt: u8,
-    st: u8
+    st: u8,
+    key: Option<usize>,
+    rpl: [u8; 16],
+    pattern: [u8; 16],
+    pitch: u8,
+    quirks: Quirks,
+    fault: Option<CpuFault>,
+    rng: Box<dyn Random>
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Cpu::new()
+    }
 }
 
 impl Cpu {
-    
+
     pub fn new() -> Self {
         Cpu {
             halted: false,
-            memory: [0; 4096],
+            memory: [0; MEMORY_SIZE],
             stack: [0; 16],
             v: [0; 16],
             i: 0,
             pc: 0,
+            overrun: false,
             sp: 0,
             dt: 0,
-            st: 0
+            st: 0,
+            key: None,
+            rpl: [0; 16],
+            pattern: [0; 16],
+            pitch: 64,
+            quirks: Quirks::default(),
+            fault: None,
+            rng: Box::new(SplitMix::from_entropy())
         }
     }
 
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Replaces the random number generator behind `rnd`.
+    pub fn set_rng(&mut self, rng: Box<dyn Random>) {
+        self.rng = rng;
+    }
+
+    pub fn rng(&self) -> &dyn Random {
+        self.rng.as_ref()
+    }
+
+    /// Restarts the random number generator from <seed>, making `rnd`
+    /// repeatable.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng.seed(seed);
+    }
+
     fn addr(&self) -> usize {
-        (self.i as usize) & 0x0fff
+        self.i as usize
     }
 
+    /// Loads the font into the bootrom area and <code> from 0x200, cutting
+    /// it off at the end of memory.
     pub fn load(&mut self, code: &[u8]) {
-        let mut boot = &mut self.memory[0..0x1ff];
-        match boot.write(&BOOTROM) {
-            Ok(n) => { log!("loaded {} bytes into bootrom", n) },
-            _ => ()
-        };
-        let mut mem = &mut self.memory[0x200..];
-        match mem.write(&code) {
-            Ok(n) => { log!("loaded {} bytes", n) },
-            _ => ()
-        }
+        self.memory[..BOOTROM.len()].copy_from_slice(BOOTROM);
+        let len = code.len().min(MEMORY_SIZE - 0x200);
+        self.memory[0x200..0x200 + len].copy_from_slice(&code[..len]);
     }
 
     pub fn reset(&mut self) {
-        self.memory = [0; 4096];
+        self.halted = false;
+        self.memory = [0; MEMORY_SIZE];
         self.v = [0; 16];
         self.i = 0;
         self.pc = 0x200;
+        self.overrun = false;
         self.sp = 0;
         self.dt = 0;
         self.st = 0;
+        self.key = None;
+        self.pattern = [0; 16];
+        self.pitch = 64;
+        self.fault = None;
     }
 
-    pub fn cycle(&mut self, ctx: &mut CpuContext) {
+    pub fn cycle(&mut self, ctx: &mut CpuContext) -> Result<(), CpuFault> {
         if self.halted {
-            return
+            return Ok(())
+        }
+        let instruction = self.fetch()?;
+        self.step(instruction.size());
+        self.execute(ctx, instruction);
+        match self.fault.take() {
+            Some(fault) => Err(fault),
+            None => Ok(())
+        }
+    }
+
+    pub fn save(&self, state: &mut Writer) {
+        state.bool(self.halted);
+        state.bytes(&self.memory);
+        for &addr in self.stack.iter() {
+            state.u16(addr);
+        }
+        state.bytes(&self.v);
+        state.u16(self.i);
+        state.u16(self.pc);
+        state.bool(self.overrun);
+        state.u8(self.sp);
+        state.u8(self.dt);
+        state.u8(self.st);
+        state.u8(self.key.map_or(0xff, |key| key as u8));
+        state.bytes(&self.rpl);
+        state.bytes(&self.pattern);
+        state.u8(self.pitch);
+        state.u64(self.rng.state());
+    }
+
+    pub fn restore(&mut self, state: &mut Reader) -> Result<(), String> {
+        self.halted = state.bool()?;
+        self.memory.copy_from_slice(state.bytes(MEMORY_SIZE)?);
+        for addr in self.stack.iter_mut() {
+            *addr = state.u16()?;
         }
-        if self.st > 0 && ctx.sound_timer.active() {
-            self.st = self.st.saturating_sub(1);
+        self.v.copy_from_slice(state.bytes(16)?);
+        self.i = state.u16()?;
+        self.pc = state.u16()?;
+        self.overrun = state.bool()?;
+        self.sp = state.u8()?;
+        self.dt = state.u8()?;
+        self.st = state.u8()?;
+        self.key = match state.u8()? {
+            0xff => None,
+            key if key < 16 => Some(key as usize),
+            key => return Err(format!("invalid key {:#04x} in save state", key))
+        };
+        self.rpl.copy_from_slice(state.bytes(16)?);
+        self.pattern.copy_from_slice(state.bytes(16)?);
+        self.pitch = state.u8()?;
+        self.rng.set_state(state.u64()?);
+        self.fault = None;
+        if self.sp as usize > self.stack.len() {
+            return Err(format!("invalid stack pointer {:#04x} in save state", self.sp));
         }
-        if self.dt > 0 && ctx.delay_timer.active() {
-            self.dt = self.dt.saturating_sub(1);
+        Ok(())
+    }
+
+    /// Counts the delay and sound timers down by one. Called once per frame.
+    pub fn tick(&mut self) {
+        self.dt = self.dt.saturating_sub(1);
+        self.st = self.st.saturating_sub(1);
+        self.rng.tick();
+    }
+
+    /// Records a fault to be returned from the current cycle.
+    fn fault(&mut self, fault: CpuFault) {
+        self.fault.get_or_insert(fault);
+    }
+
+    /// The memory the platform can address. Sprites that run off its end
+    /// wrap around to the start.
+    fn address_space(&self) -> &[u8] {
+        &self.memory[..self.quirks.memory]
+    }
+
+    /// Returns true if <len> bytes starting at <addr> lie within the
+    /// platform's address space, otherwise faults.
+    fn check(&mut self, addr: usize, len: usize) -> bool {
+        let size = self.quirks.memory;
+        if addr + len <= size {
+            true
+        } else {
+            self.fault(CpuFault::MemoryOutOfBounds(size.max(addr)));
+            false
         }
-        let opcode = self.fetch();
-        let op = self.decode(opcode);
-        ctx.opcode = opcode;
-        self.step(2);
-        op(self, ctx);
     }
 
     pub fn halt(&mut self) {
         self.halted = true;
-        log!("[cpu] halt");
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn v(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Memory for debuggers to patch.
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    pub fn set_v(&mut self, x: usize, value: u8) {
+        self.v[x] = value;
+    }
+
+    pub fn set_i(&mut self, i: u16) {
+        self.i = i;
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.jump(pc);
+    }
+
+    pub fn set_dt(&mut self, dt: u8) {
+        self.dt = dt;
+    }
+
+    pub fn set_st(&mut self, st: u8) {
+        self.st = st;
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    /// The return addresses on the call stack, innermost last.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp as usize]
+    }
+
+    pub fn dt(&self) -> u8 {
+        self.dt
+    }
+
+    pub fn st(&self) -> u8 {
+        self.st
     }
 
     pub fn dump(&self) {
@@ -147,388 +320,552 @@ impl Cpu {
         println!("sp = #{:02x}", self.sp);
     }
 
+    /// Returns true while the sound timer is active.
+    pub fn sound(&self) -> bool {
+        self.st > 0
+    }
+
+    /// The XO-CHIP audio pattern buffer, played back as 128 1-bit samples.
+    pub fn pattern(&self) -> &[u8; 16] {
+        &self.pattern
+    }
+
+    /// The XO-CHIP playback rate, as used by `4000 * 2 ^ ((pitch - 64) / 48)`.
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// Advances <pc> by <n> bytes, noting when it runs off the end of
+    /// memory.
     fn step(&mut self, n: u16) {
-        self.pc += n;
-        if self.pc as usize >= self.memory[0..].len() {
-            self.halt();
+        let (pc, overflow) = self.pc.overflowing_add(n);
+        self.pc = pc;
+        self.overrun |= overflow;
+    }
+
+    /// Moves <pc> to <addr>.
+    fn jump(&mut self, addr: u16) {
+        self.pc = addr;
+        self.overrun = false;
+    }
+
+    /// Skips the next instruction, including both words of a long `ld i`.
+    fn skip(&mut self) {
+        let size = self.fetch().map_or(2, |instruction| instruction.size());
+        self.step(size);
+    }
+
+    /// Decodes the instruction at <pc>, reading the address word that
+    /// follows a long `ld i`.
+    fn fetch(&self) -> Result<Instruction, CpuFault> {
+        if self.overrun {
+            return Err(CpuFault::MemoryOutOfBounds(MEMORY_SIZE + self.pc as usize));
         }
+        self.instruction_at(self.pc)
     }
 
-    fn fetch(&self) -> u16 {
-        let addr = self.pc as usize;
-        let x = self.memory[addr + 0] as usize;
-        let y = self.memory[addr + 1] as usize;
-        let opcode = (x << 8) | y;
-        opcode as u16
+    /// Decodes the instruction at <addr>, including the second word of
+    /// `ld i, long`.
+    pub fn instruction_at(&self, addr: u16) -> Result<Instruction, CpuFault> {
+        let opcode = self.read(addr as usize)?;
+        match Instruction::decode(opcode) {
+            LdILong(_) => Ok(LdILong(self.read(addr as usize + 2)?)),
+            instruction => Ok(instruction)
+        }
     }
 
-    fn decode(&self, opcode: u16) -> fn(&mut Cpu, &mut CpuContext) {
-        match opcode & 0xf000 {
-            0x0000 => match opcode {
-                0x00e0 => Cpu::cls,
-                0x00ee => Cpu::ret,
-                0x00fd => Cpu::exit,
-                _ => Cpu::sys
-            },
-            0x1000 => Cpu::jp,
-            0x2000 => Cpu::call,
-            0x3000 => Cpu::se_vx_kk,
-            0x4000 => Cpu::sne_vx_kk,
-            0x5000 => Cpu::se_vx_vy,
-            0x6000 => Cpu::ld_vx_kk,
-            0x7000 => Cpu::add_vx_kk,
-            0x8000 => match opcode & 0x000f {
-                0x0000 => Cpu::ld_vx_vy,
-                0x0001 => Cpu::or,
-                0x0002 => Cpu::and,
-                0x0003 => Cpu::xor,
-                0x0004 => Cpu::add_vx_vy,
-                0x0005 => Cpu::sub_vx_vy,
-                0x0006 => Cpu::shr,
-                0x0007 => Cpu::subn,
-                0x0008 => Cpu::shl,
-                _ => Cpu::nop
-            },
-            0x9000 => Cpu::sne_vx_vy,
-            0xa000 => Cpu::ld,
-            0xb000 => Cpu::jp_v0_addr,
-            0xc000 => Cpu::rnd,
-            0xd000 => Cpu::drw,
-            0xe000 => match opcode & 0x00ff {
-                0x009e => Cpu::skp,
-                0x00a1 => Cpu::sknp,
-                _ => Cpu::nop
-            },
-            0xf000 => match opcode & 0x00ff {
-                0x0007 => Cpu::ld_vx_dt,
-                0x000a => unimplemented!(),
-                0x0015 => Cpu::ld_dt_vx,
-                0x0018 => Cpu::ld_st_vx,
-                0x001e => Cpu::add_i_vx,
-                0x0029 => Cpu::ld_i_spr,
-                0x0033 => Cpu::ld_b_vx,
-                0x0055 => Cpu::ld_i_vx,
-                0x0065 => Cpu::ld_vx_i,
-                _ => Cpu::nop
-            },
-            _ => Cpu::nop
+    fn read(&self, addr: usize) -> Result<u16, CpuFault> {
+        if addr + 1 >= self.quirks.memory {
+            return Err(CpuFault::MemoryOutOfBounds(addr + 1));
+        }
+        let x = self.memory[addr] as usize;
+        let y = self.memory[addr + 1] as usize;
+        let opcode = (x << 8) | y;
+        Ok(opcode as u16)
+    }
+
+    /// Executes a decoded instruction. <pc> must already point past it.
+    pub fn execute(&mut self, ctx: &mut CpuContext, instruction: Instruction) {
+        match instruction {
+            Sys(_) => self.sys(),
+            Scd(n) => self.scd(ctx, n),
+            Cls => self.cls(ctx),
+            Ret => self.ret(),
+            Scr => self.scr(ctx),
+            Scl => self.scl(ctx),
+            Exit => self.exit(),
+            Low => self.low(ctx),
+            High => self.high(ctx),
+            Jp(nnn) => self.jp(nnn),
+            Call(nnn) => self.call(nnn),
+            SeVxKk { x, kk } => self.se_vx_kk(x as usize, kk),
+            SneVxKk { x, kk } => self.sne_vx_kk(x as usize, kk),
+            SeVxVy { x, y } => self.se_vx_vy(x as usize, y as usize),
+            Save { x, y } => self.ld_i_vx_vy(x as usize, y as usize),
+            Load { x, y } => self.ld_vx_vy_i(x as usize, y as usize),
+            LdVxKk { x, kk } => self.ld_vx_kk(x as usize, kk),
+            AddVxKk { x, kk } => self.add_vx_kk(x as usize, kk),
+            LdVxVy { x, y } => self.ld_vx_vy(x as usize, y as usize),
+            Or { x, y } => self.or(x as usize, y as usize),
+            And { x, y } => self.and(x as usize, y as usize),
+            Xor { x, y } => self.xor(x as usize, y as usize),
+            AddVxVy { x, y } => self.add_vx_vy(x as usize, y as usize),
+            SubVxVy { x, y } => self.sub_vx_vy(x as usize, y as usize),
+            Shr { x, y } => self.shr(x as usize, y as usize),
+            Subn { x, y } => self.subn(x as usize, y as usize),
+            Shl { x, y } => self.shl(x as usize, y as usize),
+            SneVxVy { x, y } => self.sne_vx_vy(x as usize, y as usize),
+            LdI(nnn) => self.ld(nnn),
+            JpV0(nnn) => self.jp_v0_addr(nnn),
+            Rnd { x, kk } => self.rnd(x as usize, kk),
+            Drw { x, y, n: 0 } => self.drw_vx_vy(ctx, x as usize, y as usize),
+            Drw { x, y, n } => self.drw(ctx, x as usize, y as usize, n),
+            Skp(x) => self.skp(ctx, x as usize),
+            Sknp(x) => self.sknp(ctx, x as usize),
+            LdILong(nnnn) => self.ld_i_long(nnnn),
+            Plane(n) => self.plane(ctx, n),
+            Audio => self.audio(),
+            LdVxDt(x) => self.ld_vx_dt(x as usize),
+            LdVxK(x) => self.ld_vx_k(ctx, x as usize),
+            LdDtVx(x) => self.ld_dt_vx(x as usize),
+            LdStVx(x) => self.ld_st_vx(x as usize),
+            AddIVx(x) => self.add_i_vx(x as usize),
+            LdFVx(x) => self.ld_i_spr(x as usize),
+            LdHfVx(x) => self.ld_hf_vx(x as usize),
+            LdBVx(x) => self.ld_b_vx(x as usize),
+            Pitch(x) => self.pitch_vx(x as usize),
+            LdIVx(x) => self.ld_i_vx(x as usize),
+            LdVxI(x) => self.ld_vx_i(x as usize),
+            LdRVx(x) => self.ld_r_vx(x as usize),
+            LdVxR(x) => self.ld_vx_r(x as usize),
+            Invalid(opcode) => self.fault(CpuFault::InvalidOpcode(opcode))
         }
     }
 
     /// Loads the location of the sprite for the character in <vx> into <i>.
     /// Characters 0-f (hexadecimal) are represented by a 4x5 font baked
     /// into the boot ROM.
-    fn ld_i_spr(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
-        let vx = self.v[vx] as u16;
-        self.i = (vx * 5) & 0x0fff;
+    fn ld_i_spr(&mut self, vx: usize) {
+        let v = self.v[vx] as u16;
+        self.i = (v * 5) & 0x0fff;
     }
 
-    fn ret(&mut self, _ctx: &mut CpuContext) {
+    /// Loads the location of the 8x10 SUPER-CHIP sprite for the character
+    /// in <vx> into <i>.
+    fn ld_hf_vx(&mut self, vx: usize) {
+        let v = (self.v[vx] & 0x0f) as u16;
+        self.i = BIGFONT + v * 10;
+    }
+
+    fn ret(&mut self) {
+        if self.sp == 0 {
+            return self.fault(CpuFault::StackUnderflow);
+        }
         self.sp -= 1;
-        self.pc = self.stack[self.sp as usize];
-        log!("ret");
+        self.jump(self.stack[self.sp as usize]);
     }
 
-    fn exit(&mut self, _ctx: &mut CpuContext) {
+    fn exit(&mut self) {
         self.halt();
-        log!("exit");
     }
 
-    fn nop(&mut self, _ctx: &mut CpuContext) {
-        log!("nop");
+    /// Scrolls the screen down by <n> pixels.
+    fn scd(&mut self, ctx: &mut CpuContext, n: u8) {
+        ctx.gpu.scroll_down(n as usize);
+    }
+
+    /// Scrolls the screen right by 4 pixels.
+    fn scr(&mut self, ctx: &mut CpuContext) {
+        ctx.gpu.scroll_right();
     }
 
-    fn sys(&mut self, _ctx: &mut CpuContext) {
-        log!("sys");
+    /// Scrolls the screen left by 4 pixels.
+    fn scl(&mut self, ctx: &mut CpuContext) {
+        ctx.gpu.scroll_left();
     }
 
+    /// Switches to the 64x32 low resolution mode.
+    fn low(&mut self, ctx: &mut CpuContext) {
+        ctx.gpu.low();
+    }
+
+    /// Switches to the 128x64 high resolution mode.
+    fn high(&mut self, ctx: &mut CpuContext) {
+        ctx.gpu.high();
+    }
+
+    /// Machine code routines can't run here, so `sys` does nothing.
+    fn sys(&mut self) {}
+
     /// Clear screen
     fn cls(&mut self, ctx: &mut CpuContext) {
         ctx.gpu.clear();
-        log!("cls");
     }
 
-    fn call(&mut self, ctx: &mut CpuContext) {
+    fn call(&mut self, addr: u16) {
+        if self.sp as usize >= self.stack.len() {
+            return self.fault(CpuFault::StackOverflow);
+        }
         self.stack[self.sp as usize] = self.pc;
         self.sp += 1;
-        self.pc = ctx.nnn();
-        log!("call {:#03x}", self.pc);
+        self.jump(addr);
     }
 
     /// Unconditional jump to absolute address
-    fn jp(&mut self, ctx: &mut CpuContext) {
-        self.pc = ctx.nnn();
-        log!("jp {:#03x}", self.pc);
+    fn jp(&mut self, addr: u16) {
+        self.jump(addr);
     }
 
     /// Skips the next instruction if <vx> equals <nn>
-    fn se_vx_kk(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
-        let nn = ctx.nn();
+    fn se_vx_kk(&mut self, vx: usize, nn: u8) {
         if self.v[vx] == nn {
-            self.step(2);
+            self.skip();
         }
-        log!("se v{:x}, {:02x}", vx, nn);
     }
 
     /// Loads <nn> into <vx>
-    fn ld_vx_kk(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
-        let nn = ctx.nn();
+    fn ld_vx_kk(&mut self, vx: usize, nn: u8) {
         self.v[vx] = nn;
-        log!("ld v{:x}, {:#02x}", vx, nn);
     }
 
     /// Adds <nn> to <vx>
-    fn add_vx_kk(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
-        let nn = ctx.nn();
+    fn add_vx_kk(&mut self, vx: usize, nn: u8) {
         self.v[vx] = self.v[vx].overflowing_add(nn).0;
-        log!("add v{:x}, {:02x}", vx, nn);
     }
 
     /// Loads <vy> into <vx>
-    fn ld_vx_vy(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
-        let vy = ctx.vy();
+    fn ld_vx_vy(&mut self, vx: usize, vy: usize) {
         self.v[vx] = self.v[vy];
-        log!("ld v{:x}, v{:x}", vx, vy);
     }
 
     /// Loads result of (<vx> | <vy>) into <vx>
-    fn or(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
-        let vy = ctx.vy();
-        self.v[vx] = self.v[vx] | self.v[vy];
-        log!("or v{:x}, v{:x}", vx, vy);
+    fn or(&mut self, vx: usize, vy: usize) {
+        self.v[vx] |= self.v[vy];
+        self.reset_flag();
     }
 
     /// Loads result of (<vx> & <vy>) into <vx>
-    fn and(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
-        let vy = ctx.vy();
-        self.v[vx] = self.v[vx] & self.v[vy];
-        log!("and v{:x}, v{:x}", vx, vy);
+    fn and(&mut self, vx: usize, vy: usize) {
+        self.v[vx] &= self.v[vy];
+        self.reset_flag();
     }
 
     /// Loads result of (<vx> ^ <vy>) into <vx>
-    fn xor(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
-        let vy = ctx.vy();
-        self.v[vx] = self.v[vx] ^ self.v[vy];
-        log!("xor v{:x}, v{:x}", vx, vy);
+    fn xor(&mut self, vx: usize, vy: usize) {
+        self.v[vx] ^= self.v[vy];
+        self.reset_flag();
     }
 
     /// Adds <vy> to <vx> and loads result into <vx>.
     ///
     /// - <vf> is set to 1 if there is a carry
     /// - <vf> is set to 0 if there is no carry
-    fn add_vx_vy(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
-        let vy = ctx.vy();
+    fn add_vx_vy(&mut self, vx: usize, vy: usize) {
         let result = self.v[vx].overflowing_add(self.v[vy]);
-        self.v[CARRY] = result.1.into();
-        self.v[vx] = result.0;
-        log!("add v{:x}, v{:x}", vx, vy);
+        self.set_with_flag(vx, result.0, result.1.into());
     }
 
     /// Subtracts <vy> from <vx> and loads result into <vx>.
-    fn sub_vx_vy(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
-        let vy = ctx.vy();
+    fn sub_vx_vy(&mut self, vx: usize, vy: usize) {
         let result = self.v[vx].overflowing_sub(self.v[vy]);
-        self.v[CARRY] = if result.1 { 0 } else { 1 };
-        self.v[vx] = result.0;
-        log!("sub v{:x}, v{:x}", vx, vy);
+        self.set_with_flag(vx, result.0, if result.1 { 0 } else { 1 });
     }
 
-    /// Shifts <vx> right once.
-    /// <vf> will contain the lsb of <vx> before the shift.
-    fn shr(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
-        self.v[CARRY] = self.v[vx] & 0x1;
-        self.v[vx] = self.v[vx] >> 1;
-        log!("shr v{:x}", vx);
+    /// Shifts <vx> (or <vy>, depending on the quirks) right once and loads
+    /// the result into <vx>.
+    /// <vf> will contain the lsb of the source before the shift.
+    fn shr(&mut self, vx: usize, vy: usize) {
+        let v = self.v[self.shift_source(vx, vy)];
+        self.set_with_flag(vx, v >> 1, v & 0x1);
     }
 
     /// Loads the result of (<vy> - <vx>) into <vx>
     ///
     /// - <vf> is set to 0 if there is a borrow
     /// - <vf> is set to 1 if there is not a borrow
-    fn subn(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
-        let vy = ctx.vy();
+    fn subn(&mut self, vx: usize, vy: usize) {
         let result = self.v[vy].overflowing_sub(self.v[vx]);
-        self.v[CARRY] = if result.1 { 0 } else { 1 };
-        self.v[vx] = result.0;
-        log!("subn v{:x}, v{:x}", vx, vy);
+        self.set_with_flag(vx, result.0, if result.1 { 0 } else { 1 });
+    }
+
+    /// Shifts <vx> (or <vy>, depending on the quirks) left once and loads
+    /// the result into <vx>.
+    /// - <vf> is set to the msb of the source before the shift.
+    fn shl(&mut self, vx: usize, vy: usize) {
+        let v = self.v[self.shift_source(vx, vy)];
+        self.set_with_flag(vx, v << 1, (v & 0x80) >> 7);
+    }
+
+    fn shift_source(&self, vx: usize, vy: usize) -> usize {
+        if self.quirks.shift_vy { vy } else { vx }
     }
 
-    /// Shifts <vx> left once and loads the result into <vx>.
-    /// - <vf> is set to the msb of <vx> before the shift.
-    fn shl(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
-        self.v[CARRY] = ctx.msb(self.v[vx]);
-        self.v[vx] = self.v[vx] << 1;
-        log!("shl v{:x}", vx);
+    /// Loads <value> into <vx> and <flag> into <vf>. Platforms differ on
+    /// which is written last, which matters when X is F.
+    fn set_with_flag(&mut self, vx: usize, value: u8, flag: u8) {
+        if self.quirks.vf_last {
+            self.v[vx] = value;
+            self.v[CARRY] = flag;
+        } else {
+            self.v[CARRY] = flag;
+            self.v[vx] = value;
+        }
+    }
+
+    fn reset_flag(&mut self) {
+        if self.quirks.logic_vf {
+            self.v[CARRY] = 0;
+        }
     }
 
     /// Skips the next instruction if <vx> != <vy>.
-    fn sne_vx_vy(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
-        let vy = ctx.vy();
+    fn sne_vx_vy(&mut self, vx: usize, vy: usize) {
         if self.v[vx] != self.v[vy] {
-            self.step(2);
+            self.skip();
         }
-        log!("sne v{:x}, v{:x}", vx, vy);
     }
 
     /// Skips the next instruction if <vx> == <vy>
-    fn se_vx_vy(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
-        let vy = ctx.vy();
+    fn se_vx_vy(&mut self, vx: usize, vy: usize) {
         if self.v[vx] == self.v[vy] {
-            self.step(2);
+            self.skip();
         }
-        log!("se v{:x}, v{:x}", vx, vy);
     }
 
     /// Skips the next instruction if <vx> != <nn>
-    fn sne_vx_kk(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
-        let nn = ctx.nn();
+    fn sne_vx_kk(&mut self, vx: usize, nn: u8) {
         if self.v[vx] != nn {
-            self.step(2);
+            self.skip();
+        }
+    }
+
+    /// Loads the 16-bit address stored in the following word into <i>.
+    fn ld_i_long(&mut self, addr: u16) {
+        self.i = addr;
+    }
+
+    /// Selects the XO-CHIP bitplanes <n> used by drawing, scrolling and clearing.
+    fn plane(&mut self, ctx: &mut CpuContext, n: u8) {
+        ctx.gpu.set_plane(n);
+    }
+
+    /// Loads 16 bytes starting at memory location <i> into the audio pattern buffer.
+    fn audio(&mut self) {
+        let addr = self.addr();
+        if !self.check(addr, self.pattern.len()) {
+            return;
+        }
+        self.pattern.copy_from_slice(&self.memory[addr..addr + 16]);
+    }
+
+    /// Loads the value of <vx> into the audio playback pitch.
+    fn pitch_vx(&mut self, vx: usize) {
+        self.pitch = self.v[vx];
+    }
+
+    /// Stores registers <vx> to <vy> (inclusive, in either order) starting
+    /// at memory address <i>, leaving <i> unchanged.
+    fn ld_i_vx_vy(&mut self, vx: usize, vy: usize) {
+        let addr = self.addr();
+        let len = vx.abs_diff(vy) + 1;
+        if !self.check(addr, len) {
+            return;
+        }
+        for offset in 0..len {
+            let r = if vx <= vy { vx + offset } else { vx - offset };
+            self.memory[addr + offset] = self.v[r];
+        }
+    }
+
+    /// Loads registers <vx> to <vy> (inclusive, in either order) from memory
+    /// starting at address <i>, leaving <i> unchanged.
+    fn ld_vx_vy_i(&mut self, vx: usize, vy: usize) {
+        let addr = self.addr();
+        let len = vx.abs_diff(vy) + 1;
+        if !self.check(addr, len) {
+            return;
+        }
+        for offset in 0..len {
+            let r = if vx <= vy { vx + offset } else { vx - offset };
+            self.v[r] = self.memory[addr + offset];
         }
-        log!("sne v{:x}, {:02x}", vx, nn);
     }
 
     /// Loads address <nnn> into <i>.
-    fn ld(&mut self, ctx: &mut CpuContext) {
-        self.i = ctx.nnn();
-        log!("ld i, {:#03x}", self.i);
+    fn ld(&mut self, addr: u16) {
+        self.i = addr;
     }
 
-    /// Jumps to the address <nnn> + <v0>
-    fn jp_v0_addr(&mut self, ctx: &mut CpuContext) {
-        let addr = ctx.nnn();
-        let v0 = self.v[0] as u16;
-        self.pc = addr + v0;
-        log!("jp v0, {:03x}", addr);
+    /// Jumps to the address <nnn> + <v0>, or <nnn> + <vx> on platforms that
+    /// decode this as BXNN.
+    fn jp_v0_addr(&mut self, addr: u16) {
+        let r = if self.quirks.jump_vx { (addr >> 8) as usize } else { 0 };
+        let v = self.v[r] as u16;
+        self.jump(addr + v);
     }
 
-    /// Generates a uniformly random 8-bit integer, masks it with immediate <nn>,
-    /// and loads the result into <vx>.
-    fn rnd(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
-        let nn = ctx.nn();
-        let mut rng = rand::thread_rng();
-        let rnd: u8 = rng.gen();
-        self.v[vx] = rnd & nn;
-        log!("rnd v{:x}, {:02x}", vx, nn);
+    /// Generates a random 8-bit integer, masks it with immediate <nn>, and
+    /// loads the result into <vx>.
+    fn rnd(&mut self, vx: usize, nn: u8) {
+        self.v[vx] = self.rng.byte() & nn;
     }
 
-    /// Draws a 8xn monochrome sprite at coordinate (<vx>, <vy>) 
+    /// Draws a 8xn monochrome sprite at coordinate (<vx>, <vy>)
     /// starting from memory location <i>.
-    fn drw(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
-        let vy = ctx.vy();
-        let n = ctx.n();
+    fn drw(&mut self, ctx: &mut CpuContext, vx: usize, vy: usize, n: u8) {
+        if !self.check(self.addr(), 1) {
+            return;
+        }
         let x = self.v[vx];
         let y = self.v[vy];
-        let result = ctx.gpu.draw_sprite(&self.memory, self.i, n, x, y);
+        let result = ctx.gpu.draw_sprite(self.address_space(), self.i, n, x, y);
+        self.v[CARRY] = result.into();
+    }
+
+    /// Draws a 16x16 SUPER-CHIP sprite at coordinate (<vx>, <vy>)
+    /// starting from memory location <i>. The COSMAC VIP draws 0 rows
+    /// instead.
+    fn drw_vx_vy(&mut self, ctx: &mut CpuContext, vx: usize, vy: usize) {
+        if !self.quirks.sprite16 {
+            return self.drw(ctx, vx, vy, 0);
+        }
+        if !self.check(self.addr(), 1) {
+            return;
+        }
+        let x = self.v[vx];
+        let y = self.v[vy];
+        let result = ctx.gpu.draw_sprite16(self.address_space(), self.i, x, y);
         self.v[CARRY] = result.into();
-        log!("drw {:x}, {:x}, {:#02x}", x, y, n);
     }
 
     /// Skips the next instruction if the key stored in <vx> is pressed.
-    fn skp(&mut self, _ctx: &mut CpuContext) {
-        unimplemented!();
-        // log!("skp v{:x}", vx);
+    fn skp(&mut self, ctx: &mut CpuContext, vx: usize) {
+        let key = (self.v[vx] & 0x0f) as usize;
+        if ctx.keypad.get(key) {
+            self.skip();
+        }
     }
 
     /// Skips the next instruction if the key stored in <vx> is not pressed.
-    fn sknp(&mut self, _ctx: &mut CpuContext) {
-        unimplemented!();
-        // log!("sknp v{:x}", vx);
+    fn sknp(&mut self, ctx: &mut CpuContext, vx: usize) {
+        let key = (self.v[vx] & 0x0f) as usize;
+        if !ctx.keypad.get(key) {
+            self.skip();
+        }
+    }
+
+    /// Waits for a key to be pressed and released, then loads it into <vx>.
+    ///
+    /// Like the COSMAC VIP, the key is only accepted once it is released.
+    /// The instruction repeats itself while waiting, so the timers keep
+    /// counting down.
+    fn ld_vx_k(&mut self, ctx: &mut CpuContext, vx: usize) {
+        match self.key {
+            Some(key) if !ctx.keypad.get(key) => {
+                self.v[vx] = key as u8;
+                self.key = None;
+            },
+            Some(_) => self.jump(self.pc.wrapping_sub(2)),
+            None => {
+                self.key = ctx.keypad.pressed();
+                self.jump(self.pc.wrapping_sub(2));
+            }
+        }
     }
 
     /// Loads value of <dt> into <vx>
-    fn ld_vx_dt(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
+    fn ld_vx_dt(&mut self, vx: usize) {
         self.v[vx] = self.dt;
-        log!("ld v{:x}, dt", vx);
     }
 
     /// Loads the value of <vx> into the delay timer <dt>.
-    fn ld_dt_vx(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
+    fn ld_dt_vx(&mut self, vx: usize) {
         self.dt = self.v[vx];
-        log!("ld dt, v{:x}", vx);
     }
 
     /// Loads the value of <vx> into the sound timer <st>.
-    fn ld_st_vx(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
+    fn ld_st_vx(&mut self, vx: usize) {
         self.st = self.v[vx];
-        log!("ld st, v{:x}", vx);
     }
 
     /// Adds <vx> to <i> and loads the result into <i>.
-    fn add_i_vx(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
-        self.i = (self.i.saturating_add(self.v[vx] as u16)) & 0x0fff;
-        log!("ld i, v{:x}", vx);
+    fn add_i_vx(&mut self, vx: usize) {
+        self.i = self.i.saturating_add(self.v[vx] as u16);
     }
 
-    fn ld_b_vx(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
+    fn ld_b_vx(&mut self, vx: usize) {
         let v = self.v[vx];
-        let addr = self.i as usize;
+        let addr = self.addr();
+        if !self.check(addr, 3) {
+            return;
+        }
         self.memory[addr + 0] = (v / 100) % 10;
         self.memory[addr + 1] = (v / 10) % 10;
         self.memory[addr + 2] = v % 10;
-        log!("ld b, v{:x}", vx);
     }
 
     /// Loads values from registers <v0> to <vx> (inclusive) starting at memory address <i>.
-    fn ld_i_vx(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
+    fn ld_i_vx(&mut self, vx: usize) {
         let addr = self.addr();
+        if !self.check(addr, vx + 1) {
+            return;
+        }
         let mut memory = &mut self.memory[addr..];
-        let v = &self.v[0..vx];
+        let v = &self.v[0..=vx];
         memory.write(v).unwrap();
-        log!("ld i, v{:x}", vx);
+        self.advance_i(vx);
     }
 
     /// Loads values from memory starting at address <i> into registers <v0> to <vx> (inclusive).
-    fn ld_vx_i(&mut self, ctx: &mut CpuContext) {
-        let vx = ctx.vx();
+    fn ld_vx_i(&mut self, vx: usize) {
         let addr = self.addr();
+        if !self.check(addr, vx + 1) {
+            return;
+        }
         let memory = &self.memory[addr..];
-        let mut v = &mut self.v[0..vx];
+        let mut v = &mut self.v[0..=vx];
         v.write(memory).unwrap();
-        log!("ld v{:x}, i", vx);
+        self.advance_i(vx);
+    }
+
+    fn advance_i(&mut self, vx: usize) {
+        if self.quirks.load_store_i {
+            self.i = self.i.wrapping_add(vx as u16 + 1);
+        }
+    }
+
+    /// Stores registers <v0> to <vx> (inclusive) in the RPL user flags.
+    fn ld_r_vx(&mut self, vx: usize) {
+        self.rpl[0..=vx].copy_from_slice(&self.v[0..=vx]);
+    }
+
+    /// Loads registers <v0> to <vx> (inclusive) from the RPL user flags.
+    fn ld_vx_r(&mut self, vx: usize) {
+        self.v[0..=vx].copy_from_slice(&self.rpl[0..=vx]);
     }
 
 }
 
+#[cfg(test)]
+impl Cpu {
+    fn exec(&mut self, ctx: &mut CpuContext, instruction: Instruction) {
+        self.step(instruction.size());
+        self.execute(ctx, instruction);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    fn cpu_test<F>(exec: F) 
+    fn cpu_test<F>(exec: F)
         where F: FnOnce(&mut Cpu, &mut CpuContext) -> () {
-        let mut delay_timer = Timer::new(0);
-        let mut sound_timer = Timer::new(0);
         let mut gpu = Gpu::new();
+        let mut keypad = Keypad::new();
         let mut cpu = Cpu::new();
         let mut ctx = CpuContext {
-            opcode: 0x0000,
-            sound_timer: &mut sound_timer,
-            delay_timer: &mut delay_timer,
-            gpu: &mut gpu
+            gpu: &mut gpu,
+            keypad: &mut keypad
         };
         exec(&mut cpu, &mut ctx);
     }
@@ -536,8 +873,7 @@ mod tests {
     #[test]
     fn nop() {
         cpu_test(|cpu, ctx| {
-            ctx.opcode = 0x0000;
-            cpu.nop(ctx);
+            cpu.exec(ctx, Sys(0x000));
             assert_eq!(cpu.pc, 2);
             assert_eq!(cpu.sp, 0);
             assert_eq!(cpu.halted, false);
@@ -549,27 +885,24 @@ mod tests {
     #[test]
     fn cls() {
         cpu_test(|cpu, ctx| {
-            ctx.opcode = 0x0123;
-            cpu.cls(ctx);
+            ctx.gpu.vram[0] = 1;
+            ctx.gpu.vram[64 * 32 - 1] = 1;
+            cpu.exec(ctx, Cls);
             assert_eq!(cpu.pc, 2);
-            assert!(false);
+            assert!(ctx.gpu.vram.iter().all(|&texel| texel == 0));
         });
     }
 
     #[test]
     fn ret() {
         cpu_test(|cpu, ctx| {
-            ctx.opcode = 0x00ee;
-            cpu.ret(ctx);
+            cpu.exec(ctx, Ret);
             assert_eq!(cpu.pc, 2);
             cpu.reset();
-            ctx.opcode = 0x0000;
-            cpu.nop(ctx);        // pc = 2
-            ctx.opcode = 0x2009;
-            cpu.call(ctx);       // pc = 9, sp = 1, stack = 2
-            ctx.opcode = 0x00ee;
-            cpu.ret(ctx);        // pc = 2, sp = 0
-            assert_eq!(cpu.pc, 2);
+            cpu.exec(ctx, Sys(0x000));   // pc = 0x202
+            cpu.exec(ctx, Call(0x009));  // pc = 9, sp = 1, stack = 0x204
+            cpu.exec(ctx, Ret);          // pc = 0x204, sp = 0
+            assert_eq!(cpu.pc, 0x204);
             assert_eq!(cpu.sp, 0);
         });
     }
@@ -577,8 +910,7 @@ mod tests {
     #[test]
     fn sys() {
         cpu_test(|cpu, ctx| {
-            ctx.opcode = 0x0000;
-            cpu.sys(ctx);
+            cpu.exec(ctx, Sys(0x123));
             assert_eq!(cpu.pc, 2);
             assert_eq!(cpu.sp, 0);
             assert_eq!(cpu.halted, false);
@@ -590,8 +922,7 @@ mod tests {
     #[test]
     fn jp() {
         cpu_test(|cpu, ctx| {
-            ctx.opcode = 0x7fff;
-            cpu.jp(ctx);
+            cpu.exec(ctx, Jp(0xfff));
             assert_eq!(cpu.pc, 0x0fff);
         });
     }
@@ -599,13 +930,11 @@ mod tests {
     #[test]
     fn call() {
         cpu_test(|cpu, ctx| {
-            ctx.opcode = 0x0000;
-            cpu.nop(ctx);
-            ctx.opcode = 0x2117;
-            cpu.call(ctx);
+            cpu.exec(ctx, Sys(0x000));
+            cpu.exec(ctx, Call(0x117));
             assert_eq!(cpu.sp, 1);
             assert_eq!(cpu.pc, 0x117);
-            assert_eq!(cpu.stack[1], 2);
+            assert_eq!(cpu.stack[0], 4);
         });
     }
 
@@ -613,14 +942,12 @@ mod tests {
     fn se_vx_kk() {
         cpu_test(|cpu, ctx| {
             cpu.v[0x1] = 0x12;
-            ctx.opcode = 0x3112;
-            cpu.se_vx_kk(ctx);
+            cpu.exec(ctx, SeVxKk { x: 0x1, kk: 0x12 });
             assert_eq!(cpu.pc, 4);
             cpu.reset();
             cpu.v[0x1] = 0x12;
-            ctx.opcode = 0x0100;
-            cpu.se_vx_kk(ctx);
-            assert_eq!(cpu.pc, 2);
+            cpu.exec(ctx, SeVxKk { x: 0x1, kk: 0x00 });
+            assert_eq!(cpu.pc, 0x202);
         });
     }
 
@@ -628,31 +955,27 @@ mod tests {
     fn sne_vx_kk() {
         cpu_test(|cpu, ctx| {
             cpu.v[0x1] = 0x12;
-            ctx.opcode = 0x0113;
-            cpu.sne_vx_kk(ctx);
+            cpu.exec(ctx, SneVxKk { x: 0x1, kk: 0x13 });
             assert_eq!(cpu.pc, 4);
             cpu.reset();
             cpu.v[0x1] = 0x13;
-            ctx.opcode = 0x0113;
-            cpu.sne_vx_kk(ctx);
-            assert_eq!(cpu.pc, 2);
-        });  
+            cpu.exec(ctx, SneVxKk { x: 0x1, kk: 0x13 });
+            assert_eq!(cpu.pc, 0x202);
+        });
     }
-    
+
     #[test]
     fn se_vx_vy() {
         cpu_test(|cpu, ctx| {
             cpu.v[0x0] = 0x12;
             cpu.v[0x1] = 0x12;
-            ctx.opcode = 0x5010;
-            cpu.se_vx_vy(ctx);
+            cpu.exec(ctx, SeVxVy { x: 0x0, y: 0x1 });
             assert_eq!(cpu.pc, 4);
             cpu.reset();
             cpu.v[0x0] = 0x01;
             cpu.v[0x1] = 0x02;
-            ctx.opcode = 0x5010;
-            cpu.se_vx_vy(ctx);
-            assert_eq!(cpu.pc, 2);
+            cpu.exec(ctx, SeVxVy { x: 0x0, y: 0x1 });
+            assert_eq!(cpu.pc, 0x202);
         });
     }
 
@@ -660,9 +983,8 @@ mod tests {
     fn ld_vx_kk() {
         cpu_test(|cpu, ctx| {
             cpu.reset();
-            ctx.opcode = 0x001f;
-            cpu.ld_vx_kk(ctx);
-            assert_eq!(cpu.pc, 2);
+            cpu.exec(ctx, LdVxKk { x: 0x0, kk: 0x1f });
+            assert_eq!(cpu.pc, 0x202);
             assert_eq!(cpu.v[0], 0x1f);
         });
     }
@@ -672,16 +994,14 @@ mod tests {
         cpu_test(|cpu, ctx| {
             cpu.reset();
             cpu.v[0] = 0x1f;
-            ctx.opcode = 0x7020;
-            cpu.add_vx_kk(ctx);
-            assert_eq!(cpu.pc, 2);
+            cpu.exec(ctx, AddVxKk { x: 0x0, kk: 0x20 });
+            assert_eq!(cpu.pc, 0x202);
             assert_eq!(cpu.v[0], 0x3f);
             cpu.reset();
             cpu.v[0x0] = 0xff;
-            ctx.opcode = 0x7002;
-            cpu.add_vx_kk(ctx);
+            cpu.exec(ctx, AddVxKk { x: 0x0, kk: 0x02 });
             assert_eq!(cpu.v[0x0], 1);
-            assert_eq!(cpu.pc, 2);
+            assert_eq!(cpu.pc, 0x202);
         });
     }
 
@@ -690,8 +1010,7 @@ mod tests {
         cpu_test(|cpu, ctx| {
             cpu.v[0] = 10;
             cpu.v[1] = 20;
-            ctx.opcode = 0x8010;
-            cpu.ld_vx_vy(ctx);
+            cpu.exec(ctx, LdVxVy { x: 0x0, y: 0x1 });
             assert_eq!(cpu.v[0], 20);
             assert_eq!(cpu.v[1], 20);
             assert_eq!(cpu.pc, 2);
@@ -703,8 +1022,7 @@ mod tests {
         cpu_test(|cpu, ctx| {
             cpu.v[0] = 0x1f;
             cpu.v[1] = 0xf1;
-            ctx.opcode = 0x8011;
-            cpu.or(ctx);
+            cpu.exec(ctx, Or { x: 0x0, y: 0x1 });
             assert_eq!(cpu.pc, 2);
             assert_eq!(cpu.v[0], 0xff);
         });
@@ -715,7 +1033,7 @@ mod tests {
         cpu_test(|cpu, ctx| {
             cpu.v[0] = 0x1f;
             cpu.v[1] = 0x1f;
-            cpu.and(ctx.op(0x8012));
+            cpu.exec(ctx, And { x: 0x0, y: 0x1 });
             assert_eq!(cpu.v[0], 0x1f);
             assert_eq!(cpu.pc, 2);
         });
@@ -726,7 +1044,7 @@ mod tests {
         cpu_test(|cpu, ctx| {
             cpu.v[0] = 0x1f;
             cpu.v[1] = 0x20;
-            cpu.xor(ctx.op(0x8013));
+            cpu.exec(ctx, Xor { x: 0x0, y: 0x1 });
             assert_eq!(cpu.v[0], 0x3f);
             assert_eq!(cpu.pc, 2);
         });
@@ -737,17 +1055,17 @@ mod tests {
         cpu_test(|cpu, ctx| {
             cpu.v[0x0] = 250;
             cpu.v[0x1] = 10;
-            cpu.add_vx_vy(ctx.op(0x8014));
+            cpu.exec(ctx, AddVxVy { x: 0x0, y: 0x1 });
             assert_eq!(cpu.v[0xf], 1);
             assert_eq!(cpu.v[0x0], 4);
             assert_eq!(cpu.pc, 2);
             cpu.reset();
             cpu.v[0x0] = 100;
             cpu.v[0x1] = 28;
-            cpu.add_vx_vy(ctx.op(0x8014));
+            cpu.exec(ctx, AddVxVy { x: 0x0, y: 0x1 });
             assert_eq!(cpu.v[0xf], 0);
             assert_eq!(cpu.v[0x0], 128);
-            assert_eq!(cpu.pc, 2);
+            assert_eq!(cpu.pc, 0x202);
         });
     }
 
@@ -756,7 +1074,7 @@ mod tests {
         cpu_test(|cpu, ctx| {
             cpu.v[0x0] = 100;
             cpu.v[0x1] = 20;
-            cpu.sub_vx_vy(ctx.op(0x8015));
+            cpu.exec(ctx, SubVxVy { x: 0x0, y: 0x1 });
             assert_eq!(cpu.v[0x0], 80);
             assert_eq!(cpu.pc, 2);
         });
@@ -766,16 +1084,16 @@ mod tests {
     fn shr() {
         cpu_test(|cpu, ctx| {
             cpu.v[0x2] = 8;
-            cpu.shr(ctx.op(0x8206));
+            cpu.exec(ctx, Shr { x: 0x2, y: 0x0 });
             assert_eq!(cpu.v[0xf], 0);
             assert_eq!(cpu.v[0x2], 4);
             assert_eq!(cpu.pc, 2);
             cpu.reset();
             cpu.v[0x2] = 7;
-            cpu.shr(ctx.op(0x8206));
+            cpu.exec(ctx, Shr { x: 0x2, y: 0x0 });
             assert_eq!(cpu.v[0xf], 1);
             assert_eq!(cpu.v[0x2], 3);
-            assert_eq!(cpu.pc, 2);
+            assert_eq!(cpu.pc, 0x202);
         });
     }
 
@@ -784,17 +1102,17 @@ mod tests {
         cpu_test(|cpu, ctx| {
             cpu.v[0x0] = 10;
             cpu.v[0x1] = 20;
-            cpu.subn(ctx.op(0x8017));
+            cpu.exec(ctx, Subn { x: 0x0, y: 0x1 });
             assert_eq!(cpu.v[0xf], 1);
             assert_eq!(cpu.v[0x0], 10);
             assert_eq!(cpu.pc, 2);
             cpu.reset();
             cpu.v[0x0] = 10;
             cpu.v[0x1] = 5;
-            cpu.subn(ctx.op(0x8017));
+            cpu.exec(ctx, Subn { x: 0x0, y: 0x1 });
             assert_eq!(cpu.v[0xf], 0);
             assert_eq!(cpu.v[0x0], 251);
-            assert_eq!(cpu.pc, 2);
+            assert_eq!(cpu.pc, 0x202);
         });
     }
 
@@ -802,37 +1120,37 @@ mod tests {
     fn shl() {
         cpu_test(|cpu, ctx| {
             cpu.v[0x2] = 8;
-            cpu.shl(ctx.op(0x820e));
+            cpu.exec(ctx, Shl { x: 0x2, y: 0x0 });
             assert_eq!(cpu.v[0xf], 0);
             assert_eq!(cpu.v[0x2], 16);
             assert_eq!(cpu.pc, 2);
             cpu.reset();
             cpu.v[0x2] = 0x80;
-            cpu.shl(ctx.op(0x820e));
+            cpu.exec(ctx, Shl { x: 0x2, y: 0x0 });
             assert_eq!(cpu.v[0xf], 1);
-            assert_eq!(cpu.pc, 2);
+            assert_eq!(cpu.pc, 0x202);
         });
     }
-    
+
     #[test]
     fn sne_vx_vy() {
         cpu_test(|cpu, ctx| {
             cpu.v[0x2] = 8;
             cpu.v[0x3] = 7;
-            cpu.sne_vx_vy(ctx.op(0x9230));
+            cpu.exec(ctx, SneVxVy { x: 0x2, y: 0x3 });
             assert_eq!(cpu.pc, 4);
             cpu.reset();
             cpu.v[0x2] = 8;
             cpu.v[0x3] = 8;
-            cpu.sne_vx_vy(ctx.op(0x9230));
-            assert_eq!(cpu.pc, 2);
+            cpu.exec(ctx, SneVxVy { x: 0x2, y: 0x3 });
+            assert_eq!(cpu.pc, 0x202);
         });
     }
 
     #[test]
     fn ld() {
         cpu_test(|cpu, ctx| {
-            cpu.ld(ctx.op(0xa777));
+            cpu.exec(ctx, LdI(0x777));
             assert_eq!(cpu.i, 0x777);
             assert_eq!(cpu.pc, 2);
         });
@@ -842,52 +1160,83 @@ mod tests {
     fn jp_v0_addr() {
         cpu_test(|cpu, ctx| {
             cpu.v[0] = 0x32;
-            cpu.jp_v0_addr(ctx.op(0xb032));
+            cpu.exec(ctx, JpV0(0x032));
             assert_eq!(cpu.pc, 0x32 + 0x32);
-        }); 
+        });
     }
 
     #[test]
-    fn rnd() {
+    fn seed() {
         cpu_test(|cpu, ctx| {
-            for _ in 0..10 {
-                cpu.reset();
-                cpu.rnd(ctx.op(0xc1ff));
-                assert_eq!(cpu.pc, 2);
-                if cpu.v[0x1] != 0 {
-                    return;
+            let mut rolls = Vec::new();
+            for _ in 0..2 {
+                cpu.seed(0x5eed);
+                for _ in 0..8 {
+                    cpu.exec(ctx, Rnd { x: 0x1, kk: 0xff });
+                    rolls.push(cpu.v[0x1]);
                 }
             }
-            assert!(false, "rng doesn't work on host platform");
-        });    
+            assert_eq!(rolls[..8], rolls[8..]);
+        });
+    }
+
+    #[test]
+    fn rnd() {
+        cpu_test(|cpu, ctx| {
+            cpu.reset();
+            cpu.seed(0);
+            cpu.exec(ctx, Rnd { x: 0x1, kk: 0xff });
+            assert_eq!(cpu.v[0x1], 0xaf);
+            cpu.exec(ctx, Rnd { x: 0x1, kk: 0x0f });
+            assert_eq!(cpu.v[0x1], 0x04);
+            assert_eq!(cpu.pc, 0x204);
+        });
     }
 
     #[test]
     fn drw() {
         cpu_test(|cpu, ctx| {
             // draw 8x5 sprite (sprite 0) at (2, 4)
+            cpu.load(&[]);
             cpu.i = 0x0000;
-            cpu.drw(ctx.op(0xd245)); 
-            let index = 2 * ctx.gpu.width + 4;
-            let addr = cpu.i as usize;
+            cpu.v[0x2] = 2;
+            cpu.v[0x4] = 4;
+            cpu.exec(ctx, Drw { x: 0x2, y: 0x4, n: 0x5 });
+            assert_eq!(cpu.v[0xf], 0);
             // assert that vram matches sprite memory
-            let char = &cpu.memory[addr..addr+40];
-            let vram = &ctx.gpu.vram[index..index+40];
-            assert_eq!(char, vram);
-        });     
+            for row in 0..5 {
+                let index = (4 + row) * ctx.gpu.width + 2;
+                for bit in 0..8 {
+                    let pixel = (cpu.memory[row] >> (7 - bit)) & 0x1;
+                    assert_eq!(ctx.gpu.vram[index + bit], pixel);
+                }
+            }
+            cpu.exec(ctx, Drw { x: 0x2, y: 0x4, n: 0x5 });
+            assert_eq!(cpu.v[0xf], 1);
+        });
     }
 
     #[test]
     fn skp() {
         cpu_test(|cpu, ctx| {
-            assert!(false);
+            cpu.v[0x3] = 0x0a;
+            cpu.exec(ctx, Skp(0x3));
+            assert_eq!(cpu.pc, 2);
+            ctx.keypad.set(0x0a, true);
+            cpu.exec(ctx, Skp(0x3));
+            assert_eq!(cpu.pc, 6);
         });
     }
 
     #[test]
     fn sknp() {
         cpu_test(|cpu, ctx| {
-            assert!(false);
+            cpu.v[0x3] = 0x0a;
+            cpu.exec(ctx, Sknp(0x3));
+            assert_eq!(cpu.pc, 4);
+            ctx.keypad.set(0x0a, true);
+            cpu.exec(ctx, Sknp(0x3));
+            assert_eq!(cpu.pc, 6);
         });
     }
 
@@ -895,7 +1244,7 @@ mod tests {
     fn ld_vx_dt() {
         cpu_test(|cpu, ctx| {
             cpu.dt = 100;
-            cpu.ld_vx_dt(ctx.op(0xf107));
+            cpu.exec(ctx, LdVxDt(0x1));
             assert_eq!(cpu.dt, 100);
             assert_eq!(cpu.v[0x1], 100);
             assert_eq!(cpu.pc, 2);
@@ -905,21 +1254,83 @@ mod tests {
     #[test]
     fn ld_vx_k() {
         cpu_test(|cpu, ctx| {
-            assert!(false);
+            // no key pressed, instruction repeats
+            cpu.exec(ctx, LdVxK(0x3));
+            assert_eq!(cpu.pc, 0);
+            // key held down, still waiting for release
+            ctx.keypad.set(0x5, true);
+            cpu.exec(ctx, LdVxK(0x3));
+            assert_eq!(cpu.pc, 0);
+            cpu.exec(ctx, LdVxK(0x3));
+            assert_eq!(cpu.pc, 0);
+            assert_eq!(cpu.v[0x3], 0);
+            // key released
+            ctx.keypad.set(0x5, false);
+            cpu.exec(ctx, LdVxK(0x3));
+            assert_eq!(cpu.pc, 2);
+            assert_eq!(cpu.v[0x3], 0x5);
+        });
+    }
+
+    #[test]
+    fn ld_vx_k_end_of_memory() {
+        cpu_test(|cpu, ctx| {
+            cpu.set_quirks(Quirks::xochip());
+            cpu.pc = 0xfffe;
+            cpu.memory[0xfffe] = 0xf3;
+            cpu.memory[0xffff] = 0x0a;
+            // keeps waiting in the last word rather than running off the end
+            for _ in 0..3 {
+                assert_eq!(cpu.cycle(ctx), Ok(()));
+                assert!(!cpu.halted);
+                assert_eq!(cpu.pc, 0xfffe);
+            }
+            ctx.keypad.set(0x7, true);
+            assert_eq!(cpu.cycle(ctx), Ok(()));
+            assert_eq!(cpu.pc, 0xfffe);
+            ctx.keypad.set(0x7, false);
+            assert_eq!(cpu.cycle(ctx), Ok(()));
+            assert_eq!(cpu.v[0x3], 0x7);
+            assert_eq!(cpu.pc, 0);
+            assert!(!cpu.halted);
         });
     }
 
     #[test]
     fn ld_dt_vx() {
         cpu_test(|cpu, ctx| {
-            assert!(false);
+            cpu.v[0x4] = 60;
+            cpu.exec(ctx, LdDtVx(0x4));
+            assert_eq!(cpu.dt, 60);
+            assert_eq!(cpu.st, 0);
+            assert_eq!(cpu.pc, 2);
         });
     }
 
     #[test]
     fn ld_st_vx() {
         cpu_test(|cpu, ctx| {
-            assert!(false);
+            cpu.v[0x4] = 30;
+            cpu.exec(ctx, LdStVx(0x4));
+            assert_eq!(cpu.st, 30);
+            assert_eq!(cpu.dt, 0);
+            assert_eq!(cpu.pc, 2);
+        });
+    }
+
+    #[test]
+    fn tick() {
+        cpu_test(|cpu, ctx| {
+            cpu.v[0x0] = 2;
+            cpu.exec(ctx, LdDtVx(0x0));
+            cpu.exec(ctx, LdStVx(0x0));
+            cpu.tick();
+            assert_eq!(cpu.dt, 1);
+            assert_eq!(cpu.st, 1);
+            cpu.tick();
+            cpu.tick();
+            assert_eq!(cpu.dt, 0);
+            assert_eq!(cpu.st, 0);
         });
     }
 
@@ -928,12 +1339,12 @@ mod tests {
         cpu_test(|cpu, ctx| {
             cpu.v[0x0f] = 10;
             cpu.i = 1;
-            cpu.add_i_vx(ctx.op(0x0f00));
+            cpu.exec(ctx, AddIVx(0xf));
             assert_eq!(cpu.pc, 2);
             assert_eq!(cpu.i, 11);
             cpu.i = 0xffff;
             cpu.v[0x0f] = 0xff;
-            cpu.add_i_vx(ctx.op(0x0f00));
+            cpu.exec(ctx, AddIVx(0xf));
             assert_eq!(cpu.i, 0xffff);
         });
     }
@@ -941,7 +1352,10 @@ mod tests {
     #[test]
     fn ld_f_vx() {
         cpu_test(|cpu, ctx| {
-            assert!(false);
+            cpu.v[0x2] = 0xa;
+            cpu.exec(ctx, LdFVx(0x2));
+            assert_eq!(cpu.i, 50);
+            assert_eq!(cpu.pc, 2);
         });
     }
 
@@ -950,7 +1364,7 @@ mod tests {
         cpu_test(|cpu, ctx| {
             cpu.i = 0x10;
             cpu.v[1] = 123;
-            cpu.ld_b_vx(ctx.op(0xf133));
+            cpu.exec(ctx, LdBVx(0x1));
             assert_eq!(cpu.pc, 2);
             assert_eq!(cpu.memory[0x10 + 0], 1);
             assert_eq!(cpu.memory[0x10 + 1], 2);
@@ -965,7 +1379,7 @@ mod tests {
             for i in 0x0..0xf {
                 cpu.v[i] = (i * 2) as u8;
             }
-            cpu.ld_i_vx(ctx.op(0xff55));
+            cpu.exec(ctx, LdIVx(0xf));
             assert_eq!(cpu.pc, 2);
             for i in 0x0..0xf {
                 assert_eq!(cpu.memory[0x10 + i], (i * 2) as u8);
@@ -980,7 +1394,7 @@ mod tests {
             for i in 0x0..0xf {
                 cpu.memory[cpu.i as usize + i] = (i * 2) as u8;
             }
-            cpu.ld_vx_i(ctx.op(0xff65));
+            cpu.exec(ctx, LdVxI(0xf));
             assert_eq!(cpu.pc, 2);
             for i in 0x0..0xf {
                 assert_eq!(cpu.v[i], (i * 2) as u8);
@@ -991,71 +1405,407 @@ mod tests {
     #[test]
     fn scd() {
         cpu_test(|cpu, ctx| {
-            assert!(false);
+            ctx.gpu.vram[0] = 1;
+            cpu.exec(ctx, Scd(0x3));
+            assert_eq!(ctx.gpu.vram[0], 0);
+            assert_eq!(ctx.gpu.vram[3 * ctx.gpu.width], 1);
         });
     }
 
     #[test]
     fn scr() {
         cpu_test(|cpu, ctx| {
-            assert!(false);
+            ctx.gpu.vram[0] = 1;
+            cpu.exec(ctx, Scr);
+            assert_eq!(ctx.gpu.vram[0], 0);
+            assert_eq!(ctx.gpu.vram[4], 1);
         });
     }
 
     #[test]
     fn scl() {
         cpu_test(|cpu, ctx| {
-            assert!(false);
+            ctx.gpu.vram[4] = 1;
+            ctx.gpu.vram[ctx.gpu.width] = 1;
+            cpu.exec(ctx, Scl);
+            assert_eq!(ctx.gpu.vram[0], 1);
+            assert_eq!(ctx.gpu.vram[4], 0);
+            assert_eq!(ctx.gpu.vram[ctx.gpu.width - 4], 0);
+        });
+    }
+
+    #[test]
+    fn ld_i_long() {
+        cpu_test(|cpu, ctx| {
+            cpu.pc = 0x200;
+            cpu.memory[0x200] = 0xf0;
+            cpu.memory[0x201] = 0x00;
+            cpu.memory[0x202] = 0x12;
+            cpu.memory[0x203] = 0x34;
+            assert_eq!(cpu.cycle(ctx), Ok(()));
+            assert_eq!(cpu.i, 0x1234);
+            assert_eq!(cpu.pc, 0x204);
+        });
+    }
+
+    #[test]
+    fn skip_long() {
+        cpu_test(|cpu, ctx| {
+            cpu.pc = 0x200;
+            cpu.memory[0x202] = 0xf0;
+            cpu.memory[0x203] = 0x00;
+            cpu.exec(ctx, SeVxKk { x: 0x0, kk: 0x00 });
+            assert_eq!(cpu.pc, 0x206);
+        });
+    }
+
+    #[test]
+    fn plane() {
+        cpu_test(|cpu, ctx| {
+            cpu.exec(ctx, Plane(0x3));
+            assert_eq!(ctx.gpu.plane, 0x3);
+            // sprite data for plane 1 follows plane 0
+            cpu.memory[0x300] = 0x80;
+            cpu.memory[0x301] = 0xc0;
+            cpu.i = 0x300;
+            cpu.exec(ctx, Drw { x: 0x0, y: 0x1, n: 0x1 });
+            assert_eq!(ctx.gpu.vram[0], 0x3);
+            assert_eq!(ctx.gpu.vram[1], 0x2);
+            cpu.exec(ctx, Plane(0x2));
+            cpu.exec(ctx, Cls);
+            assert_eq!(ctx.gpu.vram[0], 0x1);
+            assert_eq!(ctx.gpu.vram[1], 0x0);
+        });
+    }
+
+    #[test]
+    fn audio() {
+        cpu_test(|cpu, ctx| {
+            for i in 0..16 {
+                cpu.memory[0x400 + i] = i as u8;
+            }
+            cpu.i = 0x400;
+            cpu.exec(ctx, Audio);
+            assert_eq!(cpu.pattern()[15], 15);
+            cpu.v[0x4] = 112;
+            cpu.exec(ctx, Pitch(0x4));
+            assert_eq!(cpu.pitch(), 112);
+        });
+    }
+
+    #[test]
+    fn ld_i_vx_vy() {
+        cpu_test(|cpu, ctx| {
+            cpu.set_quirks(Quirks::xochip());
+            cpu.i = 0x8000;
+            cpu.v[0x2] = 1;
+            cpu.v[0x3] = 2;
+            cpu.v[0x4] = 3;
+            cpu.exec(ctx, Save { x: 0x2, y: 0x4 });
+            assert_eq!(cpu.memory[0x8000..0x8003], [1, 2, 3]);
+            cpu.exec(ctx, Save { x: 0x4, y: 0x2 });
+            assert_eq!(cpu.memory[0x8000..0x8003], [3, 2, 1]);
+            assert_eq!(cpu.i, 0x8000);
+        });
+    }
+
+    #[test]
+    fn ld_vx_vy_i() {
+        cpu_test(|cpu, ctx| {
+            cpu.set_quirks(Quirks::xochip());
+            cpu.i = 0x8000;
+            cpu.memory[0x8000] = 1;
+            cpu.memory[0x8001] = 2;
+            cpu.exec(ctx, Load { x: 0x5, y: 0x6 });
+            assert_eq!(cpu.v[0x5..0x7], [1, 2]);
+            cpu.exec(ctx, Load { x: 0x6, y: 0x5 });
+            assert_eq!(cpu.v[0x5..0x7], [2, 1]);
+            assert_eq!(cpu.i, 0x8000);
+        });
+    }
+
+    #[test]
+    fn quirk_shift_vy() {
+        cpu_test(|cpu, ctx| {
+            cpu.set_quirks(Quirks::vip());
+            cpu.v[0x1] = 0x81;
+            cpu.exec(ctx, Shr { x: 0x0, y: 0x1 });
+            assert_eq!(cpu.v[0x0], 0x40);
+            assert_eq!(cpu.v[0xf], 1);
+            cpu.exec(ctx, Shl { x: 0x0, y: 0x1 });
+            assert_eq!(cpu.v[0x0], 0x02);
+            assert_eq!(cpu.v[0xf], 1);
+            assert_eq!(cpu.v[0x1], 0x81);
+        });
+    }
+
+    #[test]
+    fn quirk_load_store_i() {
+        cpu_test(|cpu, ctx| {
+            cpu.i = 0x300;
+            cpu.exec(ctx, LdIVx(0x3));
+            assert_eq!(cpu.i, 0x300);
+            cpu.set_quirks(Quirks::vip());
+            cpu.exec(ctx, LdIVx(0x3));
+            assert_eq!(cpu.i, 0x304);
+            cpu.exec(ctx, LdVxI(0x1));
+            assert_eq!(cpu.i, 0x306);
+        });
+    }
+
+    #[test]
+    fn quirk_logic_vf() {
+        cpu_test(|cpu, ctx| {
+            cpu.v[0xf] = 1;
+            cpu.exec(ctx, Or { x: 0x0, y: 0x1 });
+            assert_eq!(cpu.v[0xf], 1);
+            cpu.set_quirks(Quirks::vip());
+            cpu.exec(ctx, Xor { x: 0x0, y: 0x1 });
+            assert_eq!(cpu.v[0xf], 0);
+        });
+    }
+
+    #[test]
+    fn quirk_jump_vx() {
+        cpu_test(|cpu, ctx| {
+            cpu.v[0x0] = 0x10;
+            cpu.v[0x2] = 0x20;
+            cpu.exec(ctx, JpV0(0x230));
+            assert_eq!(cpu.pc, 0x250);
+            cpu.set_quirks(Quirks::vip());
+            cpu.exec(ctx, JpV0(0x230));
+            assert_eq!(cpu.pc, 0x240);
+        });
+    }
+
+    #[test]
+    fn quirk_vf_last() {
+        cpu_test(|cpu, ctx| {
+            cpu.v[0xf] = 0xff;
+            cpu.v[0x1] = 0x01;
+            cpu.exec(ctx, AddVxVy { x: 0xf, y: 0x1 });
+            assert_eq!(cpu.v[0xf], 1);
+            cpu.set_quirks(Quirks { vf_last: false, ..Quirks::vip() });
+            cpu.v[0xf] = 0xff;
+            cpu.exec(ctx, AddVxVy { x: 0xf, y: 0x1 });
+            assert_eq!(cpu.v[0xf], 0);
+        });
+    }
+
+    #[test]
+    fn quirk_clip() {
+        cpu_test(|cpu, ctx| {
+            // draw the 8x5 sprite for 0 straddling the bottom right corner
+            cpu.load(&[]);
+            cpu.i = 0x0000;
+            cpu.v[0x0] = 60;
+            cpu.v[0x1] = 30;
+            cpu.exec(ctx, Drw { x: 0x0, y: 0x1, n: 0x5 });
+            assert_eq!(ctx.gpu.vram[30 * 64 + 60], 1);
+            assert_eq!(ctx.gpu.vram[60], 0);
+            ctx.gpu.clear();
+            ctx.gpu.quirks = Quirks::xochip();
+            cpu.exec(ctx, Drw { x: 0x0, y: 0x1, n: 0x5 });
+            assert_eq!(ctx.gpu.vram[30 * 64 + 60], 1);
+            assert_eq!(ctx.gpu.vram[60], 1);
+            assert_eq!(ctx.gpu.vram[2 * 64 + 63], 1);
+        });
+    }
+
+    #[test]
+    fn stack_overflow() {
+        cpu_test(|cpu, ctx| {
+            for _ in 0..16 {
+                cpu.exec(ctx, Call(0x300));
+            }
+            assert_eq!(cpu.fault, None);
+            cpu.exec(ctx, Call(0x400));
+            assert_eq!(cpu.fault, Some(CpuFault::StackOverflow));
+            assert_eq!(cpu.sp, 16);
+            assert_eq!(cpu.pc, 0x302);
+        });
+    }
+
+    #[test]
+    fn stack_underflow() {
+        cpu_test(|cpu, ctx| {
+            cpu.exec(ctx, Ret);
+            assert_eq!(cpu.fault, Some(CpuFault::StackUnderflow));
+            assert_eq!(cpu.sp, 0);
+        });
+    }
+
+    #[test]
+    fn memory_out_of_bounds() {
+        cpu_test(|cpu, ctx| {
+            cpu.set_quirks(Quirks { load_store_i: false, ..Quirks::xochip() });
+            cpu.i = 0xfffe;
+            cpu.exec(ctx, LdBVx(0x0));
+            assert_eq!(cpu.fault, Some(CpuFault::MemoryOutOfBounds(0x10000)));
+            cpu.fault = None;
+            cpu.exec(ctx, LdIVx(0x1));
+            assert_eq!(cpu.fault, None);
+            cpu.exec(ctx, LdVxI(0x2));
+            assert_eq!(cpu.fault, Some(CpuFault::MemoryOutOfBounds(0x10000)));
+        });
+    }
+
+    #[test]
+    fn memory_out_of_bounds_vip() {
+        cpu_test(|cpu, ctx| {
+            // the VIP only addresses 4K, not the whole 64K array
+            cpu.set_quirks(Quirks::vip());
+            cpu.i = 0x0ffe;
+            cpu.exec(ctx, LdBVx(0x0));
+            assert_eq!(cpu.fault, Some(CpuFault::MemoryOutOfBounds(0x1000)));
+            cpu.fault = None;
+            cpu.i = 0x1000;
+            cpu.exec(ctx, LdVxI(0x0));
+            assert_eq!(cpu.fault, Some(CpuFault::MemoryOutOfBounds(0x1000)));
+            assert_eq!(cpu.i, 0x1000);
+            cpu.fault = None;
+            cpu.exec(ctx, Drw { x: 0x0, y: 0x1, n: 0x1 });
+            assert_eq!(cpu.fault, Some(CpuFault::MemoryOutOfBounds(0x1000)));
+            cpu.fault = None;
+            cpu.pc = 0x0fff;
+            assert_eq!(cpu.cycle(ctx), Err(CpuFault::MemoryOutOfBounds(0x1000)));
+        });
+    }
+
+    #[test]
+    fn cycle_faults() {
+        cpu_test(|cpu, ctx| {
+            cpu.reset();
+            cpu.set_quirks(Quirks::xochip());
+            cpu.load(&[0x80, 0x09, 0x00, 0xe0]);
+            assert_eq!(cpu.cycle(ctx), Err(CpuFault::InvalidOpcode(0x8009)));
+            assert_eq!(cpu.cycle(ctx), Ok(()));
+            assert_eq!(cpu.pc, 0x204);
+            cpu.pc = 0xffff;
+            assert_eq!(cpu.cycle(ctx), Err(CpuFault::MemoryOutOfBounds(0x10000)));
+            cpu.pc = 0xfffe;
+            cpu.memory[0xfffe] = 0xf0;
+            cpu.memory[0xffff] = 0x00;
+            assert_eq!(cpu.cycle(ctx), Err(CpuFault::MemoryOutOfBounds(0x10001)));
+        });
+    }
+
+    #[test]
+    fn end_of_memory() {
+        cpu_test(|cpu, ctx| {
+            cpu.set_quirks(Quirks::xochip());
+            // a jump in the last word runs normally
+            cpu.pc = 0xfffe;
+            cpu.memory[0xfffe] = 0x12;
+            cpu.memory[0xffff] = 0x00;
+            assert_eq!(cpu.cycle(ctx), Ok(()));
+            assert_eq!(cpu.pc, 0x200);
+            assert!(!cpu.halted);
+            // falling off the end faults on the next fetch
+            cpu.pc = 0xfffe;
+            cpu.memory[0xfffe] = 0x00;
+            cpu.memory[0xffff] = 0xe0;
+            assert_eq!(cpu.cycle(ctx), Ok(()));
+            assert_eq!(cpu.pc, 0);
+            assert!(!cpu.halted);
+            assert_eq!(cpu.cycle(ctx), Err(CpuFault::MemoryOutOfBounds(0x10000)));
         });
     }
 
     #[test]
     fn exit() {
         cpu_test(|cpu, ctx| {
-            cpu.exit(ctx.op(0x0000));
+            cpu.exec(ctx, Exit);
             assert!(cpu.halted);
-        });   
+        });
     }
 
     #[test]
     fn low() {
         cpu_test(|cpu, ctx| {
-            assert!(false);
+            cpu.exec(ctx, High);
+            cpu.exec(ctx, Low);
+            assert_eq!(ctx.gpu.width, 64);
+            assert_eq!(ctx.gpu.height, 32);
         });
     }
 
     #[test]
     fn high() {
         cpu_test(|cpu, ctx| {
-            assert!(false);
+            cpu.exec(ctx, High);
+            assert_eq!(ctx.gpu.width, 128);
+            assert_eq!(ctx.gpu.height, 64);
         });
     }
 
     #[test]
     fn drw_vx_vy() {
         cpu_test(|cpu, ctx| {
-            assert!(false);
+            // draw 16x16 solid sprite at (8, 2)
+            ctx.gpu.high();
+            cpu.memory[0x300..0x320].copy_from_slice(&[0xff; 32]);
+            cpu.i = 0x300;
+            cpu.v[0x1] = 8;
+            cpu.v[0x2] = 2;
+            cpu.exec(ctx, Drw { x: 0x1, y: 0x2, n: 0x0 });
+            assert_eq!(cpu.v[0xf], 0);
+            let width = ctx.gpu.width;
+            for y in 0..16 {
+                let row = (y + 2) * width;
+                assert_eq!(ctx.gpu.vram[row + 7], 0);
+                assert_eq!(&ctx.gpu.vram[row + 8..row + 24], &[1; 16]);
+                assert_eq!(ctx.gpu.vram[row + 24], 0);
+            }
+            cpu.exec(ctx, Drw { x: 0x1, y: 0x2, n: 0x0 });
+            assert_eq!(cpu.v[0xf], 1);
+        });
+    }
+
+    #[test]
+    fn drw_vx_vy_vip() {
+        cpu_test(|cpu, ctx| {
+            // the VIP draws nothing and clears vf
+            cpu.set_quirks(Quirks::vip());
+            cpu.memory[0x300..0x320].copy_from_slice(&[0xff; 32]);
+            cpu.i = 0x300;
+            cpu.v[0xf] = 1;
+            cpu.exec(ctx, Drw { x: 0x1, y: 0x2, n: 0x0 });
+            assert_eq!(cpu.v[0xf], 0);
+            assert!(ctx.gpu.vram.iter().all(|&pixel| pixel == 0));
         });
     }
 
     #[test]
     fn ld_hf_vx() {
         cpu_test(|cpu, ctx| {
-            assert!(false);
+            cpu.v[0x2] = 0x3;
+            cpu.exec(ctx, LdHfVx(0x2));
+            assert_eq!(cpu.i, BIGFONT + 30);
         });
     }
 
     #[test]
     fn ld_r_vx() {
         cpu_test(|cpu, ctx| {
-            assert!(false);
+            for i in 0x0..0x10 {
+                cpu.v[i] = (i + 1) as u8;
+            }
+            cpu.exec(ctx, LdRVx(0x7));
+            assert_eq!(cpu.rpl[0..8], [1, 2, 3, 4, 5, 6, 7, 8]);
+            assert_eq!(cpu.rpl[8], 0);
         });
     }
 
     #[test]
     fn ld_vx_r() {
         cpu_test(|cpu, ctx| {
-            assert!(false);
+            for i in 0x0..0x10 {
+                cpu.rpl[i] = (i + 1) as u8;
+            }
+            cpu.exec(ctx, LdVxR(0x3));
+            assert_eq!(cpu.v[0..4], [1, 2, 3, 4]);
+            assert_eq!(cpu.v[4], 0);
         });
     }
 
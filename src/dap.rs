@@ -0,0 +1,728 @@
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use crate::console::{Action, Console};
+use crate::debugger::{self, Condition, Debugger, Register, Stop, Trigger};
+use crate::json::Value;
+use crate::machine::Machine;
+use crate::octo;
+use crate::sourcemap::SourceMap;
+use crate::timer::{Timer, FRAME_RATE};
+
+/// The only thread.
+const THREAD: i64 = 1;
+
+/// The variables reference of the register scope.
+const REGISTERS: i64 = 1;
+
+/// The source reference of the disassembly shown for ROMs without a
+/// source map.
+const LISTING: i64 = 1;
+
+/// The largest message body accepted, far beyond anything an editor sends.
+const MAX_MESSAGE: usize = 1 << 20;
+
+/// Builds the machine to debug from a launch request's arguments.
+pub type Launcher = Box<dyn FnMut(&Value) -> Result<Machine, String>>;
+
+/// A Debug Adapter Protocol server, letting editors such as VS Code debug
+/// a ROM against its assembly source.
+///
+/// Launch arguments are `program`, the ROM, `sourceMap`, a file of
+/// `<addr> <file>:<line>` lines (default: the ROM path with a .map
+/// extension, if there is one) and `stopOnEntry`. Without a source map the
+/// editor is given a disassembly of the ROM to step through instead.
+pub struct Server {
+    launcher: Launcher,
+    out: Box<dyn Write>,
+    seq: i64,
+    machine: Option<Machine>,
+    debugger: Debugger,
+    console: Console,
+    map: SourceMap,
+    /// The disassembly, when there's no source map.
+    listing: Option<String>,
+    /// The debugger ids of the breakpoints set in each source.
+    breakpoints: Vec<(String, Vec<usize>)>,
+    stop_on_entry: bool,
+    /// Events waiting for the response to the current request to go
+    /// first.
+    events: Vec<Value>,
+    running: bool,
+    clock: Timer,
+    done: bool
+}
+
+impl Server {
+
+    pub fn new(launcher: Launcher, out: Box<dyn Write>) -> Self {
+        Server {
+            launcher,
+            out,
+            seq: 0,
+            machine: None,
+            debugger: Debugger::new(),
+            console: Console::new(),
+            map: SourceMap::new(),
+            listing: None,
+            breakpoints: Vec::new(),
+            stop_on_entry: false,
+            events: Vec::new(),
+            running: false,
+            clock: Timer::new(FRAME_RATE),
+            done: false
+        }
+    }
+
+    /// Handles messages from <input> until the client disconnects, running
+    /// the machine in real time while it isn't stopped.
+    pub fn serve(mut self, mut input: Box<dyn BufRead + Send>) -> Result<(), String> {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            while let Ok(Some(message)) = read_message(&mut input) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        let period = Duration::from_secs(1) / FRAME_RATE;
+        while !self.done {
+            let message = match self.running {
+                true => receiver.recv_timeout(period / 4),
+                false => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            };
+            match message {
+                Ok(message) => self.receive(&message),
+                Err(RecvTimeoutError::Timeout) => {
+                    let frames = self.clock.tick();
+                    self.advance(frames);
+                },
+                Err(RecvTimeoutError::Disconnected) => break
+            }
+        }
+        self.finish()
+    }
+
+    /// Handles one message, reporting unreadable ones to the client.
+    fn receive(&mut self, text: &str) {
+        match crate::json::parse(text) {
+            Ok(message) => self.handle(&message),
+            Err(e) => {
+                self.output(&format!("invalid message: {}", e));
+                self.flush();
+            }
+        }
+    }
+
+    /// Handles a request, writing its response and any events it causes.
+    pub fn handle(&mut self, request: &Value) {
+        if request.get("type").as_str() != Some("request") {
+            return;
+        }
+        let command = request.get("command").as_str().unwrap_or("");
+        let arguments = request.get("arguments");
+        let result = match command {
+            "initialize" => Ok(capabilities()),
+            "launch" => self.launch(arguments),
+            "disconnect" | "terminate" => {
+                self.done = true;
+                Ok(Value::Null)
+            },
+            _ if self.machine.is_none() => Err(String::from("no program has been launched")),
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "configurationDone" => {
+                match self.stop_on_entry {
+                    true => self.stopped("entry", "entry", None),
+                    false => self.run()
+                }
+                Ok(Value::Null)
+            },
+            "threads" => Ok(Value::object(vec![
+                ("threads", Value::from(vec![Value::object(vec![("id", Value::from(THREAD)), ("name", Value::from("chip8"))])]))
+            ])),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(Value::object(vec![
+                ("scopes", Value::from(vec![Value::object(vec![
+                    ("name", Value::from("Registers")),
+                    ("presentationHint", Value::from("registers")),
+                    ("variablesReference", Value::from(REGISTERS)),
+                    ("expensive", Value::from(false))
+                ])]))
+            ])),
+            "variables" => Ok(self.variables(arguments)),
+            "setVariable" => self.set_variable(arguments),
+            "continue" => {
+                self.debugger.resume();
+                self.run();
+                Ok(Value::object(vec![("allThreadsContinued", Value::from(true))]))
+            },
+            "next" => {
+                self.debugger.step_over(self.machine.as_ref().unwrap());
+                self.run();
+                Ok(Value::Null)
+            },
+            "stepIn" => {
+                self.debugger.step();
+                self.run();
+                Ok(Value::Null)
+            },
+            "stepOut" => {
+                let machine = self.machine.as_ref().unwrap();
+                match machine.cpu.sp() {
+                    0 => self.debugger.step(),
+                    _ => self.debugger.step_out(machine)
+                }
+                self.run();
+                Ok(Value::Null)
+            },
+            "pause" => {
+                self.running = false;
+                self.stopped("pause", "paused", None);
+                Ok(Value::Null)
+            },
+            "readMemory" => self.read_memory(arguments),
+            "disassemble" => self.disassemble(arguments),
+            "source" => match &self.listing {
+                Some(listing) => Ok(Value::object(vec![("content", Value::from(listing.as_str()))])),
+                None => Err(String::from("no such source"))
+            },
+            "evaluate" => self.evaluate(arguments),
+            _ => Err(format!("unsupported request '{}'", command))
+        };
+        let mut response = vec![
+            ("type", Value::from("response")),
+            ("request_seq", request.get("seq").clone()),
+            ("command", Value::from(command)),
+            ("success", Value::from(result.is_ok()))
+        ];
+        match result {
+            Ok(Value::Null) => (),
+            Ok(body) => response.push(("body", body)),
+            Err(e) => response.push(("message", Value::from(e)))
+        }
+        self.send(Value::object(response));
+        self.flush();
+    }
+
+    /// Runs up to <frames> frames, stopping early if the debugger stops.
+    pub fn advance(&mut self, frames: u32) {
+        let machine = match (&mut self.machine, self.running) {
+            (Some(machine), true) => machine,
+            _ => return
+        };
+        for _ in 0..frames {
+            if let Some(stop) = self.debugger.frame(machine) {
+                self.running = false;
+                self.report(stop);
+                break;
+            }
+        }
+        self.flush();
+    }
+
+    /// Finishes the trace, if one is being written.
+    pub fn finish(&mut self) -> Result<(), String> {
+        match &mut self.machine {
+            Some(machine) => machine.finish_trace(),
+            None => Ok(())
+        }
+    }
+
+    fn run(&mut self) {
+        self.running = true;
+        self.clock.reset();
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let machine = (self.launcher)(arguments)?;
+        let program = arguments.get("program").as_str().map(PathBuf::from);
+        self.map = match (arguments.get("sourceMap").as_str(), &program) {
+            (Some(path), _) => SourceMap::load(Path::new(path))?,
+            (None, Some(program)) if program.with_extension("map").exists() => {
+                SourceMap::load(&program.with_extension("map"))?
+            },
+            (None, Some(program)) if octo::is_source(program) => octo::compile_file(program)?.map,
+            _ => SourceMap::new()
+        };
+        self.listing = None;
+        if self.map.is_empty() {
+            let (listing, map) = listing(&machine);
+            self.listing = Some(listing);
+            self.map = map;
+        }
+        self.stop_on_entry = arguments.get("stopOnEntry").as_bool().unwrap_or(false);
+        self.machine = Some(machine);
+        // the client only sends breakpoints once it knows the program is
+        // loaded
+        self.event("initialized", Value::Null);
+        Ok(Value::Null)
+    }
+
+    /// The source the client knows <addr> by, and the line in it.
+    fn source(&self, addr: u16) -> Option<(Value, u32)> {
+        let location = self.map.location(addr)?;
+        let source = match self.listing {
+            Some(_) => Value::object(vec![
+                ("name", Value::from("disassembly")),
+                ("sourceReference", Value::from(LISTING))
+            ]),
+            None => Value::object(vec![
+                ("name", Value::from(location.file.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned()))),
+                ("path", Value::from(location.file.to_string_lossy().into_owned()))
+            ])
+        };
+        Some((source, location.line))
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let source = arguments.get("source");
+        let (key, file) = match (source.get("path").as_str(), source.get("sourceReference").as_i64()) {
+            (_, Some(reference)) if reference > 0 && self.listing.is_some() => (format!("#{}", reference), PathBuf::new()),
+            (Some(path), _) => (String::from(path), PathBuf::from(path)),
+            _ => return Err(String::from("unknown source"))
+        };
+        // the request replaces every breakpoint in the source
+        if let Some(at) = self.breakpoints.iter().position(|(name, _)| *name == key) {
+            for id in self.breakpoints.remove(at).1 {
+                self.debugger.remove(id);
+            }
+        }
+        let mut ids = Vec::new();
+        let mut results = Vec::new();
+        for breakpoint in arguments.get("breakpoints").as_array().unwrap_or(&[]) {
+            let line = breakpoint.get("line").as_i64().unwrap_or(0).max(0) as u32;
+            let condition = breakpoint.get("condition").as_str()
+                .filter(|text| !text.trim().is_empty())
+                .map(Condition::parse)
+                .transpose();
+            let found = self.map.address(&file, line).ok_or(String::from("no code at or after this line"));
+            let result = match (found, condition) {
+                (Ok((line, addr)), Ok(condition)) => {
+                    let id = self.debugger.add(Trigger::Breakpoint { addr, condition });
+                    ids.push(id);
+                    vec![("id", Value::from(id as i64)), ("verified", Value::from(true)), ("line", Value::from(line as i64))]
+                },
+                (Err(e), _) | (_, Err(e)) => {
+                    vec![("verified", Value::from(false)), ("line", Value::from(line as i64)), ("message", Value::from(e))]
+                }
+            };
+            results.push(Value::object(result));
+        }
+        self.breakpoints.push((key, ids));
+        Ok(Value::object(vec![("breakpoints", Value::from(results))]))
+    }
+
+    /// The current instruction, then the call of each subroutine on the
+    /// stack, innermost first.
+    fn stack_trace(&self) -> Value {
+        let cpu = &self.machine.as_ref().unwrap().cpu;
+        // the stack holds return addresses, and calls are 2 bytes
+        let calls = cpu.stack().iter().rev().map(|addr| addr.wrapping_sub(2));
+        let frames: Vec<Value> = std::iter::once(cpu.pc()).chain(calls).enumerate().map(|(id, addr)| {
+            let mut frame = vec![
+                ("id", Value::from(id as i64)),
+                ("name", Value::from(format!("{:#06x}", addr))),
+                ("instructionPointerReference", Value::from(format!("{:#06x}", addr)))
+            ];
+            match self.source(addr) {
+                Some((source, line)) => {
+                    frame.push(("source", source));
+                    frame.push(("line", Value::from(line as i64)));
+                    frame.push(("column", Value::from(1)));
+                },
+                None => {
+                    frame.push(("line", Value::from(0)));
+                    frame.push(("column", Value::from(0)));
+                }
+            }
+            Value::object(frame)
+        }).collect();
+        Value::object(vec![
+            ("totalFrames", Value::from(frames.len() as i64)),
+            ("stackFrames", Value::from(frames))
+        ])
+    }
+
+    fn variables(&self, arguments: &Value) -> Value {
+        let machine = self.machine.as_ref().unwrap();
+        let mut variables = Vec::new();
+        if arguments.get("variablesReference").as_i64() == Some(REGISTERS) {
+            let registers = (0..16).map(Register::V)
+                .chain([Register::I, Register::Pc, Register::Sp, Register::Dt, Register::St]);
+            for register in registers {
+                let mut variable = vec![
+                    ("name", Value::from(register.to_string())),
+                    ("value", Value::from(value(register, machine))),
+                    ("variablesReference", Value::from(0))
+                ];
+                if let Register::I | Register::Pc = register {
+                    variable.push(("memoryReference", Value::from(format!("{:#06x}", register.read(machine)))));
+                }
+                variables.push(Value::object(variable));
+            }
+        }
+        Value::object(vec![("variables", Value::from(variables))])
+    }
+
+    fn set_variable(&mut self, arguments: &Value) -> Result<Value, String> {
+        let name = arguments.get("name").as_str().unwrap_or("");
+        let register = Register::parse(name).ok_or(format!("unknown register '{}'", name))?;
+        let text = arguments.get("value").as_str().unwrap_or("");
+        let number = debugger::number(text.trim()).ok_or(format!("invalid value '{}'", text))?;
+        let machine = self.machine.as_mut().unwrap();
+        register.write(machine, number)?;
+        Ok(Value::object(vec![("value", Value::from(value(register, machine)))]))
+    }
+
+    fn read_memory(&self, arguments: &Value) -> Result<Value, String> {
+        let memory = self.machine.as_ref().unwrap().cpu.memory();
+        let start = address(arguments)?;
+        let count = arguments.get("count").as_i64().unwrap_or(0).max(0) as usize;
+        let end = (start + count).min(memory.len());
+        let data = memory.get(start..end).unwrap_or(&[]);
+        Ok(Value::object(vec![
+            ("address", Value::from(format!("{:#06x}", start))),
+            ("data", Value::from(base64(data))),
+            ("unreadableBytes", Value::from((count - data.len()) as i64))
+        ]))
+    }
+
+    /// Disassembles from the address, assuming 2 byte instructions when
+    /// counting back.
+    fn disassemble(&self, arguments: &Value) -> Result<Value, String> {
+        let cpu = &self.machine.as_ref().unwrap().cpu;
+        let offset = arguments.get("instructionOffset").as_i64().unwrap_or(0);
+        let count = arguments.get("instructionCount").as_i64().unwrap_or(0).max(0);
+        let mut addr = address(arguments)? as i64 + offset * 2;
+        let mut instructions = Vec::new();
+        for _ in 0..count {
+            let mut instruction = vec![("address", Value::from(format!("{:#06x}", addr.max(0))))];
+            let decoded = u16::try_from(addr).ok()
+                .and_then(|at| cpu.instruction_at(at).ok().map(|instruction| (at, instruction)));
+            match decoded {
+                Some((at, decoded)) => {
+                    let bytes: Vec<String> = decoded.to_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+                    instruction.push(("instructionBytes", Value::from(bytes.join(" "))));
+                    instruction.push(("instruction", Value::from(decoded.to_string())));
+                    if let Some((source, line)) = self.source(at) {
+                        instruction.push(("location", source));
+                        instruction.push(("line", Value::from(line as i64)));
+                    }
+                    addr += decoded.size() as i64;
+                },
+                None => {
+                    instruction.push(("instruction", Value::from("??")));
+                    instruction.push(("presentationHint", Value::from("invalid")));
+                    addr += 2;
+                }
+            }
+            instructions.push(Value::object(instruction));
+        }
+        Ok(Value::object(vec![("instructions", Value::from(instructions))]))
+    }
+
+    /// Registers evaluate to their value, for hovers and watches. In the
+    /// debug console, anything else is a console command.
+    fn evaluate(&mut self, arguments: &Value) -> Result<Value, String> {
+        let expression = arguments.get("expression").as_str().unwrap_or("").trim();
+        let machine = self.machine.as_mut().unwrap();
+        let result = match Register::parse(expression) {
+            Some(register) => value(register, machine),
+            None if arguments.get("context").as_str() == Some("repl") => {
+                match self.console.execute(&mut self.debugger, machine, expression)? {
+                    Action::Print(text) => text,
+                    Action::Run => {
+                        self.run();
+                        String::from("running")
+                    },
+                    Action::Quit => return Err(String::from("use the editor to stop debugging"))
+                }
+            },
+            None => return Err(format!("unknown register '{}'", expression))
+        };
+        Ok(Value::object(vec![("result", Value::from(result)), ("variablesReference", Value::from(0))]))
+    }
+
+    fn report(&mut self, stop: Stop) {
+        let description = stop.to_string();
+        match stop {
+            Stop::Step | Stop::Reached(_) | Stop::Returned(_) => self.stopped("step", &description, None),
+            Stop::Breakpoint { id, .. } => self.stopped("breakpoint", &description, Some(id)),
+            Stop::Watchpoint { id, .. } | Stop::Watch { id, .. } => self.stopped("data breakpoint", &description, Some(id)),
+            Stop::Fault(_) => self.stopped("exception", &description, None),
+            Stop::Halted => self.stopped("pause", &description, None)
+        }
+    }
+
+    fn stopped(&mut self, reason: &str, description: &str, breakpoint: Option<usize>) {
+        let mut body = vec![
+            ("reason", Value::from(reason)),
+            ("description", Value::from(description)),
+            ("threadId", Value::from(THREAD)),
+            ("allThreadsStopped", Value::from(true))
+        ];
+        if reason == "exception" {
+            body.push(("text", Value::from(description)));
+        }
+        if let Some(id) = breakpoint {
+            body.push(("hitBreakpointIds", Value::from(vec![Value::from(id as i64)])));
+        }
+        self.event("stopped", Value::object(body));
+    }
+
+    fn output(&mut self, text: &str) {
+        let body = Value::object(vec![("category", Value::from("stderr")), ("output", Value::from(format!("{}\n", text)))]);
+        self.event("output", body);
+    }
+
+    /// Queues an event, sent by `flush`.
+    fn event(&mut self, event: &str, body: Value) {
+        let mut message = vec![("type", Value::from("event")), ("event", Value::from(event))];
+        if !body.is_null() {
+            message.push(("body", body));
+        }
+        self.events.push(Value::object(message));
+    }
+
+    fn flush(&mut self) {
+        for event in std::mem::take(&mut self.events) {
+            self.send(event);
+        }
+    }
+
+    fn send(&mut self, message: Value) {
+        self.seq += 1;
+        let mut message = match message {
+            Value::Object(members) => members,
+            _ => return
+        };
+        message.insert(0, (String::from("seq"), Value::from(self.seq)));
+        let text = Value::Object(message).to_string();
+        // a client that has gone away will disconnect, ending the session
+        let _ = write!(self.out, "Content-Length: {}\r\n\r\n{}", text.len(), text)
+            .and_then(|_| self.out.flush());
+    }
+
+}
+
+fn capabilities() -> Value {
+    Value::object(vec![
+        ("supportsConfigurationDoneRequest", Value::from(true)),
+        ("supportsConditionalBreakpoints", Value::from(true)),
+        ("supportsEvaluateForHovers", Value::from(true)),
+        ("supportsSetVariable", Value::from(true)),
+        ("supportsReadMemoryRequest", Value::from(true)),
+        ("supportsDisassembleRequest", Value::from(true)),
+        ("supportsTerminateRequest", Value::from(true))
+    ])
+}
+
+fn value(register: Register, machine: &Machine) -> String {
+    match register {
+        Register::V(_) | Register::Dt | Register::St => format!("{:#04x}", register.read(machine)),
+        Register::Sp => register.read(machine).to_string(),
+        Register::I | Register::Pc => format!("{:#06x}", register.read(machine))
+    }
+}
+
+/// The address given by a memory reference and offset.
+fn address(arguments: &Value) -> Result<usize, String> {
+    let reference = arguments.get("memoryReference").as_str().unwrap_or("");
+    let base = debugger::number(reference).ok_or(format!("invalid memory reference '{}'", reference))?;
+    let addr = base as i64 + arguments.get("offset").as_i64().unwrap_or(0);
+    usize::try_from(addr).map_err(|_| format!("address {} is out of range", addr))
+}
+
+/// A disassembly of the ROM, one instruction per line, and the source map
+/// for it.
+fn listing(machine: &Machine) -> (String, SourceMap) {
+    let cpu = &machine.cpu;
+    let mut text = String::new();
+    let mut map = SourceMap::new();
+    let mut addr = 0x200;
+    let end = 0x200 + machine.rom().len();
+    let mut line = 1;
+    while (addr as usize) < end {
+        let instruction = match cpu.instruction_at(addr) {
+            Ok(instruction) => instruction,
+            Err(_) => break
+        };
+        let opcode: String = instruction.to_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+        text.push_str(&format!("{:#06x}  {:<8}  {}\n", addr, opcode, instruction));
+        map.add(addr, Path::new(""), line);
+        line += 1;
+        addr = addr.wrapping_add(instruction.size());
+    }
+    (text, map)
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (n, &byte)| bits | ((byte as u32) << (16 - 8 * n)));
+        for n in 0..4 {
+            match n <= chunk.len() {
+                true => text.push(ALPHABET[((bits >> (18 - 6 * n)) & 0x3f) as usize] as char),
+                false => text.push('=')
+            }
+        }
+    }
+    text
+}
+
+/// Reads the body of a `Content-Length` framed message, or None at the end
+/// of input.
+pub fn read_message(input: &mut dyn BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let length = length.unwrap_or(0);
+    if length > MAX_MESSAGE {
+        let error = format!("message of {} bytes is larger than {}", length, MAX_MESSAGE);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(String::from_utf8_lossy(&body).into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// A sink the test can read back after the server is done with it.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // 0200: ld v0, 0     0202: call 020a    0204: add v0, 1
+    // 0206: ld [i], v0   0208: jp 0202      020a: ld i, 0x300
+    // 020c: ret
+    const ROM: [u8; 14] = [
+        0x60, 0x00, 0x22, 0x0a, 0x70, 0x01, 0xf0, 0x55, 0x12, 0x02, 0xa3, 0x00, 0x00, 0xee
+    ];
+
+    fn server() -> (Server, Shared) {
+        let out = Shared::default();
+        let launcher = Box::new(|_: &Value| {
+            let mut machine = Machine::new();
+            machine.load(&ROM);
+            Ok(machine)
+        });
+        (Server::new(launcher, Box::new(out.clone())), out)
+    }
+
+    /// Sends a request, returning the messages written in reply.
+    fn request(server: &mut Server, out: &Shared, command: &str, arguments: &str) -> Vec<Value> {
+        let text = format!(r#"{{"seq":1,"type":"request","command":"{}","arguments":{}}}"#, command, arguments);
+        server.handle(&json::parse(&text).unwrap());
+        messages(out)
+    }
+
+    fn messages(out: &Shared) -> Vec<Value> {
+        let data = out.0.replace(Vec::new());
+        let mut input = &data[..];
+        let mut messages = Vec::new();
+        while let Some(text) = read_message(&mut input).unwrap() {
+            messages.push(json::parse(&text).unwrap());
+        }
+        messages
+    }
+
+    #[test]
+    fn session() {
+        let (mut server, out) = server();
+        let replies = request(&mut server, &out, "initialize", "{}");
+        assert_eq!(replies[0].get("body").get("supportsConfigurationDoneRequest"), &Value::Bool(true));
+        let replies = request(&mut server, &out, "launch", r#"{"program":"test.ch8"}"#);
+        assert_eq!(replies[1].get("event").as_str(), Some("initialized"));
+
+        let source = request(&mut server, &out, "source", r#"{"sourceReference":1}"#);
+        let listing = source[0].get("body").get("content").as_str().unwrap().to_string();
+        assert_eq!(listing.lines().nth(5), Some("0x020a  a300      ld i, 0x300"));
+
+        // line 7 is the ret in the subroutine
+        let replies = request(&mut server, &out, "setBreakpoints",
+            r#"{"source":{"sourceReference":1},"breakpoints":[{"line":7},{"line":9}]}"#);
+        let breakpoints = replies[0].get("body").get("breakpoints").as_array().unwrap();
+        assert_eq!(breakpoints[0].get("verified"), &Value::Bool(true));
+        assert_eq!(breakpoints[1].get("verified"), &Value::Bool(false));
+
+        request(&mut server, &out, "configurationDone", "{}");
+        server.advance(1);
+        let events = messages(&out);
+        assert_eq!(events[0].get("body").get("reason").as_str(), Some("breakpoint"));
+        assert_eq!(events[0].get("body").get("description").as_str(), Some("breakpoint 1 at 0x020c"));
+
+        let trace = request(&mut server, &out, "stackTrace", r#"{"threadId":1}"#);
+        let frames = trace[0].get("body").get("stackFrames").as_array().unwrap();
+        let lines: Vec<i64> = frames.iter().map(|frame| frame.get("line").as_i64().unwrap()).collect();
+        assert_eq!(lines, [7, 2]);
+        assert_eq!(frames[1].get("name").as_str(), Some("0x0202"));
+
+        request(&mut server, &out, "setVariable", r#"{"variablesReference":1,"name":"v0","value":"0x2a"}"#);
+        let variables = request(&mut server, &out, "variables", r#"{"variablesReference":1}"#);
+        let variables = variables[0].get("body").get("variables").as_array().unwrap();
+        assert_eq!(variables[0].get("value").as_str(), Some("0x2a"));
+        assert_eq!(variables[16].get("memoryReference").as_str(), Some("0x0300"));
+
+        let memory = request(&mut server, &out, "readMemory", r#"{"memoryReference":"0x0200","offset":2,"count":4}"#);
+        assert_eq!(memory[0].get("body").get("data").as_str(), Some("IgpwAQ=="));
+
+        request(&mut server, &out, "stepOut", r#"{"threadId":1}"#);
+        server.advance(1);
+        let events = messages(&out);
+        assert_eq!(events[0].get("body").get("description").as_str(), Some("returned to 0x0204"));
+
+        let result = request(&mut server, &out, "evaluate", r#"{"expression":"x/2 0x300","context":"repl"}"#);
+        assert_eq!(result[0].get("body").get("result").as_str(), Some("0x0300: 00 00"));
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        let mut input = &b"Content-Length: 2\r\n\r\n{}Content-Length: 4\r\n\r\nnull"[..];
+        assert_eq!(read_message(&mut input).unwrap(), Some(String::from("{}")));
+        assert_eq!(read_message(&mut input).unwrap(), Some(String::from("null")));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+        let mut input = &b"Content-Length: 18446744073709551615\r\n\r\n{}"[..];
+        assert_eq!(read_message(&mut input).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+}
@@ -0,0 +1,617 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::fault::CpuFault;
+use crate::instruction::Instruction;
+use crate::machine::Machine;
+
+/// A register that can be watched or compared in a condition.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Register {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St
+}
+
+impl Register {
+
+    /// Parses `v0`-`vf`, `i`, `pc`, `sp`, `dt` or `st`.
+    pub fn parse(name: &str) -> Option<Register> {
+        match name {
+            "i" => Some(Register::I),
+            "pc" => Some(Register::Pc),
+            "sp" => Some(Register::Sp),
+            "dt" => Some(Register::Dt),
+            "st" => Some(Register::St),
+            _ if name.len() == 2 => {
+                let x = name.strip_prefix('v')?;
+                u8::from_str_radix(x, 16).ok().map(Register::V)
+            },
+            _ => None
+        }
+    }
+
+    pub fn read(&self, machine: &Machine) -> u16 {
+        let cpu = &machine.cpu;
+        match *self {
+            Register::V(x) => cpu.v()[x as usize] as u16,
+            Register::I => cpu.i(),
+            Register::Pc => cpu.pc(),
+            Register::Sp => cpu.sp() as u16,
+            Register::Dt => cpu.dt() as u16,
+            Register::St => cpu.st() as u16
+        }
+    }
+
+    /// Sets the register to <value>, which must fit it. `sp` can't be set,
+    /// as the stack would be left inconsistent.
+    pub fn write(&self, machine: &mut Machine, value: u16) -> Result<(), String> {
+        let cpu = &mut machine.cpu;
+        let byte = || u8::try_from(value).map_err(|_| format!("{:#x} doesn't fit in {}", value, self));
+        match *self {
+            Register::V(x) => cpu.set_v(x as usize, byte()?),
+            Register::I => cpu.set_i(value),
+            Register::Pc => cpu.set_pc(value),
+            Register::Sp => return Err(String::from("sp can't be set")),
+            Register::Dt => cpu.set_dt(byte()?),
+            Register::St => cpu.set_st(byte()?)
+        }
+        Ok(())
+    }
+
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "v{:x}", x),
+            Register::I => write!(f, "i"),
+            Register::Pc => write!(f, "pc"),
+            Register::Sp => write!(f, "sp"),
+            Register::Dt => write!(f, "dt"),
+            Register::St => write!(f, "st")
+        }
+    }
+}
+
+/// One side of a condition.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    Register(Register),
+    /// The byte at an address, written `[0x300]`.
+    Memory(u16),
+    Value(u16)
+}
+
+impl Operand {
+
+    fn parse(text: &str) -> Option<Operand> {
+        if let Some(addr) = text.strip_prefix('[').and_then(|text| text.strip_suffix(']')) {
+            return number(addr).map(Operand::Memory);
+        }
+        Register::parse(text).map(Operand::Register)
+            .or_else(|| number(text).map(Operand::Value))
+    }
+
+    fn read(&self, machine: &Machine) -> u16 {
+        match *self {
+            Operand::Register(register) => register.read(machine),
+            Operand::Memory(addr) => machine.cpu.memory().get(addr as usize).map_or(0, |&byte| byte as u16),
+            Operand::Value(value) => value
+        }
+    }
+
+}
+
+/// How a condition compares its operands.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Le,
+    Ge,
+    Lt,
+    Gt
+}
+
+impl Comparison {
+
+    /// Two-character operators first, so `<=` isn't taken for `<`.
+    const ALL: [Comparison; 6] = [
+        Comparison::Eq, Comparison::Ne, Comparison::Le, Comparison::Ge, Comparison::Lt, Comparison::Gt
+    ];
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Le => "<=",
+            Comparison::Ge => ">=",
+            Comparison::Lt => "<",
+            Comparison::Gt => ">"
+        }
+    }
+
+}
+
+/// A comparison guarding a breakpoint, such as `v3 == 0x10`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Condition {
+    pub left: Operand,
+    pub comparison: Comparison,
+    pub right: Operand
+}
+
+impl Condition {
+
+    /// Parses `<operand> <comparison> <operand>`, where an operand is a
+    /// register, `[<address>]` or a number, and the comparison is one of
+    /// `==`, `!=`, `<`, `<=`, `>` or `>=`.
+    pub fn parse(text: &str) -> Result<Condition, String> {
+        let error = || format!("expected a condition such as 'v3 == 0x10', got '{}'", text);
+        let (comparison, at) = Comparison::ALL.iter()
+            .find_map(|&comparison| text.find(comparison.symbol()).map(|at| (comparison, at)))
+            .ok_or_else(error)?;
+        let left = Operand::parse(text[..at].trim()).ok_or_else(error)?;
+        let right = Operand::parse(text[at + comparison.symbol().len()..].trim()).ok_or_else(error)?;
+        Ok(Condition { left, comparison, right })
+    }
+
+    pub fn eval(&self, machine: &Machine) -> bool {
+        let (left, right) = (self.left.read(machine), self.right.read(machine));
+        match self.comparison {
+            Comparison::Eq => left == right,
+            Comparison::Ne => left != right,
+            Comparison::Le => left <= right,
+            Comparison::Ge => left >= right,
+            Comparison::Lt => left < right,
+            Comparison::Gt => left > right
+        }
+    }
+
+}
+
+/// Parses a number in decimal or 0x-prefixed hex.
+pub fn number(text: &str) -> Option<u16> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok()
+    }
+}
+
+/// Which memory accesses a watchpoint stops on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    /// Reads and writes, for watchpoints.
+    Any
+}
+
+impl Access {
+
+    fn matches(&self, access: Access) -> bool {
+        *self == Access::Any || *self == access
+    }
+
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+            Access::Any => write!(f, "access")
+        }
+    }
+}
+
+/// What a breakpoint, watchpoint or watch stops on.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Trigger {
+    /// Reaching an address, when the condition holds.
+    Breakpoint { addr: u16, condition: Option<Condition> },
+    /// Accessing any of <len> bytes from <addr>.
+    Watchpoint { addr: u16, len: u16, access: Access },
+    /// A register changing value.
+    Watch(Register)
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trigger::Breakpoint { addr, condition } => {
+                write!(f, "breakpoint at {:#06x}", addr)?;
+                if let Some(condition) = condition {
+                    write!(f, " if {} {} {}", operand(&condition.left), condition.comparison.symbol(), operand(&condition.right))?;
+                }
+                Ok(())
+            },
+            Trigger::Watchpoint { addr, len: 1, access } => write!(f, "{} watchpoint at {:#06x}", access, addr),
+            Trigger::Watchpoint { addr, len, access } => {
+                write!(f, "{} watchpoint at {:#06x}-{:#06x}", access, addr, addr + len - 1)
+            },
+            Trigger::Watch(register) => write!(f, "watch on {}", register)
+        }
+    }
+}
+
+fn operand(operand: &Operand) -> String {
+    match operand {
+        Operand::Register(register) => register.to_string(),
+        Operand::Memory(addr) => format!("[{:#06x}]", addr),
+        Operand::Value(value) => format!("{:#x}", value)
+    }
+}
+
+/// Why execution stopped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stop {
+    /// A step, or a step over a `call`, finished.
+    Step,
+    /// Stopped before the instruction at a breakpoint.
+    Breakpoint { id: usize, addr: u16 },
+    /// Stopped after an instruction accessed watched memory.
+    Watchpoint { id: usize, addr: u16, access: Access },
+    /// Stopped after an instruction changed a watched register.
+    Watch { id: usize, register: Register, old: u16, new: u16 },
+    /// `run_to` reached its address.
+    Reached(u16),
+    /// `step_out` returned from the current subroutine.
+    Returned(u16),
+    Fault(CpuFault),
+    /// The program exited.
+    Halted
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Step => write!(f, "stepped"),
+            Stop::Breakpoint { id, addr } => write!(f, "breakpoint {} at {:#06x}", id, addr),
+            Stop::Watchpoint { id, addr, access } => write!(f, "watchpoint {}: {} at {:#06x}", id, access, addr),
+            Stop::Watch { id, register, old, new } => {
+                write!(f, "watch {}: {} changed from {:#x} to {:#x}", id, register, old, new)
+            },
+            Stop::Reached(addr) => write!(f, "reached {:#06x}", addr),
+            Stop::Returned(addr) => write!(f, "returned to {:#06x}", addr),
+            Stop::Fault(fault) => write!(f, "{}", fault),
+            Stop::Halted => write!(f, "halted")
+        }
+    }
+}
+
+/// What running does until something else stops it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Continue,
+    Step,
+    /// Run until execution is back at <addr> with the stack at depth <sp>.
+    StepOver { addr: u16, sp: u8 },
+    /// Run until the stack is shallower than <sp>.
+    StepOut { sp: u8 },
+    RunTo(u16)
+}
+
+/// Runs a machine an instruction at a time, stopping at breakpoints,
+/// watchpoints and register watches, and implementing step, step over,
+/// step out and run to.
+///
+/// Frames are counted in cycles, so the timers tick after every
+/// <instructions_per_frame> instructions however execution is split up.
+pub struct Debugger {
+    triggers: Vec<(usize, Trigger)>,
+    next_id: usize,
+    mode: Mode,
+    /// Set by commands and breakpoint stops, so execution can carry on past
+    /// a breakpoint at the current address.
+    skip_breakpoint: bool,
+    cycle: u32
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
+
+impl Debugger {
+
+    pub fn new() -> Self {
+        Debugger {
+            triggers: Vec::new(),
+            next_id: 1,
+            mode: Mode::Continue,
+            skip_breakpoint: false,
+            cycle: 0
+        }
+    }
+
+    /// Adds a breakpoint, watchpoint or watch, returning its id.
+    pub fn add(&mut self, trigger: Trigger) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.triggers.push((id, trigger));
+        id
+    }
+
+    /// Removes the trigger with <id>, returning false if there isn't one.
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.triggers.len();
+        self.triggers.retain(|(other, _)| *other != id);
+        self.triggers.len() != len
+    }
+
+    /// The breakpoints, watchpoints and watches with their ids.
+    pub fn triggers(&self) -> &[(usize, Trigger)] {
+        &self.triggers
+    }
+
+    /// Runs freely until something stops execution.
+    pub fn resume(&mut self) {
+        self.command(Mode::Continue);
+    }
+
+    /// Stops after the next instruction.
+    pub fn step(&mut self) {
+        self.command(Mode::Step);
+    }
+
+    /// Steps, running a `call` through to its return.
+    pub fn step_over(&mut self, machine: &Machine) {
+        let cpu = &machine.cpu;
+        self.command(match cpu.instruction_at(cpu.pc()) {
+            Ok(instruction @ Instruction::Call(_)) => {
+                Mode::StepOver { addr: cpu.pc().wrapping_add(instruction.size()), sp: cpu.sp() }
+            },
+            _ => Mode::Step
+        });
+    }
+
+    /// Runs until the current subroutine returns.
+    pub fn step_out(&mut self, machine: &Machine) {
+        self.command(Mode::StepOut { sp: machine.cpu.sp() });
+    }
+
+    /// Runs until execution reaches <addr>.
+    pub fn run_to(&mut self, addr: u16) {
+        self.command(Mode::RunTo(addr));
+    }
+
+    fn command(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.skip_breakpoint = true;
+    }
+
+    /// Starts counting cycles from the start of a frame, after the machine
+    /// was reset or restored from a save state.
+    pub fn restart_frame(&mut self) {
+        self.cycle = 0;
+    }
+
+    /// Runs the rest of the current frame, ticking the timers at its end,
+    /// unless execution stops first.
+    pub fn frame(&mut self, machine: &mut Machine) -> Option<Stop> {
+        loop {
+            let stop = self.cycle(machine);
+            if stop.is_some() || self.cycle == 0 {
+                return stop;
+            }
+        }
+    }
+
+    /// Runs a single instruction, unless a breakpoint stops execution
+    /// before it, and reports why execution stopped, if it did.
+    pub fn cycle(&mut self, machine: &mut Machine) -> Option<Stop> {
+        if machine.halted() {
+            return Some(machine.fault().map_or(Stop::Halted, Stop::Fault));
+        }
+        let pc = machine.cpu.pc();
+        if !std::mem::take(&mut self.skip_breakpoint) {
+            if let Some(id) = self.breakpoint(machine, pc) {
+                self.skip_breakpoint = true;
+                return Some(Stop::Breakpoint { id, addr: pc });
+            }
+        }
+        let instruction = machine.cpu.instruction_at(pc).ok();
+        let access = instruction.and_then(|instruction| access(machine, &instruction));
+        let watched: Vec<(usize, Register, u16)> = self.triggers.iter()
+            .filter_map(|(id, trigger)| match trigger {
+                Trigger::Watch(register) => Some((*id, *register, register.read(machine))),
+                _ => None
+            })
+            .collect();
+
+        let result = machine.cycle();
+        self.cycle += 1;
+        if self.cycle >= machine.instructions_per_frame() {
+            machine.end_frame();
+            self.cycle = 0;
+        }
+        if let Err(fault) = result {
+            return Some(Stop::Fault(fault));
+        }
+        if machine.halted() {
+            return Some(machine.fault().map_or(Stop::Halted, Stop::Fault));
+        }
+
+        if let Some((addr, len, kind)) = access {
+            for (id, trigger) in self.triggers.iter() {
+                if let Trigger::Watchpoint { addr: start, len: watched, access } = *trigger {
+                    let first = addr.max(start);
+                    let end = (addr as u32 + len as u32).min(start as u32 + watched as u32);
+                    if access.matches(kind) && (first as u32) < end {
+                        self.mode = Mode::Continue;
+                        return Some(Stop::Watchpoint { id: *id, addr: first, access: kind });
+                    }
+                }
+            }
+        }
+        for (id, register, old) in watched {
+            let new = register.read(machine);
+            if new != old {
+                self.mode = Mode::Continue;
+                return Some(Stop::Watch { id, register, old, new });
+            }
+        }
+
+        let pc = machine.cpu.pc();
+        let sp = machine.cpu.sp();
+        let stop = match self.mode {
+            Mode::Continue => None,
+            Mode::Step => Some(Stop::Step),
+            Mode::StepOver { addr, sp: depth } if pc == addr && sp == depth => Some(Stop::Step),
+            Mode::StepOut { sp: depth } if sp < depth => Some(Stop::Returned(pc)),
+            Mode::RunTo(addr) if pc == addr => Some(Stop::Reached(addr)),
+            _ => None
+        };
+        if stop.is_some() {
+            self.mode = Mode::Continue;
+        }
+        stop
+    }
+
+    /// The first breakpoint at <pc> whose condition holds.
+    fn breakpoint(&self, machine: &Machine, pc: u16) -> Option<usize> {
+        self.triggers.iter().find_map(|(id, trigger)| match trigger {
+            Trigger::Breakpoint { addr, condition } if *addr == pc &&
+                condition.is_none_or(|condition| condition.eval(machine)) => Some(*id),
+            _ => None
+        })
+    }
+
+}
+
+/// The memory <instruction> is about to read or write through <i>, as its
+/// address, length and kind of access.
+pub fn access(machine: &Machine, instruction: &Instruction) -> Option<(u16, u16, Access)> {
+    let i = machine.cpu.i();
+    let planes = machine.gpu.plane.count_ones() as u16;
+    let range = |x: u8, y: u8| (x as i16 - y as i16).unsigned_abs() + 1;
+    match *instruction {
+        Instruction::Drw { n: 0, .. } if planes > 0 => Some((i, 32 * planes, Access::Read)),
+        Instruction::Drw { n, .. } if planes > 0 => Some((i, n as u16 * planes, Access::Read)),
+        Instruction::Audio => Some((i, 16, Access::Read)),
+        Instruction::LdBVx(_) => Some((i, 3, Access::Write)),
+        Instruction::LdIVx(x) => Some((i, x as u16 + 1, Access::Write)),
+        Instruction::LdVxI(x) => Some((i, x as u16 + 1, Access::Read)),
+        Instruction::Save { x, y } => Some((i, range(x, y), Access::Write)),
+        Instruction::Load { x, y } => Some((i, range(x, y), Access::Read)),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0200: ld v0, 0     0202: call 020a    0204: add v0, 1
+    // 0206: ld [i], v0   0208: jp 0202      020a: ld i, 0x300
+    // 020c: ret
+    const ROM: [u8; 14] = [
+        0x60, 0x00, 0x22, 0x0a, 0x70, 0x01, 0xf0, 0x55, 0x12, 0x02, 0xa3, 0x00, 0x00, 0xee
+    ];
+
+    fn machine() -> Machine {
+        let mut machine = Machine::new();
+        machine.load(&ROM);
+        machine
+    }
+
+    #[test]
+    fn condition() {
+        let mut machine = machine();
+        let condition = Condition::parse("v0 >= 0x2").unwrap();
+        assert_eq!(condition.left, Operand::Register(Register::V(0)));
+        assert!(!condition.eval(&machine));
+        assert_eq!(Condition::parse("[0x200]==96").map(|condition| condition.eval(&machine)), Ok(true));
+        assert!(Condition::parse("v10 == 1").is_err());
+        assert!(Condition::parse("v1").is_err());
+
+        let mut debugger = Debugger::new();
+        let id = debugger.add(Trigger::Breakpoint { addr: 0x204, condition: Some(condition) });
+        assert_eq!(debugger.frame(&mut machine), None);
+        assert_eq!(debugger.frame(&mut machine), Some(Stop::Breakpoint { id, addr: 0x204 }));
+        assert_eq!(machine.cpu.v()[0], 2);
+    }
+
+    #[test]
+    fn breakpoint() {
+        let mut machine = machine();
+        let mut debugger = Debugger::new();
+        let id = debugger.add(Trigger::Breakpoint { addr: 0x20a, condition: None });
+        assert_eq!(debugger.frame(&mut machine), Some(Stop::Breakpoint { id, addr: 0x20a }));
+        assert_eq!(machine.cpu.pc(), 0x20a);
+        assert_eq!(machine.cpu.stack(), [0x204]);
+
+        // carrying on runs the instruction at the breakpoint
+        assert_eq!(debugger.cycle(&mut machine), None);
+        assert_eq!(machine.cpu.pc(), 0x20c);
+        assert!(debugger.remove(id));
+        assert!(!debugger.remove(id));
+
+        // stepping onto a breakpoint stops there, and stepping again leaves it
+        let id = debugger.add(Trigger::Breakpoint { addr: 0x204, condition: None });
+        debugger.step();
+        assert_eq!(debugger.cycle(&mut machine), Some(Stop::Step));
+        assert_eq!(debugger.cycle(&mut machine), Some(Stop::Breakpoint { id, addr: 0x204 }));
+        debugger.step();
+        assert_eq!(debugger.cycle(&mut machine), Some(Stop::Step));
+        assert_eq!(machine.cpu.pc(), 0x206);
+    }
+
+    #[test]
+    fn watchpoint() {
+        let mut machine = machine();
+        let mut debugger = Debugger::new();
+        debugger.add(Trigger::Watchpoint { addr: 0x2ff, len: 1, access: Access::Any });
+        let id = debugger.add(Trigger::Watchpoint { addr: 0x2fe, len: 3, access: Access::Write });
+        assert_eq!(debugger.frame(&mut machine), Some(Stop::Watchpoint { id, addr: 0x300, access: Access::Write }));
+        assert_eq!(machine.cpu.pc(), 0x208);
+        assert_eq!(machine.cpu.memory()[0x300], 1);
+    }
+
+    #[test]
+    fn watch() {
+        let mut machine = machine();
+        let mut debugger = Debugger::new();
+        let id = debugger.add(Trigger::Watch(Register::I));
+        assert_eq!(debugger.frame(&mut machine), Some(Stop::Watch { id, register: Register::I, old: 0, new: 0x300 }));
+        // i doesn't change again
+        assert_eq!(debugger.frame(&mut machine), None);
+    }
+
+    #[test]
+    fn stepping() {
+        let mut machine = machine();
+        let mut debugger = Debugger::new();
+        debugger.step();
+        assert_eq!(debugger.cycle(&mut machine), Some(Stop::Step));
+        debugger.step_over(&machine);
+        assert_eq!(debugger.frame(&mut machine), Some(Stop::Step));
+        assert_eq!((machine.cpu.pc(), machine.cpu.i()), (0x204, 0x300));
+
+        debugger.run_to(0x20a);
+        assert_eq!(debugger.frame(&mut machine), Some(Stop::Reached(0x20a)));
+        debugger.step_out(&machine);
+        assert_eq!(debugger.frame(&mut machine), Some(Stop::Returned(0x204)));
+        assert_eq!(debugger.frame(&mut machine), None);
+    }
+
+    #[test]
+    fn timers() {
+        // the timers tick once per frame's worth of cycles, however they're run
+        let mut machine = machine();
+        machine.set_instructions_per_frame(3);
+        let mut debugger = Debugger::new();
+        debugger.cycle(&mut machine);
+        debugger.cycle(&mut machine);
+        assert_eq!(machine.frame_count(), 0);
+        debugger.frame(&mut machine);
+        assert_eq!(machine.frame_count(), 1);
+    }
+
+}
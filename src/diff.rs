@@ -0,0 +1,278 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::machine::Machine;
+use crate::trace::Record;
+
+/// Instructions of context shown around a divergence by default.
+pub const DEFAULT_CONTEXT: usize = 8;
+
+/// A machine run instruction by instruction, feeding it recorded inputs.
+pub struct Run {
+    pub machine: Machine,
+    inputs: Vec<u16>,
+    frame: usize,
+    cycle: u32
+}
+
+impl Run {
+
+    /// Runs <machine> from its current state, holding <inputs>[n] during
+    /// frame n, until the inputs run out.
+    pub fn new(machine: Machine, inputs: Vec<u16>) -> Self {
+        Run { machine, inputs, frame: 0, cycle: 0 }
+    }
+
+    fn step(&mut self) -> Step {
+        if self.cycle == self.machine.instructions_per_frame() {
+            self.machine.end_frame();
+            self.frame += 1;
+            self.cycle = 0;
+        }
+        let input = match self.inputs.get(self.frame) {
+            Some(&input) => input,
+            None => return Step::Finished
+        };
+        if self.machine.halted() {
+            return Step::Stopped;
+        }
+        if self.cycle == 0 {
+            self.machine.keypad.set_bits(input);
+        }
+        self.cycle += 1;
+        let pc = self.machine.cpu.pc();
+        let instruction = match self.machine.cpu.instruction_at(pc) {
+            Ok(instruction) => instruction,
+            Err(_) => return Step::Stopped
+        };
+        let before = *self.machine.cpu.v();
+        if self.machine.cycle().is_err() {
+            return Step::Stopped;
+        }
+        let cpu = &self.machine.cpu;
+        Step::Ran(Record::new(self.machine.frame_count(), pc, instruction, &before, cpu.v(), cpu.i()))
+    }
+
+}
+
+/// One side of a comparison.
+pub enum Side {
+    /// A live run, whose memory is compared as well as its trace.
+    Run(Box<Run>),
+    /// A trace recorded earlier, perhaps by another emulator.
+    Trace(std::vec::IntoIter<Record>)
+}
+
+impl Side {
+
+    fn step(&mut self) -> Step {
+        match self {
+            Side::Run(run) => run.step(),
+            Side::Trace(records) => records.next().map_or(Step::Finished, Step::Ran)
+        }
+    }
+
+    fn memory(&self) -> Option<&[u8]> {
+        match self {
+            Side::Run(run) => Some(run.machine.cpu.memory()),
+            Side::Trace(_) => None
+        }
+    }
+
+}
+
+enum Step {
+    Ran(Record),
+    /// The machine halted or faulted.
+    Stopped,
+    /// The inputs or trace ran out.
+    Finished
+}
+
+/// Where two runs first differed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    /// The number of instructions both sides ran identically first.
+    pub index: u64,
+    /// The identical instructions leading up to the divergence.
+    pub before: Vec<Record>,
+    /// The first differing instruction on each side and those following
+    /// it, empty for a side that had stopped.
+    pub a: Vec<Record>,
+    pub b: Vec<Record>,
+    /// What differed, such as `v3 04->05 vs unchanged`.
+    pub differences: Vec<String>
+}
+
+/// Runs <a> and <b> in lockstep until the first instruction where the
+/// address, opcode, registers, <i> or memory differ, keeping <context>
+/// instructions either side of it. Returns None if the sides agree until
+/// either one finishes.
+pub fn diff(a: &mut Side, b: &mut Side, context: usize) -> Option<Divergence> {
+    let mut before = VecDeque::with_capacity(context + 1);
+    let mut index = 0;
+    loop {
+        let (first, second) = match (a.step(), b.step()) {
+            (Step::Finished, _) | (_, Step::Finished) | (Step::Stopped, Step::Stopped) => return None,
+            (Step::Ran(first), Step::Ran(second)) => (Some(first), Some(second)),
+            (Step::Ran(first), Step::Stopped) => (Some(first), None),
+            (Step::Stopped, Step::Ran(second)) => (None, Some(second))
+        };
+        let differences = compare(first.as_ref(), second.as_ref(), a.memory(), b.memory());
+        if differences.is_empty() {
+            before.push_back(first.unwrap());
+            if before.len() > context {
+                before.pop_front();
+            }
+            index += 1;
+            continue;
+        }
+        return Some(Divergence {
+            index,
+            before: before.into(),
+            a: follow(a, first, context),
+            b: follow(b, second, context),
+            differences
+        });
+    }
+}
+
+/// <first> followed by up to <context> more instructions from <side>.
+fn follow(side: &mut Side, first: Option<Record>, context: usize) -> Vec<Record> {
+    let mut records: Vec<Record> = first.into_iter().collect();
+    while !records.is_empty() && records.len() <= context {
+        match side.step() {
+            Step::Ran(record) => records.push(record),
+            _ => break
+        }
+    }
+    records
+}
+
+fn compare(a: Option<&Record>, b: Option<&Record>, a_memory: Option<&[u8]>, b_memory: Option<&[u8]>) -> Vec<String> {
+    let (a, b) = match (a, b) {
+        (Some(a), Some(b)) => (a, b),
+        (None, _) => return vec![String::from("a stopped")],
+        (_, None) => return vec![String::from("b stopped")]
+    };
+    let mut differences = Vec::new();
+    if a.frame != b.frame {
+        differences.push(format!("frame {} vs {}", a.frame, b.frame));
+    }
+    if a.pc != b.pc {
+        differences.push(format!("pc {:#06x} vs {:#06x}", a.pc, b.pc));
+    }
+    if a.instruction != b.instruction {
+        differences.push(format!("{} vs {}", a.instruction, b.instruction));
+    }
+    for register in 0..16 {
+        let change = |record: &Record| {
+            record.deltas.iter()
+                .find(|delta| delta.register == register)
+                .map_or(String::from("unchanged"), |delta| format!("{:02x}->{:02x}", delta.old, delta.new))
+        };
+        let (a_change, b_change) = (change(a), change(b));
+        if a_change != b_change {
+            differences.push(format!("v{:x} {} vs {}", register, a_change, b_change));
+        }
+    }
+    if a.i != b.i {
+        differences.push(format!("i {:#06x} vs {:#06x}", a.i, b.i));
+    }
+    if let (Some(a_memory), Some(b_memory)) = (a_memory, b_memory) {
+        let mut addresses = (0..a_memory.len().min(b_memory.len())).filter(|&addr| a_memory[addr] != b_memory[addr]);
+        if let Some(addr) = addresses.next() {
+            differences.push(format!("memory at {:#06x} {:02x} vs {:02x}, {} bytes differ in all",
+                addr, a_memory[addr], b_memory[addr], 1 + addresses.count()));
+        }
+    }
+    differences
+}
+
+/// A report listing the differences, then the instructions leading up to
+/// the divergence and the way each side went from there.
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "diverged after {} matching instructions:", self.index)?;
+        for difference in self.differences.iter() {
+            writeln!(f, "  {}", difference)?;
+        }
+        writeln!(f)?;
+        for record in self.before.iter() {
+            writeln!(f, "   {}", record)?;
+        }
+        for (name, records) in [("a", &self.a), ("b", &self.b)] {
+            match records.split_first() {
+                Some((first, rest)) => {
+                    writeln!(f, "{}> {}", name, first)?;
+                    for record in rest {
+                        writeln!(f, "   {}", record)?;
+                    }
+                },
+                None => writeln!(f, "{}> stopped", name)?
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    // 0200: ld v0, 5    0202: ld v1, 3    0204: shr v0, v1
+    // 0206: ld i, 0x300  0208: ld [i], v0   020a: jp 020a
+    const ROM: [u8; 12] = [0x60, 0x05, 0x61, 0x03, 0x80, 0x16, 0xa3, 0x00, 0xf0, 0x55, 0x12, 0x0a];
+
+    fn run(quirks: Quirks) -> Side {
+        let mut machine = Machine::new();
+        machine.set_quirks(quirks);
+        machine.load(&ROM);
+        Side::Run(Box::new(Run::new(machine, vec![0; 3])))
+    }
+
+    #[test]
+    fn same() {
+        assert_eq!(diff(&mut run(Quirks::schip()), &mut run(Quirks::schip()), 2), None);
+    }
+
+    #[test]
+    fn quirks() {
+        let divergence = diff(&mut run(Quirks::vip()), &mut run(Quirks::schip()), 2).unwrap();
+        assert_eq!(divergence.index, 2);
+        assert_eq!(divergence.differences, ["v0 05->01 vs 05->02"]);
+        assert_eq!(divergence.before.iter().map(|record| record.pc).collect::<Vec<_>>(), [0x200, 0x202]);
+        assert_eq!(divergence.a.len(), 3);
+        assert_eq!(divergence.b[0].pc, 0x204);
+    }
+
+    #[test]
+    fn trace() {
+        let mut records = Vec::new();
+        let mut vip = run(Quirks::vip());
+        while let Step::Ran(record) = vip.step() {
+            records.push(record);
+        }
+        assert_eq!(records.len(), 30);
+        let mut trace = Side::Trace(records.into_iter());
+        assert_eq!(diff(&mut trace, &mut run(Quirks::vip()), 0), None);
+
+        let mut vip = Quirks::vip();
+        vip.load_store_i = false;
+        let divergence = diff(&mut run(Quirks::vip()), &mut run(vip), 0).unwrap();
+        assert_eq!(divergence.index, 4);
+        assert_eq!(divergence.differences, ["i 0x0301 vs 0x0300"]);
+    }
+
+    #[test]
+    fn memory() {
+        let mut machine = Machine::new();
+        machine.load(&[&ROM[..], &[0xff, 0xff]].concat());
+        let mut data = Side::Run(Box::new(Run::new(machine, vec![0])));
+        let divergence = diff(&mut run(Quirks::schip()), &mut data, 0).unwrap();
+        assert_eq!(divergence.index, 0);
+        assert_eq!(divergence.differences, ["memory at 0x020c 00 vs ff, 2 bytes differ in all"]);
+    }
+
+}
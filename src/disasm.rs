@@ -0,0 +1,340 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::instruction::Instruction::{self, *};
+
+/// Where ROMs are loaded and start running.
+const START: u16 = 0x200;
+
+/// Data bytes per line.
+const BYTES_PER_LINE: usize = 8;
+
+/// The column hex comments start at.
+const COMMENT_COLUMN: usize = 28;
+
+/// The assembly language a listing is written in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Syntax {
+    /// Cowgod's mnemonics, as used by traces and the debugger.
+    Cowgod,
+    /// The Octo language.
+    Octo
+}
+
+impl Syntax {
+
+    pub fn parse(name: &str) -> Option<Syntax> {
+        match name {
+            "cowgod" => Some(Syntax::Cowgod),
+            "octo" => Some(Syntax::Octo),
+            _ => None
+        }
+    }
+
+}
+
+/// Why an address is labelled, in increasing order of precedence when it
+/// is more than one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Label {
+    Data,
+    Jump,
+    Subroutine
+}
+
+/// The instructions reachable from the start of a ROM, and the addresses
+/// they refer to.
+struct Analysis {
+    instructions: BTreeMap<u16, Instruction>,
+    /// The addresses of every byte belonging to an instruction.
+    code: BTreeSet<u16>,
+    labels: BTreeMap<u16, Label>
+}
+
+/// Decodes the instruction at <addr>, if the whole of it lies in <rom>.
+fn fetch(rom: &[u8], addr: u16) -> Option<Instruction> {
+    let at = addr.checked_sub(START)? as usize;
+    let word = |at: usize| Some(u16::from_be_bytes([*rom.get(at)?, *rom.get(at + 1)?]));
+    match Instruction::decode(word(at)?) {
+        LdILong(_) => word(at + 2).map(LdILong),
+        instruction => Some(instruction)
+    }
+}
+
+/// Follows control flow from the start of <rom>, so that only bytes
+/// execution can reach are treated as code. `jp v0` targets are assumed
+/// to be the start of a jump table.
+fn analyse(rom: &[u8]) -> Analysis {
+    let end = START as usize + rom.len();
+    let mut analysis = Analysis { instructions: BTreeMap::new(), code: BTreeSet::new(), labels: BTreeMap::new() };
+    let mut pending = vec![START];
+    while let Some(addr) = pending.pop() {
+        if analysis.code.contains(&addr) {
+            continue;
+        }
+        let instruction = match fetch(rom, addr) {
+            Some(Invalid(_)) | None => continue,
+            Some(instruction) => instruction
+        };
+        let next = addr.wrapping_add(instruction.size());
+        if (addr..next).any(|byte| analysis.code.contains(&byte)) {
+            // overlaps an instruction already found, so this is data
+            continue;
+        }
+        analysis.instructions.insert(addr, instruction);
+        analysis.code.extend(addr..next);
+        let mut label = |target: u16, label: Label| {
+            if (START as usize..end).contains(&(target as usize)) {
+                let entry = analysis.labels.entry(target).or_insert(label);
+                *entry = (*entry).max(label);
+                return true;
+            }
+            false
+        };
+        match instruction {
+            Jp(nnn) | JpV0(nnn) => {
+                if label(nnn, Label::Jump) {
+                    pending.push(nnn);
+                }
+            },
+            Call(nnn) => {
+                if label(nnn, Label::Subroutine) {
+                    pending.push(nnn);
+                }
+                pending.push(next);
+            },
+            Ret | Exit => (),
+            SeVxKk { .. } | SneVxKk { .. } | SeVxVy { .. } | SneVxVy { .. } | Skp(_) | Sknp(_) => {
+                pending.push(next);
+                // XO-CHIP skips the whole of a long load
+                let skipped = fetch(rom, next).map_or(2, |instruction| instruction.size());
+                pending.push(next.wrapping_add(skipped));
+            },
+            LdI(nnnn) | LdILong(nnnn) => {
+                label(nnnn, Label::Data);
+                pending.push(next);
+            },
+            _ => pending.push(next)
+        }
+    }
+    // a label inside an instruction can't be written, so it stays a number
+    let instructions = &analysis.instructions;
+    let code = &analysis.code;
+    analysis.labels.retain(|addr, _| !code.contains(addr) || instructions.contains_key(addr));
+    analysis
+}
+
+/// A listing of <rom>, loaded at 0x200, with labels for the targets of
+/// jumps, calls and `ld i`, data written as bytes and each line's address
+/// and bytes in a comment.
+pub fn disassemble(rom: &[u8], syntax: Syntax) -> String {
+    let analysis = analyse(rom);
+    let name = |addr: u16| -> Option<String> {
+        match analysis.labels.get(&addr)? {
+            _ if addr == START && syntax == Syntax::Octo => Some(String::from("main")),
+            Label::Subroutine => Some(format!("sub_{:03x}", addr)),
+            Label::Jump => Some(format!("label_{:03x}", addr)),
+            Label::Data => Some(format!("data_{:03x}", addr))
+        }
+    };
+    let comment = match syntax {
+        Syntax::Cowgod => ';',
+        Syntax::Octo => '#'
+    };
+    let mut text = String::new();
+    if syntax == Syntax::Octo && name(START).is_none() {
+        // Octo starts running at main
+        text.push_str(": main\n");
+    }
+    let end = START as usize + rom.len();
+    let mut addr = START as usize;
+    while addr < end {
+        if let Some(label) = name(addr as u16) {
+            match syntax {
+                Syntax::Cowgod => writeln!(text, "{}:", label).unwrap(),
+                Syntax::Octo => writeln!(text, ": {}", label).unwrap()
+            }
+        }
+        let (line, bytes) = match analysis.instructions.get(&(addr as u16)) {
+            Some(instruction) => {
+                let line = match syntax {
+                    Syntax::Cowgod => cowgod(instruction, &name),
+                    Syntax::Octo => octo(instruction, &name)
+                };
+                (line, instruction.size() as usize)
+            },
+            None => {
+                // data runs until the next instruction or label
+                let mut len = 1;
+                while len < BYTES_PER_LINE && addr + len < end &&
+                    !analysis.instructions.contains_key(&((addr + len) as u16)) &&
+                    name((addr + len) as u16).is_none() {
+                    len += 1;
+                }
+                let data = &rom[addr - START as usize..addr - START as usize + len];
+                let values: Vec<String> = data.iter().map(|byte| format!("{:#04x}", byte)).collect();
+                let line = match syntax {
+                    Syntax::Cowgod => format!("db {}", values.join(", ")),
+                    Syntax::Octo => values.join(" ")
+                };
+                (line, len)
+            }
+        };
+        let hex: String = rom[addr - START as usize..addr - START as usize + bytes].iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        writeln!(text, "    {:<width$} {} {:04x}  {}", line, comment, addr, hex, width = COMMENT_COLUMN - 5).unwrap();
+        addr += bytes;
+    }
+    text
+}
+
+/// The instruction in Cowgod syntax, with labels for the addresses that
+/// have them.
+fn cowgod(instruction: &Instruction, name: &dyn Fn(u16) -> Option<String>) -> String {
+    let target = |addr: u16| name(addr).unwrap_or_else(|| format!("{:#05x}", addr));
+    match *instruction {
+        Jp(nnn) => format!("jp {}", target(nnn)),
+        Call(nnn) => format!("call {}", target(nnn)),
+        LdI(nnn) => format!("ld i, {}", target(nnn)),
+        JpV0(nnn) => format!("jp v0, {}", target(nnn)),
+        LdILong(nnnn) => format!("ld i, long {}", name(nnnn).unwrap_or_else(|| format!("{:#06x}", nnnn))),
+        instruction => instruction.to_string()
+    }
+}
+
+/// The instruction in Octo syntax. Octo's skips are written as the
+/// condition under which the next instruction runs, so they read as the
+/// opposite of the opcode.
+fn octo(instruction: &Instruction, name: &dyn Fn(u16) -> Option<String>) -> String {
+    let target = |addr: u16| name(addr).unwrap_or_else(|| format!("{:#05x}", addr));
+    // instructions Octo has no words for are written as bytes
+    let bytes = || {
+        let bytes: Vec<String> = instruction.to_bytes().iter().map(|byte| format!("{:#04x}", byte)).collect();
+        bytes.join(" ")
+    };
+    match *instruction {
+        Sys(_) | Invalid(_) => bytes(),
+        Scd(n) => format!("scroll-down {}", n),
+        Cls => String::from("clear"),
+        Ret => String::from("return"),
+        Scr => String::from("scroll-right"),
+        Scl => String::from("scroll-left"),
+        Exit => String::from("exit"),
+        Low => String::from("lores"),
+        High => String::from("hires"),
+        Jp(nnn) => format!("jump {}", target(nnn)),
+        Call(nnn) => name(nnn).unwrap_or_else(bytes),
+        SeVxKk { x, kk } => format!("if v{:x} != {:#04x} then", x, kk),
+        SneVxKk { x, kk } => format!("if v{:x} == {:#04x} then", x, kk),
+        SeVxVy { x, y } => format!("if v{:x} != v{:x} then", x, y),
+        SneVxVy { x, y } => format!("if v{:x} == v{:x} then", x, y),
+        Skp(x) => format!("if v{:x} -key then", x),
+        Sknp(x) => format!("if v{:x} key then", x),
+        Save { x, y } => format!("save v{:x} - v{:x}", x, y),
+        Load { x, y } => format!("load v{:x} - v{:x}", x, y),
+        LdVxKk { x, kk } => format!("v{:x} := {:#04x}", x, kk),
+        AddVxKk { x, kk } => format!("v{:x} += {:#04x}", x, kk),
+        LdVxVy { x, y } => format!("v{:x} := v{:x}", x, y),
+        Or { x, y } => format!("v{:x} |= v{:x}", x, y),
+        And { x, y } => format!("v{:x} &= v{:x}", x, y),
+        Xor { x, y } => format!("v{:x} ^= v{:x}", x, y),
+        AddVxVy { x, y } => format!("v{:x} += v{:x}", x, y),
+        SubVxVy { x, y } => format!("v{:x} -= v{:x}", x, y),
+        Shr { x, y } => format!("v{:x} >>= v{:x}", x, y),
+        Subn { x, y } => format!("v{:x} =- v{:x}", x, y),
+        Shl { x, y } => format!("v{:x} <<= v{:x}", x, y),
+        LdI(nnn) => format!("i := {}", target(nnn)),
+        JpV0(nnn) => format!("jump0 {}", target(nnn)),
+        Rnd { x, kk } => format!("v{:x} := random {:#04x}", x, kk),
+        Drw { x, y, n } => format!("sprite v{:x} v{:x} {}", x, y, n),
+        LdILong(nnnn) => format!("i := long {}", name(nnnn).unwrap_or_else(|| format!("{:#06x}", nnnn))),
+        Plane(n) => format!("plane {}", n),
+        Audio => String::from("audio"),
+        LdVxDt(x) => format!("v{:x} := delay", x),
+        LdVxK(x) => format!("v{:x} := key", x),
+        LdDtVx(x) => format!("delay := v{:x}", x),
+        LdStVx(x) => format!("buzzer := v{:x}", x),
+        AddIVx(x) => format!("i += v{:x}", x),
+        LdFVx(x) => format!("i := hex v{:x}", x),
+        LdHfVx(x) => format!("i := bighex v{:x}", x),
+        LdBVx(x) => format!("bcd v{:x}", x),
+        Pitch(x) => format!("pitch := v{:x}", x),
+        LdIVx(x) => format!("save v{:x}", x),
+        LdVxI(x) => format!("load v{:x}", x),
+        LdRVx(x) => format!("saveflags v{:x}", x),
+        LdVxR(x) => format!("loadflags v{:x}", x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0200: ld i, 0x20e   0202: call 0x20a   0204: se v0, 1
+    // 0206: jp 0x204      0208: jp 0x208     020a: drw v0, v0, 2
+    // 020c: ret           020e: sprite data
+    const ROM: [u8; 17] = [
+        0xa2, 0x0e, 0x22, 0x0a, 0x30, 0x01, 0x12, 0x04, 0x12, 0x08, 0xd0, 0x02, 0x00, 0xee,
+        0x3c, 0x42, 0xff
+    ];
+
+    #[test]
+    fn analysis() {
+        let analysis = analyse(&ROM);
+        let code: Vec<u16> = analysis.instructions.keys().copied().collect();
+        assert_eq!(code, [0x200, 0x202, 0x204, 0x206, 0x208, 0x20a, 0x20c]);
+        let labels: Vec<(u16, Label)> = analysis.labels.into_iter().collect();
+        assert_eq!(labels, [(0x204, Label::Jump), (0x208, Label::Jump), (0x20a, Label::Subroutine), (0x20e, Label::Data)]);
+    }
+
+    #[test]
+    fn cowgod() {
+        assert_eq!(disassemble(&ROM, Syntax::Cowgod), "    \
+    ld i, data_20e          ; 0200  a20e
+    call sub_20a            ; 0202  220a
+label_204:
+    se v0, 0x01             ; 0204  3001
+    jp label_204            ; 0206  1204
+label_208:
+    jp label_208            ; 0208  1208
+sub_20a:
+    drw v0, v0, 0x2         ; 020a  d002
+    ret                     ; 020c  00ee
+data_20e:
+    db 0x3c, 0x42, 0xff     ; 020e  3c42ff
+");
+    }
+
+    #[test]
+    fn octo() {
+        let listing = disassemble(&ROM, Syntax::Octo);
+        let lines: Vec<&str> = listing.lines().map(|line| line.split('#').next().unwrap().trim_end()).collect();
+        assert_eq!(lines, [
+            ": main", "    i := data_20e", "    sub_20a", ": label_204", "    if v0 != 0x01 then",
+            "    jump label_204", ": label_208", "    jump label_208", ": sub_20a", "    sprite v0 v0 2",
+            "    return", ": data_20e", "    0x3c 0x42 0xff"
+        ]);
+    }
+
+    #[test]
+    fn data() {
+        // a jump over data, a label inside a long load and an odd byte
+        let rom = [0xf0, 0x00, 0x02, 0x02, 0x12, 0x08, 0x01, 0x02, 0x12, 0x08, 0x05];
+        let listing = disassemble(&rom, Syntax::Cowgod);
+        assert!(listing.starts_with("    ld i, long 0x0202       ; 0200  f0000202\n"));
+        assert!(listing.contains("    db 0x01, 0x02           ; 0206  0102\n"));
+        assert!(listing.ends_with("    db 0x05                 ; 020a  05\n"));
+    }
+
+    #[test]
+    fn wide_lines() {
+        // a full line of data is wider than the comment column
+        let rom = [0x12, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+        let listing = disassemble(&rom, Syntax::Cowgod);
+        assert!(listing.contains("    db 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08 ; 0202  0102030405060708\n"));
+        let listing = disassemble(&rom, Syntax::Octo);
+        assert!(listing.contains("    0x01 0x02 0x03 0x04 0x05 0x06 0x07 0x08 # 0202  0102030405060708\n"));
+    }
+
+}
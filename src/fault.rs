@@ -0,0 +1,76 @@
+use std::fmt;
+
+/// A condition the interpreter cannot execute past, reported by `Cpu::cycle`
+/// instead of panicking.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CpuFault {
+    /// `call` with all 16 stack entries in use.
+    StackOverflow,
+    /// `ret` with an empty stack.
+    StackUnderflow,
+    /// An opcode that no supported platform defines.
+    InvalidOpcode(u16),
+    /// An access that runs past the end of memory, at the given address.
+    MemoryOutOfBounds(usize)
+}
+
+impl fmt::Display for CpuFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuFault::StackOverflow => write!(f, "stack overflow"),
+            CpuFault::StackUnderflow => write!(f, "stack underflow"),
+            CpuFault::InvalidOpcode(opcode) => write!(f, "invalid opcode {:#06x}", opcode),
+            CpuFault::MemoryOutOfBounds(addr) => write!(f, "memory access out of bounds at {:#06x}", addr)
+        }
+    }
+}
+
+impl std::error::Error for CpuFault {}
+
+/// What to do when the cpu faults.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FaultAction {
+    /// Stop the cpu until it is reset.
+    Halt,
+    /// Carry on with the next instruction.
+    Ignore,
+    /// Pause execution and dump the cpu state for inspection.
+    Break
+}
+
+/// The action taken for each kind of fault.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FaultPolicy {
+    pub stack_overflow: FaultAction,
+    pub stack_underflow: FaultAction,
+    pub invalid_opcode: FaultAction,
+    pub memory_out_of_bounds: FaultAction
+}
+
+impl FaultPolicy {
+
+    pub fn all(action: FaultAction) -> Self {
+        FaultPolicy {
+            stack_overflow: action,
+            stack_underflow: action,
+            invalid_opcode: action,
+            memory_out_of_bounds: action
+        }
+    }
+
+    pub fn action(&self, fault: &CpuFault) -> FaultAction {
+        match fault {
+            CpuFault::StackOverflow => self.stack_overflow,
+            CpuFault::StackUnderflow => self.stack_underflow,
+            CpuFault::InvalidOpcode(_) => self.invalid_opcode,
+            CpuFault::MemoryOutOfBounds(_) => self.memory_out_of_bounds
+        }
+    }
+
+}
+
+impl Default for FaultPolicy {
+    fn default() -> Self {
+        FaultPolicy::all(FaultAction::Halt)
+    }
+}
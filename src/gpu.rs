@@ -1,80 +1,231 @@
-use coffee::graphics::{Frame, Color, Shape, Rectangle, Mesh};
+use crate::quirks::Quirks;
+use crate::state::{Reader, Writer};
 
+const LOW_WIDTH: usize = 64;
+const LOW_HEIGHT: usize = 32;
+const HIGH_WIDTH: usize = 128;
+const HIGH_HEIGHT: usize = 64;
+const PLANES: usize = 2;
+const PLANE_MASK: u8 = 0x3;
+
+pub const PALETTE: [u32; 4] = [0x000000, 0xffffff, 0xaaaaaa, 0x555555];
+
+/// Each byte of `vram` holds one pixel, with bit n set when the pixel is lit
+/// on XO-CHIP bitplane n. Plain CHIP-8 and SUPER-CHIP only use plane 0.
 pub struct Gpu {
     pub width: usize,
     pub height: usize,
-    pub vram: [u8; 4096]
+    pub vram: [u8; HIGH_WIDTH * HIGH_HEIGHT],
+    pub plane: u8,
+    pub quirks: Quirks,
+    /// The rrggbb color of each combination of bitplanes.
+    pub palette: [u32; 4]
+}
+
+impl Default for Gpu {
+    fn default() -> Self {
+        Gpu::new()
+    }
 }
 
 impl Gpu {
 
     pub fn new() -> Self {
         Gpu {
-            width: 64,
-            height: 32,
-            vram: [0; 4096]
+            width: LOW_WIDTH,
+            height: LOW_HEIGHT,
+            vram: [0; HIGH_WIDTH * HIGH_HEIGHT],
+            plane: 0x1,
+            quirks: Quirks::default(),
+            palette: PALETTE
         }
     }
 
+    /// Clears the selected bitplanes.
     pub fn clear(&mut self) {
+        let mask = !self.plane;
+        for x in self.vram.iter_mut() {
+            *x &= mask;
+        }
+    }
+
+    /// Selects the bitplanes affected by drawing, scrolling and clearing.
+    pub fn set_plane(&mut self, plane: u8) {
+        self.plane = plane & PLANE_MASK;
+    }
+
+    /// Switches to the 64x32 resolution and clears the screen.
+    pub fn low(&mut self) {
+        self.resize(LOW_WIDTH, LOW_HEIGHT);
+    }
+
+    /// Switches to the 128x64 SUPER-CHIP resolution and clears the screen.
+    pub fn high(&mut self) {
+        self.resize(HIGH_WIDTH, HIGH_HEIGHT);
+    }
+
+    fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
         for x in self.vram.iter_mut() {
             *x = 0;
         }
     }
 
+    /// Copies the selected planes of pixel <from> into pixel <to>, or clears
+    /// them if there is no source pixel.
+    fn shift(&mut self, to: usize, from: Option<usize>) {
+        let plane = self.plane;
+        let texel = from.map_or(0, |from| self.vram[from]);
+        self.vram[to] = (self.vram[to] & !plane) | (texel & plane);
+    }
+
+    /// Scrolls the selected planes down by <n> pixels.
+    pub fn scroll_down(&mut self, n: usize) {
+        let width = self.width;
+        for y in (0..self.height).rev() {
+            for x in 0..width {
+                let from = if y >= n { Some((y - n) * width + x) } else { None };
+                self.shift(y * width + x, from);
+            }
+        }
+    }
+
+    /// Scrolls the selected planes right by 4 pixels.
+    pub fn scroll_right(&mut self) {
+        let width = self.width;
+        for y in 0..self.height {
+            let row = y * width;
+            for x in (0..width).rev() {
+                let from = if x >= 4 { Some(row + x - 4) } else { None };
+                self.shift(row + x, from);
+            }
+        }
+    }
+
+    /// Scrolls the selected planes left by 4 pixels.
+    pub fn scroll_left(&mut self) {
+        let width = self.width;
+        for y in 0..self.height {
+            let row = y * width;
+            for x in 0..width {
+                let from = if x + 4 < width { Some(row + x + 4) } else { None };
+                self.shift(row + x, from);
+            }
+        }
+    }
+
+    /// Draws a 8xn sprite, returning true if any pixel was erased.
     pub fn draw_sprite(&mut self, 
         memory: &[u8], addr: u16, len: u8, x: u8, y: u8) -> bool {
+        self.draw(memory, addr, len as usize, 1, x, y)
+    }
+
+    /// Draws a 16x16 SUPER-CHIP sprite, returning true if any pixel was erased.
+    pub fn draw_sprite16(&mut self, memory: &[u8], addr: u16, x: u8, y: u8) -> bool {
+        self.draw(memory, addr, 16, 2, x, y)
+    }
+
+    /// Draws a sprite onto each selected plane in turn. When more than one
+    /// plane is selected, the sprite data for each plane follows the last.
+    ///
+    /// The starting coordinate always wraps around the screen. Pixels that
+    /// fall off an edge are clipped or wrapped depending on the quirks.
+    fn draw(&mut self,
+        memory: &[u8], addr: u16, rows: usize, stride: usize, x: u8, y: u8) -> bool {
         let mut collision = false;
-        let x = x as u16;
-        let y = y as u16;
-        let len = len as u16;
-        let width = self.width as u16;
-        for py in 0..len {
-            let pixel = memory[(addr + py) as usize];
-            for px in 0..8 {
-                if (pixel & (0x80 >> px)) != 0x0 {
-                    let addr = x + px + ((y + py) * width);
-                    let addr = addr as usize;
-                    if self.vram[addr] == 1 {
-                        collision |= true;
+        let mut addr = addr as usize;
+        let width = self.width;
+        let height = self.height;
+        let x = x as usize % width;
+        let y = y as usize % height;
+        for layer in 0..PLANES {
+            let mask = 1 << layer;
+            if self.plane & mask == 0 {
+                continue;
+            }
+            for py in 0..rows {
+                let row = y + py;
+                if row >= height && self.quirks.clip {
+                    break;
+                }
+                let row = row % height;
+                for column in 0..stride {
+                    let pixel = memory[(addr + py * stride + column) % memory.len()];
+                    for px in 0..8 {
+                        let col = x + column * 8 + px;
+                        if col >= width && self.quirks.clip {
+                            break;
+                        }
+                        let col = col % width;
+                        if (pixel & (0x80 >> px)) != 0x0 {
+                            let addr = col + row * width;
+                            if self.vram[addr] & mask != 0 {
+                                collision |= true;
+                            }
+                            self.vram[addr] ^= mask;
+                        }
                     }
-                    self.vram[addr] ^= 1;
                 }
             }
+            addr += rows * stride;
         }
         collision
     }
 
     pub fn reset(&mut self) {
-        self.clear();
-        log!("[gpu] reset");
+        self.plane = 0x1;
+        self.low();
     }
 
-    pub fn render(&mut self, frame: &mut Frame) {
+    pub fn save(&self, state: &mut Writer) {
+        state.bool(self.width == HIGH_WIDTH);
+        state.u8(self.plane);
+        state.bytes(&self.vram);
+    }
 
-        frame.clear(Color::BLACK);
-        
-        let mut mesh = Mesh::new();
-        let scale = 10f32;
+    pub fn restore(&mut self, state: &mut Reader) -> Result<(), String> {
+        if state.bool()? {
+            self.high();
+        } else {
+            self.low();
+        }
+        self.set_plane(state.u8()?);
+        self.vram.copy_from_slice(state.bytes(HIGH_WIDTH * HIGH_HEIGHT)?);
+        Ok(())
+    }
 
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let index = y * self.width + x;
-                let texel = self.vram[index as usize] & 0x01;
-                let x = x as f32;
-                let y = y as f32;
-                let color = if texel == 0x01 { Color::WHITE } else { Color::BLACK };
-                
-                mesh.fill(Shape::Rectangle(Rectangle {
-                    x: x * scale,
-                    y: y * scale,
-                    width: scale,
-                    height: scale
-                }), color);
-            }
+    /// The visible part of vram, as plain data.
+    pub fn framebuffer(&self) -> Framebuffer<'_> {
+        Framebuffer {
+            width: self.width,
+            height: self.height,
+            pixels: &self.vram[..self.width * self.height],
+            palette: self.palette
         }
+    }
 
-        mesh.draw(&mut frame.as_target());
+}
+
+/// A snapshot of the screen: one byte of bitplanes per pixel, row by row,
+/// and the rrggbb color of each combination of planes.
+pub struct Framebuffer<'a> {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: &'a [u8],
+    pub palette: [u32; 4]
+}
+
+impl<'a> Framebuffer<'a> {
+
+    /// The bitplanes lit at (<x>, <y>).
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x] & PLANE_MASK
     }
 
-}
\ No newline at end of file
+    /// The rrggbb color of (<x>, <y>).
+    pub fn color(&self, x: usize, y: usize) -> u32 {
+        self.palette[self.pixel(x, y) as usize]
+    }
+
+}
@@ -0,0 +1,210 @@
+use std::fs;
+use std::path::Path;
+
+use crate::machine::Machine;
+use crate::image::{self, Format};
+use crate::fault::CpuFault;
+use crate::movie::{Player, Recorder};
+
+/// Holds CHIP-8 <key> down for <frames> frames, starting at frame <frame>.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Press {
+    pub frame: u32,
+    pub key: u8,
+    pub frames: u32
+}
+
+/// Frames a scripted key is held for when no duration is given. Long enough
+/// for programs that poll the keypad once every few frames.
+const DEFAULT_PRESS_FRAMES: u32 = 6;
+
+impl Press {
+
+    /// Parses `<frame>:<key>[:<frames>]`, with the key as a hex digit.
+    pub fn parse(text: &str) -> Result<Press, String> {
+        let error = || format!("expected <frame>:<key>[:<frames>], got '{}'", text);
+        let parts: Vec<&str> = text.split(':').collect();
+        if parts.len() < 2 || parts.len() > 3 {
+            return Err(error());
+        }
+        let frame = parts[0].parse().map_err(|_| error())?;
+        let key = match u8::from_str_radix(parts[1], 16) {
+            Ok(key) if key < 16 => key,
+            _ => return Err(format!("'{}' is not a CHIP-8 key (0-f)", parts[1]))
+        };
+        let frames = match parts.get(2) {
+            Some(frames) => frames.parse().map_err(|_| error())?,
+            None => DEFAULT_PRESS_FRAMES
+        };
+        Ok(Press { frame, key, frames })
+    }
+
+    /// Parses a script of whitespace separated presses. `#` starts a comment
+    /// that runs to the end of the line.
+    pub fn parse_script(text: &str) -> Result<Vec<Press>, String> {
+        let mut presses = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            for word in line.split_whitespace() {
+                presses.push(Press::parse(word).map_err(|e| format!("{}: {}", n + 1, e))?);
+            }
+        }
+        Ok(presses)
+    }
+
+    fn held(&self, frame: u32) -> bool {
+        frame >= self.frame && frame - self.frame < self.frames
+    }
+
+}
+
+/// How a headless run ended.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    /// Still running after the last frame.
+    Running,
+    /// Stopped by an `exit` instruction.
+    Halted,
+    /// Stopped by a fault.
+    Faulted(CpuFault)
+}
+
+impl Status {
+
+    /// The process exit status for the run. 1 and 2 are left for errors
+    /// and bad command lines.
+    pub fn code(&self) -> i32 {
+        match self {
+            Status::Running => 0,
+            Status::Halted => 5,
+            Status::Faulted(_) => 3
+        }
+    }
+
+}
+
+/// Runs <machine> for <frames> frames, pressing keys as scripted, and
+/// recording the run to <recorder> if given.
+pub fn run(machine: &mut Machine, frames: u32, presses: &[Press], mut recorder: Option<&mut Recorder>) -> Status {
+    for input in inputs(presses, frames) {
+        machine.keypad.set_bits(input);
+        let result = match &mut recorder {
+            Some(recorder) => recorder.frame(machine),
+            None => machine.frame()
+        };
+        if let Err(fault) = result {
+            return Status::Faulted(fault);
+        }
+        if machine.halted() {
+            break;
+        }
+    }
+    status(machine)
+}
+
+/// The keypad bits held during each of <frames> frames.
+pub fn inputs(presses: &[Press], frames: u32) -> Vec<u16> {
+    (0..frames).map(|frame| {
+        presses.iter()
+            .filter(|press| press.held(frame))
+            .fold(0, |keys, press| keys | 1 << press.key)
+    }).collect()
+}
+
+/// Runs <machine> until <player>'s movie ends. Fails if the run desyncs
+/// from the recording.
+pub fn replay(machine: &mut Machine, player: &mut Player) -> Result<Status, String> {
+    while player.frame(machine)? {
+        if machine.halted() && !player.finished() {
+            return Err(format!("halted at frame {} before the movie ended", player.position()));
+        }
+    }
+    Ok(status(machine))
+}
+
+fn status(machine: &Machine) -> Status {
+    match (machine.halted(), machine.fault()) {
+        (true, Some(fault)) => Status::Faulted(fault),
+        (true, None) => Status::Halted,
+        (false, _) => Status::Running
+    }
+}
+
+/// Writes the framebuffer to <path>, in <format> or the format implied by
+/// the file extension.
+pub fn dump(machine: &Machine, path: &Path, format: Option<Format>) -> Result<(), String> {
+    let format = format.or_else(|| Format::from_path(path))
+        .ok_or(format!("can't tell the image format of '{}', use --format", path.display()))?;
+    fs::write(path, image::encode(&machine.framebuffer(), format))
+        .map_err(|e| format!("can't write '{}': {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine(rom: &[u8]) -> Machine {
+        let mut machine = Machine::new();
+        machine.load(rom);
+        machine
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Press::parse("120:5"), Ok(Press { frame: 120, key: 0x5, frames: 6 }));
+        assert_eq!(Press::parse("0:F:30"), Ok(Press { frame: 0, key: 0xf, frames: 30 }));
+        assert_eq!(Press::parse("120"), Err(String::from("expected <frame>:<key>[:<frames>], got '120'")));
+        assert_eq!(Press::parse("1:2:3:4"), Err(String::from("expected <frame>:<key>[:<frames>], got '1:2:3:4'")));
+        assert_eq!(Press::parse("x:5"), Err(String::from("expected <frame>:<key>[:<frames>], got 'x:5'")));
+        assert_eq!(Press::parse("-1:5"), Err(String::from("expected <frame>:<key>[:<frames>], got '-1:5'")));
+        assert_eq!(Press::parse("1:5:"), Err(String::from("expected <frame>:<key>[:<frames>], got '1:5:'")));
+        assert_eq!(Press::parse("1:10"), Err(String::from("'10' is not a CHIP-8 key (0-f)")));
+        assert_eq!(Press::parse("1:"), Err(String::from("'' is not a CHIP-8 key (0-f)")));
+    }
+
+    #[test]
+    fn parse_script() {
+        let script = "
+            # serve, then move up
+            10:5 20:1:3
+            30:4  # and down
+        ";
+        assert_eq!(Press::parse_script(script), Ok(vec![
+            Press { frame: 10, key: 0x5, frames: 6 },
+            Press { frame: 20, key: 0x1, frames: 3 },
+            Press { frame: 30, key: 0x4, frames: 6 }
+        ]));
+        assert_eq!(Press::parse_script(""), Ok(vec![]));
+        assert_eq!(Press::parse_script("1:1\n\n2:g"), Err(String::from("3: 'g' is not a CHIP-8 key (0-f)")));
+    }
+
+    #[test]
+    fn inputs() {
+        let presses = [
+            Press { frame: 1, key: 0x1, frames: 3 },
+            Press { frame: 2, key: 0x4, frames: 1 },
+            Press { frame: 3, key: 0x1, frames: 2 }
+        ];
+        assert_eq!(super::inputs(&presses, 6), [0, 0x2, 0x12, 0x2, 0x2, 0]);
+        assert_eq!(super::inputs(&presses, 2), [0, 0x2]);
+        assert_eq!(super::inputs(&[], 2), [0, 0]);
+    }
+
+    #[test]
+    fn code() {
+        assert_eq!(Status::Running.code(), 0);
+        assert_eq!(Status::Halted.code(), 5);
+        assert_eq!(Status::Faulted(CpuFault::StackUnderflow).code(), 3);
+    }
+
+    #[test]
+    fn status() {
+        // jp 0x200
+        assert_eq!(run(&mut machine(&[0x12, 0x00]), 10, &[], None), Status::Running);
+        // exit
+        assert_eq!(run(&mut machine(&[0x00, 0xfd]), 10, &[], None), Status::Halted);
+        assert_eq!(run(&mut machine(&[0x80, 0x09]), 10, &[], None),
+            Status::Faulted(CpuFault::InvalidOpcode(0x8009)));
+    }
+
+}
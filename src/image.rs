@@ -0,0 +1,170 @@
+use std::path::Path;
+use crate::gpu::Framebuffer;
+
+/// Characters used for each combination of bitplanes in ASCII dumps.
+const ASCII: [char; 4] = ['.', '#', '+', '*'];
+
+/// An image format the framebuffer can be written as.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// 24-bit RGB PNG, colored with the palette.
+    Png,
+    /// Binary PBM (P4), with any lit plane as black.
+    Pbm,
+    /// One character per pixel, one line per row.
+    Ascii
+}
+
+impl Format {
+
+    pub fn parse(name: &str) -> Option<Format> {
+        match name {
+            "png" => Some(Format::Png),
+            "pbm" => Some(Format::Pbm),
+            "ascii" | "txt" => Some(Format::Ascii),
+            _ => None
+        }
+    }
+
+    /// Guesses the format from the extension of <path>.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        Format::parse(&path.extension()?.to_str()?.to_lowercase())
+    }
+
+}
+
+/// Encodes <framebuffer> in <format>.
+pub fn encode(framebuffer: &Framebuffer, format: Format) -> Vec<u8> {
+    match format {
+        Format::Png => png(framebuffer),
+        Format::Pbm => pbm(framebuffer),
+        Format::Ascii => ascii(framebuffer).into_bytes()
+    }
+}
+
+pub fn ascii(framebuffer: &Framebuffer) -> String {
+    let (width, height) = (framebuffer.width, framebuffer.height);
+    let mut text = String::with_capacity((width + 1) * height);
+    for y in 0..height {
+        for x in 0..width {
+            text.push(ASCII[framebuffer.pixel(x, y) as usize]);
+        }
+        text.push('\n');
+    }
+    text
+}
+
+pub fn pbm(framebuffer: &Framebuffer) -> Vec<u8> {
+    let (width, height) = (framebuffer.width, framebuffer.height);
+    let mut data = format!("P4\n{} {}\n", width, height).into_bytes();
+    for y in 0..height {
+        for x in (0..width).step_by(8) {
+            let mut byte = 0;
+            for bit in 0..8 {
+                if x + bit < width && framebuffer.pixel(x + bit, y) != 0 {
+                    byte |= 0x80 >> bit;
+                }
+            }
+            data.push(byte);
+        }
+    }
+    data
+}
+
+pub fn png(framebuffer: &Framebuffer) -> Vec<u8> {
+    let (width, height) = (framebuffer.width, framebuffer.height);
+    let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+    for y in 0..height {
+        // filter type: none
+        raw.push(0);
+        for x in 0..width {
+            let rgb = framebuffer.color(x, y);
+            raw.extend_from_slice(&[(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8]);
+        }
+    }
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8-bit RGB, deflate, adaptive filtering, no interlace
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &zlib(&raw));
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wraps <data> in a zlib stream of uncompressed deflate blocks.
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[0x01, 0x00, 0x00, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        stream.push(last as u8);
+        stream.extend_from_slice(&len.to_le_bytes());
+        stream.extend_from_slice(&(!len).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn framebuffer(pixels: &[u8]) -> Framebuffer<'_> {
+        Framebuffer { width: 3, height: 2, pixels, palette: [0x000000, 0xffffff, 0xff0000, 0x00ff00] }
+    }
+
+    #[test]
+    fn ascii() {
+        assert_eq!(super::ascii(&framebuffer(&[0, 1, 2, 3, 0, 1])), ".#+\n*.#\n");
+    }
+
+    #[test]
+    fn pbm() {
+        assert_eq!(super::pbm(&framebuffer(&[0, 1, 2, 3, 0, 1])), b"P4\n3 2\n\x60\xa0");
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+}
@@ -0,0 +1,456 @@
+use std::fmt;
+
+/// A decoded CHIP-8, SUPER-CHIP or XO-CHIP instruction.
+///
+/// Register operands are indices into V0-VF. `Display` produces the
+/// mnemonics used in traces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    /// 0nnn - call machine code routine (ignored)
+    Sys(u16),
+    /// 00cn - scroll down n pixels
+    Scd(u8),
+    /// 00e0 - clear the screen
+    Cls,
+    /// 00ee - return from subroutine
+    Ret,
+    /// 00fb - scroll right 4 pixels
+    Scr,
+    /// 00fc - scroll left 4 pixels
+    Scl,
+    /// 00fd - exit the interpreter
+    Exit,
+    /// 00fe - 64x32 resolution
+    Low,
+    /// 00ff - 128x64 resolution
+    High,
+    /// 1nnn - jump to nnn
+    Jp(u16),
+    /// 2nnn - call subroutine at nnn
+    Call(u16),
+    /// 3xkk - skip if vx == kk
+    SeVxKk { x: u8, kk: u8 },
+    /// 4xkk - skip if vx != kk
+    SneVxKk { x: u8, kk: u8 },
+    /// 5xy0 - skip if vx == vy
+    SeVxVy { x: u8, y: u8 },
+    /// 5xy2 - store vx..vy at i
+    Save { x: u8, y: u8 },
+    /// 5xy3 - load vx..vy from i
+    Load { x: u8, y: u8 },
+    /// 6xkk - vx = kk
+    LdVxKk { x: u8, kk: u8 },
+    /// 7xkk - vx += kk
+    AddVxKk { x: u8, kk: u8 },
+    /// 8xy0 - vx = vy
+    LdVxVy { x: u8, y: u8 },
+    /// 8xy1 - vx |= vy
+    Or { x: u8, y: u8 },
+    /// 8xy2 - vx &= vy
+    And { x: u8, y: u8 },
+    /// 8xy3 - vx ^= vy
+    Xor { x: u8, y: u8 },
+    /// 8xy4 - vx += vy, vf = carry
+    AddVxVy { x: u8, y: u8 },
+    /// 8xy5 - vx -= vy, vf = not borrow
+    SubVxVy { x: u8, y: u8 },
+    /// 8xy6 - vx >>= 1, vf = lsb
+    Shr { x: u8, y: u8 },
+    /// 8xy7 - vx = vy - vx, vf = not borrow
+    Subn { x: u8, y: u8 },
+    /// 8xye - vx <<= 1, vf = msb
+    Shl { x: u8, y: u8 },
+    /// 9xy0 - skip if vx != vy
+    SneVxVy { x: u8, y: u8 },
+    /// annn - i = nnn
+    LdI(u16),
+    /// bnnn - jump to nnn + v0
+    JpV0(u16),
+    /// cxkk - vx = random & kk
+    Rnd { x: u8, kk: u8 },
+    /// dxyn - draw 8xn sprite, or 16x16 when n is 0
+    Drw { x: u8, y: u8, n: u8 },
+    /// ex9e - skip if key vx is pressed
+    Skp(u8),
+    /// exa1 - skip if key vx is not pressed
+    Sknp(u8),
+    /// f000 nnnn - i = nnnn
+    LdILong(u16),
+    /// fn01 - select bitplanes n
+    Plane(u8),
+    /// f002 - load audio pattern from i
+    Audio,
+    /// fx07 - vx = dt
+    LdVxDt(u8),
+    /// fx0a - wait for a key and load it into vx
+    LdVxK(u8),
+    /// fx15 - dt = vx
+    LdDtVx(u8),
+    /// fx18 - st = vx
+    LdStVx(u8),
+    /// fx1e - i += vx
+    AddIVx(u8),
+    /// fx29 - i = small font sprite for vx
+    LdFVx(u8),
+    /// fx30 - i = big font sprite for vx
+    LdHfVx(u8),
+    /// fx33 - store bcd of vx at i
+    LdBVx(u8),
+    /// fx3a - pitch = vx
+    Pitch(u8),
+    /// fx55 - store v0..vx at i
+    LdIVx(u8),
+    /// fx65 - load v0..vx from i
+    LdVxI(u8),
+    /// fx75 - store v0..vx in rpl flags
+    LdRVx(u8),
+    /// fx85 - load v0..vx from rpl flags
+    LdVxR(u8),
+    /// Any opcode not defined by a supported platform.
+    Invalid(u16)
+}
+
+use Instruction::*;
+
+impl Instruction {
+
+    /// Decodes a single opcode.
+    ///
+    /// `f000` is the only instruction that spans two words. It decodes to
+    /// `LdILong(0)`, and the caller fills in the address from the next word.
+    pub fn decode(opcode: u16) -> Instruction {
+        let x = ((opcode & 0x0f00) >> 8) as u8;
+        let y = ((opcode & 0x00f0) >> 4) as u8;
+        let n = (opcode & 0x000f) as u8;
+        let kk = (opcode & 0x00ff) as u8;
+        let nnn = opcode & 0x0fff;
+        match opcode & 0xf000 {
+            0x0000 => match opcode {
+                0x00c0..=0x00cf => Scd(n),
+                0x00e0 => Cls,
+                0x00ee => Ret,
+                0x00fb => Scr,
+                0x00fc => Scl,
+                0x00fd => Exit,
+                0x00fe => Low,
+                0x00ff => High,
+                _ => Sys(nnn)
+            },
+            0x1000 => Jp(nnn),
+            0x2000 => Call(nnn),
+            0x3000 => SeVxKk { x, kk },
+            0x4000 => SneVxKk { x, kk },
+            0x5000 => match n {
+                0x0 => SeVxVy { x, y },
+                0x2 => Save { x, y },
+                0x3 => Load { x, y },
+                _ => Invalid(opcode)
+            },
+            0x6000 => LdVxKk { x, kk },
+            0x7000 => AddVxKk { x, kk },
+            0x8000 => match n {
+                0x0 => LdVxVy { x, y },
+                0x1 => Or { x, y },
+                0x2 => And { x, y },
+                0x3 => Xor { x, y },
+                0x4 => AddVxVy { x, y },
+                0x5 => SubVxVy { x, y },
+                0x6 => Shr { x, y },
+                0x7 => Subn { x, y },
+                0xe => Shl { x, y },
+                _ => Invalid(opcode)
+            },
+            0x9000 => match n {
+                0x0 => SneVxVy { x, y },
+                _ => Invalid(opcode)
+            },
+            0xa000 => LdI(nnn),
+            0xb000 => JpV0(nnn),
+            0xc000 => Rnd { x, kk },
+            0xd000 => Drw { x, y, n },
+            0xe000 => match kk {
+                0x9e => Skp(x),
+                0xa1 => Sknp(x),
+                _ => Invalid(opcode)
+            },
+            _ => match kk {
+                0x00 if x == 0 => LdILong(0),
+                0x01 => Plane(x),
+                0x02 if x == 0 => Audio,
+                0x07 => LdVxDt(x),
+                0x0a => LdVxK(x),
+                0x15 => LdDtVx(x),
+                0x18 => LdStVx(x),
+                0x1e => AddIVx(x),
+                0x29 => LdFVx(x),
+                0x30 => LdHfVx(x),
+                0x33 => LdBVx(x),
+                0x3a => Pitch(x),
+                0x55 => LdIVx(x),
+                0x65 => LdVxI(x),
+                0x75 => LdRVx(x),
+                0x85 => LdVxR(x),
+                _ => Invalid(opcode)
+            }
+        }
+    }
+
+    /// Encodes the instruction's first word. For `LdILong` the address is
+    /// the second word, see `to_bytes`.
+    pub fn encode(&self) -> u16 {
+        let xy = |op: u16, x: u8, y: u8, n: u16| {
+            op | ((x as u16 & 0xf) << 8) | ((y as u16 & 0xf) << 4) | n
+        };
+        let xkk = |op: u16, x: u8, kk: u8| op | ((x as u16 & 0xf) << 8) | kk as u16;
+        match *self {
+            Sys(nnn) => nnn & 0x0fff,
+            Scd(n) => 0x00c0 | (n as u16 & 0xf),
+            Cls => 0x00e0,
+            Ret => 0x00ee,
+            Scr => 0x00fb,
+            Scl => 0x00fc,
+            Exit => 0x00fd,
+            Low => 0x00fe,
+            High => 0x00ff,
+            Jp(nnn) => 0x1000 | (nnn & 0x0fff),
+            Call(nnn) => 0x2000 | (nnn & 0x0fff),
+            SeVxKk { x, kk } => xkk(0x3000, x, kk),
+            SneVxKk { x, kk } => xkk(0x4000, x, kk),
+            SeVxVy { x, y } => xy(0x5000, x, y, 0x0),
+            Save { x, y } => xy(0x5000, x, y, 0x2),
+            Load { x, y } => xy(0x5000, x, y, 0x3),
+            LdVxKk { x, kk } => xkk(0x6000, x, kk),
+            AddVxKk { x, kk } => xkk(0x7000, x, kk),
+            LdVxVy { x, y } => xy(0x8000, x, y, 0x0),
+            Or { x, y } => xy(0x8000, x, y, 0x1),
+            And { x, y } => xy(0x8000, x, y, 0x2),
+            Xor { x, y } => xy(0x8000, x, y, 0x3),
+            AddVxVy { x, y } => xy(0x8000, x, y, 0x4),
+            SubVxVy { x, y } => xy(0x8000, x, y, 0x5),
+            Shr { x, y } => xy(0x8000, x, y, 0x6),
+            Subn { x, y } => xy(0x8000, x, y, 0x7),
+            Shl { x, y } => xy(0x8000, x, y, 0xe),
+            SneVxVy { x, y } => xy(0x9000, x, y, 0x0),
+            LdI(nnn) => 0xa000 | (nnn & 0x0fff),
+            JpV0(nnn) => 0xb000 | (nnn & 0x0fff),
+            Rnd { x, kk } => xkk(0xc000, x, kk),
+            Drw { x, y, n } => xy(0xd000, x, y, n as u16 & 0xf),
+            Skp(x) => xkk(0xe000, x, 0x9e),
+            Sknp(x) => xkk(0xe000, x, 0xa1),
+            LdILong(_) => 0xf000,
+            Plane(n) => xkk(0xf000, n, 0x01),
+            Audio => 0xf002,
+            LdVxDt(x) => xkk(0xf000, x, 0x07),
+            LdVxK(x) => xkk(0xf000, x, 0x0a),
+            LdDtVx(x) => xkk(0xf000, x, 0x15),
+            LdStVx(x) => xkk(0xf000, x, 0x18),
+            AddIVx(x) => xkk(0xf000, x, 0x1e),
+            LdFVx(x) => xkk(0xf000, x, 0x29),
+            LdHfVx(x) => xkk(0xf000, x, 0x30),
+            LdBVx(x) => xkk(0xf000, x, 0x33),
+            Pitch(x) => xkk(0xf000, x, 0x3a),
+            LdIVx(x) => xkk(0xf000, x, 0x55),
+            LdVxI(x) => xkk(0xf000, x, 0x65),
+            LdRVx(x) => xkk(0xf000, x, 0x75),
+            LdVxR(x) => xkk(0xf000, x, 0x85),
+            Invalid(opcode) => opcode
+        }
+    }
+
+    /// The size of the instruction in bytes.
+    pub fn size(&self) -> u16 {
+        match self {
+            LdILong(_) => 4,
+            _ => 2
+        }
+    }
+
+    /// The instruction as it is laid out in memory.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.encode().to_be_bytes().to_vec();
+        if let LdILong(nnnn) = self {
+            bytes.extend_from_slice(&nnnn.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn class(&self) -> Class {
+        match self {
+            Sys(_) | Ret | Exit | Jp(_) | Call(_) | JpV0(_) |
+            SeVxKk { .. } | SneVxKk { .. } | SeVxVy { .. } | SneVxVy { .. } => Class::Flow,
+            LdVxKk { .. } | AddVxKk { .. } | LdVxVy { .. } | Or { .. } | And { .. } |
+            Xor { .. } | AddVxVy { .. } | SubVxVy { .. } | Shr { .. } | Subn { .. } |
+            Shl { .. } | Rnd { .. } => Class::Alu,
+            LdI(_) | LdILong(_) | AddIVx(_) | LdFVx(_) | LdHfVx(_) | LdBVx(_) |
+            LdIVx(_) | LdVxI(_) | Save { .. } | Load { .. } | LdRVx(_) | LdVxR(_) => Class::Memory,
+            Cls | Scd(_) | Scr | Scl | Low | High | Drw { .. } | Plane(_) => Class::Display,
+            Skp(_) | Sknp(_) | LdVxK(_) => Class::Input,
+            LdVxDt(_) | LdDtVx(_) | LdStVx(_) => Class::Timer,
+            Audio | Pitch(_) => Class::Audio,
+            Invalid(_) => Class::Invalid
+        }
+    }
+
+    /// The platform that introduced the instruction.
+    pub fn extension(&self) -> Extension {
+        match self {
+            Scd(_) | Scr | Scl | Exit | Low | High | LdHfVx(_) | LdRVx(_) | LdVxR(_) => Extension::Schip,
+            Save { .. } | Load { .. } | LdILong(_) | Plane(_) | Audio | Pitch(_) => Extension::Xochip,
+            _ => Extension::Chip8
+        }
+    }
+
+}
+
+/// A platform's instruction set, each including those before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Extension {
+    Chip8,
+    Schip,
+    Xochip
+}
+
+impl Extension {
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Extension::Chip8 => "CHIP-8",
+            Extension::Schip => "SUPER-CHIP",
+            Extension::Xochip => "XO-CHIP"
+        }
+    }
+
+}
+
+/// A broad grouping of instructions, for filtering traces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Class {
+    /// Jumps, calls, returns and skips on registers.
+    Flow,
+    /// Register loads and arithmetic, including `rnd`.
+    Alu,
+    /// Loads and stores through <i>, <i> itself, and the RPL flags.
+    Memory,
+    /// Drawing, scrolling and display modes.
+    Display,
+    /// Keypad skips and waits.
+    Input,
+    /// Delay and sound timers.
+    Timer,
+    /// XO-CHIP audio pattern and pitch.
+    Audio,
+    Invalid
+}
+
+impl Class {
+
+    pub fn parse(name: &str) -> Option<Class> {
+        match name {
+            "flow" => Some(Class::Flow),
+            "alu" => Some(Class::Alu),
+            "memory" => Some(Class::Memory),
+            "display" => Some(Class::Display),
+            "input" => Some(Class::Input),
+            "timer" => Some(Class::Timer),
+            "audio" => Some(Class::Audio),
+            "invalid" => Some(Class::Invalid),
+            _ => None
+        }
+    }
+
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Sys(nnn) => write!(f, "sys {:#05x}", nnn),
+            Scd(n) => write!(f, "scd {:#x}", n),
+            Cls => write!(f, "cls"),
+            Ret => write!(f, "ret"),
+            Scr => write!(f, "scr"),
+            Scl => write!(f, "scl"),
+            Exit => write!(f, "exit"),
+            Low => write!(f, "low"),
+            High => write!(f, "high"),
+            Jp(nnn) => write!(f, "jp {:#05x}", nnn),
+            Call(nnn) => write!(f, "call {:#05x}", nnn),
+            SeVxKk { x, kk } => write!(f, "se v{:x}, {:#04x}", x, kk),
+            SneVxKk { x, kk } => write!(f, "sne v{:x}, {:#04x}", x, kk),
+            SeVxVy { x, y } => write!(f, "se v{:x}, v{:x}", x, y),
+            Save { x, y } => write!(f, "save v{:x}-v{:x}", x, y),
+            Load { x, y } => write!(f, "load v{:x}-v{:x}", x, y),
+            LdVxKk { x, kk } => write!(f, "ld v{:x}, {:#04x}", x, kk),
+            AddVxKk { x, kk } => write!(f, "add v{:x}, {:#04x}", x, kk),
+            LdVxVy { x, y } => write!(f, "ld v{:x}, v{:x}", x, y),
+            Or { x, y } => write!(f, "or v{:x}, v{:x}", x, y),
+            And { x, y } => write!(f, "and v{:x}, v{:x}", x, y),
+            Xor { x, y } => write!(f, "xor v{:x}, v{:x}", x, y),
+            AddVxVy { x, y } => write!(f, "add v{:x}, v{:x}", x, y),
+            SubVxVy { x, y } => write!(f, "sub v{:x}, v{:x}", x, y),
+            Shr { x, y } => write!(f, "shr v{:x}, v{:x}", x, y),
+            Subn { x, y } => write!(f, "subn v{:x}, v{:x}", x, y),
+            Shl { x, y } => write!(f, "shl v{:x}, v{:x}", x, y),
+            SneVxVy { x, y } => write!(f, "sne v{:x}, v{:x}", x, y),
+            LdI(nnn) => write!(f, "ld i, {:#05x}", nnn),
+            JpV0(nnn) => write!(f, "jp v0, {:#05x}", nnn),
+            Rnd { x, kk } => write!(f, "rnd v{:x}, {:#04x}", x, kk),
+            Drw { x, y, n } => write!(f, "drw v{:x}, v{:x}, {:#x}", x, y, n),
+            Skp(x) => write!(f, "skp v{:x}", x),
+            Sknp(x) => write!(f, "sknp v{:x}", x),
+            LdILong(nnnn) => write!(f, "ld i, long {:#06x}", nnnn),
+            Plane(n) => write!(f, "plane {:#x}", n),
+            Audio => write!(f, "audio"),
+            LdVxDt(x) => write!(f, "ld v{:x}, dt", x),
+            LdVxK(x) => write!(f, "ld v{:x}, k", x),
+            LdDtVx(x) => write!(f, "ld dt, v{:x}", x),
+            LdStVx(x) => write!(f, "ld st, v{:x}", x),
+            AddIVx(x) => write!(f, "add i, v{:x}", x),
+            LdFVx(x) => write!(f, "ld f, v{:x}", x),
+            LdHfVx(x) => write!(f, "ld hf, v{:x}", x),
+            LdBVx(x) => write!(f, "ld b, v{:x}", x),
+            Pitch(x) => write!(f, "pitch v{:x}", x),
+            LdIVx(x) => write!(f, "ld [i], v{:x}", x),
+            LdVxI(x) => write!(f, "ld v{:x}, [i]", x),
+            LdRVx(x) => write!(f, "ld r, v{:x}", x),
+            LdVxR(x) => write!(f, "ld v{:x}, r", x),
+            Invalid(opcode) => write!(f, "dw {:#06x}", opcode)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for opcode in 0..=0xffff {
+            let instruction = Instruction::decode(opcode);
+            assert_eq!(instruction.encode(), opcode, "{}", instruction);
+        }
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(Instruction::decode(0xd125), Drw { x: 1, y: 2, n: 5 });
+        assert_eq!(Instruction::decode(0x1234), Jp(0x234));
+        assert_eq!(Instruction::decode(0xf000), LdILong(0));
+        assert_eq!(Instruction::decode(0xf100), Invalid(0xf100));
+        assert_eq!(Instruction::decode(0x8008), Invalid(0x8008));
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Drw { x: 1, y: 2, n: 5 }.to_string(), "drw v1, v2, 0x5");
+        assert_eq!(Jp(0x200).to_string(), "jp 0x200");
+        assert_eq!(LdVxKk { x: 0xa, kk: 0x1f }.to_string(), "ld va, 0x1f");
+        assert_eq!(LdILong(0x1234).to_string(), "ld i, long 0x1234");
+        assert_eq!(LdIVx(0xf).to_string(), "ld [i], vf");
+    }
+
+    #[test]
+    fn to_bytes() {
+        assert_eq!(LdILong(0x1234).to_bytes(), vec![0xf0, 0x00, 0x12, 0x34]);
+        assert_eq!(Cls.to_bytes(), vec![0x00, 0xe0]);
+    }
+}
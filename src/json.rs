@@ -0,0 +1,337 @@
+use std::fmt;
+
+/// A JSON value, just enough for the debug adapter's messages. Objects
+/// keep their keys in order, so output is stable.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>)
+}
+
+impl Value {
+
+    pub fn object(members: Vec<(&str, Value)>) -> Value {
+        Value::Object(members.into_iter().map(|(key, value)| (String::from(key), value)).collect())
+    }
+
+    /// The member <key> of an object, or Null if there isn't one.
+    pub fn get(&self, key: &str) -> &Value {
+        match self {
+            Value::Object(members) => members.iter()
+                .find(|(name, _)| name == key)
+                .map_or(&Value::Null, |(_, value)| value),
+            _ => &Value::Null
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(text) => Some(text),
+            _ => None
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(value) => Some(*value),
+            _ => None
+        }
+    }
+
+    /// The value as a whole number that fits in an i64.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 9.0e18 => Some(*n as i64),
+            _ => None
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Value::Null
+    }
+
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Value {
+        Value::Bool(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Value {
+        Value::Number(value as f64)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Value {
+        Value::String(String::from(value))
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Value {
+        Value::String(value)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(values: Vec<Value>) -> Value {
+        Value::Array(values)
+    }
+}
+
+/// Compact JSON, with no whitespace between tokens.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 9.0e15 => write!(f, "{}", *n as i64),
+            Value::Number(n) if n.is_finite() => write!(f, "{}", n),
+            Value::Number(_) => write!(f, "null"),
+            Value::String(text) => string(f, text),
+            Value::Array(values) => {
+                write!(f, "[")?;
+                for (n, value) in values.iter().enumerate() {
+                    if n > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            },
+            Value::Object(members) => {
+                write!(f, "{{")?;
+                for (n, (key, value)) in members.iter().enumerate() {
+                    if n > 0 {
+                        write!(f, ",")?;
+                    }
+                    string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn string(f: &mut fmt::Formatter, text: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in text.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?
+        }
+    }
+    write!(f, "\"")
+}
+
+/// How deeply arrays and objects may nest, so hostile input can't exhaust
+/// the stack.
+const MAX_DEPTH: usize = 128;
+
+/// Parses a complete JSON document.
+pub fn parse(text: &str) -> Result<Value, String> {
+    let mut parser = Parser { chars: text.char_indices().peekable(), depth: 0 };
+    let value = parser.value()?;
+    parser.whitespace();
+    match parser.chars.next() {
+        Some((at, c)) => Err(format!("unexpected '{}' at offset {}", c, at)),
+        None => Ok(value)
+    }
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    depth: usize
+}
+
+impl<'a> Parser<'a> {
+
+    fn whitespace(&mut self) {
+        while self.chars.next_if(|(_, c)| c.is_ascii_whitespace()).is_some() {}
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.chars.next() {
+            Some((_, c)) if c == expected => Ok(()),
+            Some((at, c)) => Err(format!("expected '{}', got '{}' at offset {}", expected, c, at)),
+            None => Err(format!("expected '{}', got the end of input", expected))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.whitespace();
+        let (at, c) = *self.chars.peek().ok_or("unexpected end of input")?;
+        match c {
+            '{' | '[' if self.depth == MAX_DEPTH => {
+                Err(format!("nested more than {} deep at offset {}", MAX_DEPTH, at))
+            },
+            '{' | '[' => {
+                self.depth += 1;
+                let value = if c == '{' { self.object() } else { self.array() };
+                self.depth -= 1;
+                value
+            },
+            '"' => self.string().map(Value::String),
+            't' => self.word("true", Value::Bool(true)),
+            'f' => self.word("false", Value::Bool(false)),
+            'n' => self.word("null", Value::Null),
+            '-' | '0'..='9' => self.number(),
+            _ => Err(format!("unexpected '{}' at offset {}", c, at))
+        }
+    }
+
+    fn word(&mut self, word: &str, value: Value) -> Result<Value, String> {
+        for expected in word.chars() {
+            self.expect(expected)?;
+        }
+        Ok(value)
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let mut text = String::new();
+        while let Some((_, c)) = self.chars.next_if(|(_, c)| matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9')) {
+            text.push(c);
+        }
+        text.parse().map(Value::Number).map_err(|_| format!("invalid number '{}'", text))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut text = String::new();
+        loop {
+            match self.chars.next().ok_or("unterminated string")?.1 {
+                '"' => return Ok(text),
+                '\\' => match self.chars.next().ok_or("unterminated string")?.1 {
+                    '"' => text.push('"'),
+                    '\\' => text.push('\\'),
+                    '/' => text.push('/'),
+                    'b' => text.push('\u{8}'),
+                    'f' => text.push('\u{c}'),
+                    'n' => text.push('\n'),
+                    'r' => text.push('\r'),
+                    't' => text.push('\t'),
+                    'u' => {
+                        let mut code = self.hex()?;
+                        // a surrogate pair encodes one character outside the BMP
+                        if (0xd800..0xdc00).contains(&code) {
+                            self.expect('\\')?;
+                            self.expect('u')?;
+                            code = 0x10000 + ((code - 0xd800) << 10) + (self.hex()?.wrapping_sub(0xdc00) & 0x3ff);
+                        }
+                        text.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                    },
+                    c => return Err(format!("invalid escape '\\{}'", c))
+                },
+                c => text.push(c)
+            }
+        }
+    }
+
+    fn hex(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self.chars.next().and_then(|(_, c)| c.to_digit(16))
+                .ok_or("invalid \\u escape")?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        self.whitespace();
+        if self.chars.next_if(|&(_, c)| c == ']').is_some() {
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.whitespace();
+            match self.chars.next() {
+                Some((_, ',')) => continue,
+                Some((_, ']')) => return Ok(Value::Array(values)),
+                _ => return Err(String::from("expected ',' or ']' in array"))
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.expect('{')?;
+        let mut members = Vec::new();
+        self.whitespace();
+        if self.chars.next_if(|&(_, c)| c == '}').is_some() {
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.whitespace();
+            let key = self.string()?;
+            self.whitespace();
+            self.expect(':')?;
+            members.push((key, self.value()?));
+            self.whitespace();
+            match self.chars.next() {
+                Some((_, ',')) => continue,
+                Some((_, '}')) => return Ok(Value::Object(members)),
+                _ => return Err(String::from("expected ',' or '}' in object"))
+            }
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let text = r#"{"seq":1,"type":"request","arguments":{"lines":[3,-4.5],"name":"a \"b\"\n","ok":true,"none":null}}"#;
+        let value = parse(text).unwrap();
+        assert_eq!(value.get("seq").as_i64(), Some(1));
+        assert_eq!(value.get("arguments").get("name").as_str(), Some("a \"b\"\n"));
+        assert_eq!(value.get("arguments").get("lines").as_array().unwrap()[1], Value::Number(-4.5));
+        assert!(value.get("missing").is_null());
+        assert_eq!(value.to_string(), text);
+    }
+
+    #[test]
+    fn escapes() {
+        let value = parse(r#" [ "é😀\t" , { } , [ ] ] "#).unwrap();
+        assert_eq!(value.as_array().unwrap()[0].as_str(), Some("é😀\t"));
+        assert_eq!(Value::from("\u{1}").to_string(), r#""\u0001""#);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(parse("{\"a\":}").is_err());
+        assert!(parse("[1,]").is_err());
+        assert!(parse("\"open").is_err());
+        assert!(parse("1 2").is_err());
+        assert!(parse("").is_err());
+        let deep = "[".repeat(200_000);
+        assert_eq!(parse(&deep), Err(String::from("nested more than 128 deep at offset 128")));
+        let nested = format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        assert!(parse(&nested).is_ok());
+    }
+
+}
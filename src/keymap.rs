@@ -0,0 +1,180 @@
+use std::path::Path;
+use coffee::input::keyboard::KeyCode;
+use crate::options;
+
+use KeyCode::*;
+
+/// The conventional layout, mapping the COSMAC VIP hex keypad
+///
+/// ```text
+/// 1 2 3 c
+/// 4 5 6 d
+/// 7 8 9 e
+/// a 0 b f
+/// ```
+///
+/// onto the left-hand block of a QWERTY keyboard.
+const DEFAULT: [KeyCode; 16] = [
+    X, Key1, Key2, Key3,
+    Q, W, E, A,
+    S, D, Z, C,
+    Key4, R, F, V
+];
+
+/// Keys the window keeps for its own hotkeys: pause, reset, the debugger
+/// panel, quick-save, step, quick-load and rewind.
+const HOTKEYS: &[KeyCode] = &[F1, F2, F3, F5, F6, F9, Back];
+
+const NAMES: &[(&str, KeyCode)] = &[
+    ("1", Key1), ("2", Key2), ("3", Key3), ("4", Key4), ("5", Key5),
+    ("6", Key6), ("7", Key7), ("8", Key8), ("9", Key9), ("0", Key0),
+    ("a", A), ("b", B), ("c", C), ("d", D), ("e", E), ("f", F), ("g", G),
+    ("h", H), ("i", I), ("j", J), ("k", K), ("l", L), ("m", M), ("n", N),
+    ("o", O), ("p", P), ("q", Q), ("r", R), ("s", S), ("t", T), ("u", U),
+    ("v", V), ("w", W), ("x", X), ("y", Y), ("z", Z),
+    ("up", Up), ("down", Down), ("left", Left), ("right", Right),
+    ("space", Space), ("return", Return), ("enter", Return), ("tab", Tab),
+    ("backspace", Back), ("lshift", LShift), ("rshift", RShift),
+    ("lctrl", LControl), ("rctrl", RControl), ("lalt", LAlt), ("ralt", RAlt),
+    ("comma", Comma), ("period", Period), ("slash", Slash),
+    ("semicolon", Semicolon), ("minus", Minus), ("equals", Equals),
+    ("numpad0", Numpad0), ("numpad1", Numpad1), ("numpad2", Numpad2),
+    ("numpad3", Numpad3), ("numpad4", Numpad4), ("numpad5", Numpad5),
+    ("numpad6", Numpad6), ("numpad7", Numpad7), ("numpad8", Numpad8),
+    ("numpad9", Numpad9)
+];
+
+/// Binds each of the 16 CHIP-8 keys to one or more keyboard keys.
+///
+/// Keymap files hold `<chip-8 key> = <key>[, <key>...]` lines, where the
+/// CHIP-8 key is a hex digit. Bindings under a `[<rom file name>]` header
+/// only apply to that ROM, replacing the defaults for the keys they list.
+/// Lines starting with `#` are comments.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keymap {
+    bindings: [Vec<KeyCode>; 16]
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut bindings: [Vec<KeyCode>; 16] = Default::default();
+        for (key, code) in DEFAULT.iter().enumerate() {
+            bindings[key].push(*code);
+        }
+        Keymap { bindings }
+    }
+}
+
+impl Keymap {
+
+    /// The keyboard keys bound to CHIP-8 <key>.
+    pub fn get(&self, key: usize) -> &[KeyCode] {
+        &self.bindings[key]
+    }
+
+    /// Loads the keymap file at <path>, applying the overrides for <rom>.
+    pub fn load(path: &Path, rom: &str) -> Result<Keymap, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("can't read keymap '{}': {}", path.display(), e))?;
+        Keymap::parse(&text, rom)
+            .map_err(|e| format!("{}:{}", path.display(), e))
+    }
+
+    /// Loads `chip8/keymap.cfg` from the user's config directory, if there
+    /// is one.
+    pub fn load_user(rom: &str) -> Result<Keymap, String> {
+        match options::config_path("keymap.cfg") {
+            Some(path) if path.exists() => Keymap::load(&path, rom),
+            _ => Ok(Keymap::default())
+        }
+    }
+
+    /// Parses keymap file <text>, applying the overrides for <rom>. Errors
+    /// are prefixed with their line number.
+    pub fn parse(text: &str, rom: &str) -> Result<Keymap, String> {
+        let mut keymap = Keymap::default();
+        let mut section: Option<&str> = None;
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                section = Some(line[1..line.len() - 1].trim());
+                continue;
+            }
+            let (key, codes) = Keymap::binding(line)
+                .map_err(|e| format!("{}: {}", n + 1, e))?;
+            if section.is_none_or(|name| name == rom) {
+                keymap.bindings[key] = codes;
+            }
+        }
+        Ok(keymap)
+    }
+
+    fn binding(line: &str) -> Result<(usize, Vec<KeyCode>), String> {
+        let mut parts = line.splitn(2, '=');
+        let key = parts.next().unwrap_or("").trim();
+        let codes = parts.next().ok_or(format!("expected '<key> = <keys>', got '{}'", line))?;
+        let key = match usize::from_str_radix(key, 16) {
+            Ok(key) if key < 16 => key,
+            _ => return Err(format!("'{}' is not a CHIP-8 key (0-f)", key))
+        };
+        let codes = codes.split(',')
+            .map(|name| Keymap::code(name.trim()))
+            .collect::<Result<Vec<KeyCode>, String>>()?;
+        Ok((key, codes))
+    }
+
+    fn code(name: &str) -> Result<KeyCode, String> {
+        let lower = name.to_lowercase();
+        let code = match NAMES.iter().find(|(n, _)| *n == lower) {
+            Some((_, code)) => *code,
+            None => match lower.strip_prefix('f').and_then(|n| n.parse::<usize>().ok()) {
+                Some(n @ 1..=12) => [F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12][n - 1],
+                _ => return Err(format!("unknown key '{}'", name))
+            }
+        };
+        match HOTKEYS.contains(&code) {
+            true => Err(format!("'{}' is reserved for a hotkey", name)),
+            false => Ok(code)
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default() {
+        let keymap = Keymap::default();
+        assert_eq!(keymap.get(0x1), &[Key1]);
+        assert_eq!(keymap.get(0xc), &[Key4]);
+        assert_eq!(keymap.get(0x0), &[X]);
+        assert_eq!(keymap.get(0xf), &[V]);
+    }
+
+    #[test]
+    fn parse() {
+        let text = "
+            # arrows as well as wasd
+            5 = w, up
+            [pong.ch8]
+            1 = Up
+            4 = down, F4
+        ";
+        let keymap = Keymap::parse(text, "brix.ch8").unwrap();
+        assert_eq!(keymap.get(0x5), &[W, Up]);
+        assert_eq!(keymap.get(0x1), &[Key1]);
+        let keymap = Keymap::parse(text, "pong.ch8").unwrap();
+        assert_eq!(keymap.get(0x1), &[Up]);
+        assert_eq!(keymap.get(0x4), &[Down, F4]);
+        assert_eq!(Keymap::parse("g = a", "").unwrap_err(), "1: 'g' is not a CHIP-8 key (0-f)");
+        assert_eq!(Keymap::parse("\n1 = foo", "").unwrap_err(), "2: unknown key 'foo'");
+        assert_eq!(Keymap::parse("4 = down, F9", "").unwrap_err(), "1: 'F9' is reserved for a hotkey");
+        assert_eq!(Keymap::parse("1 = backspace", "").unwrap_err(), "1: 'backspace' is reserved for a hotkey");
+    }
+
+}
@@ -1,9 +1,16 @@
 use bv::BitVec;
+use crate::state::{Reader, Writer};
 
 pub struct Keypad {
     state: BitVec<u16>
 }
 
+impl Default for Keypad {
+    fn default() -> Self {
+        Keypad::new()
+    }
+}
+
 impl Keypad {
     pub fn new() -> Self {
         Keypad {
@@ -16,4 +23,23 @@ impl Keypad {
     pub fn set(&mut self, key: usize, state: bool) {
         self.state.set(key as u64, state);
     }
+    pub fn pressed(&self) -> Option<usize> {
+        (0..16).find(|&key| self.get(key))
+    }
+    /// The state of all 16 keys, with bit n set while key n is held.
+    pub fn bits(&self) -> u16 {
+        (0..16).fold(0, |keys, key| keys | (self.get(key) as u16) << key)
+    }
+    pub fn set_bits(&mut self, keys: u16) {
+        for key in 0..16 {
+            self.set(key, keys & (1 << key) != 0);
+        }
+    }
+    pub fn save(&self, state: &mut Writer) {
+        state.u16(self.bits());
+    }
+    pub fn restore(&mut self, state: &mut Reader) -> Result<(), String> {
+        self.set_bits(state.u16()?);
+        Ok(())
+    }
 }
\ No newline at end of file
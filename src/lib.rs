@@ -0,0 +1,34 @@
+//! A CHIP-8, SUPER-CHIP and XO-CHIP emulator core.
+//!
+//! `Machine` ties the cpu, gpu, keypad and timers together and knows nothing
+//! about windows or audio devices; a frontend feeds it key state, calls
+//! `frame` at 60 Hz and presents the `Framebuffer` it exposes.
+
+pub mod cpu;
+pub mod fault;
+pub mod gpu;
+pub mod timer;
+pub mod keypad;
+pub mod quirks;
+pub mod instruction;
+pub mod audio;
+pub mod machine;
+pub mod headless;
+pub mod image;
+pub mod state;
+pub mod rewind;
+pub mod rng;
+pub mod movie;
+pub mod trace;
+pub mod diff;
+pub mod debugger;
+pub mod console;
+pub mod json;
+pub mod sourcemap;
+pub mod dap;
+pub mod disasm;
+pub mod asm;
+pub mod octo;
+
+pub use machine::Machine;
+pub use gpu::Framebuffer;
@@ -1,6 +0,0 @@
-macro_rules! log {
-    ($($arg:tt)*) => ({
-        // print!("chip-8: "); 
-        // println!($($arg)*)
-    });
-}
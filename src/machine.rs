@@ -0,0 +1,213 @@
+use crate::cpu::{Cpu, CpuContext};
+use crate::gpu::{Gpu, Framebuffer};
+use crate::keypad::Keypad;
+use crate::quirks::Quirks;
+use crate::instruction::Instruction;
+use crate::fault::{CpuFault, FaultAction, FaultPolicy};
+use crate::state::{self, Reader, Writer};
+use crate::trace::{Record, Tracer};
+
+use std::time::Duration;
+
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 10;
+
+/// A complete CHIP-8 machine, independent of any frontend.
+///
+/// The frontend calls `frame` at 60 Hz, feeding it input through `keypad`
+/// and presenting `framebuffer` afterwards.
+pub struct Machine {
+    pub cpu: Cpu,
+    pub gpu: Gpu,
+    pub keypad: Keypad,
+    rom: Vec<u8>,
+    faults: FaultPolicy,
+    fault: Option<CpuFault>,
+    instructions_per_frame: u32,
+    frame: u32,
+    tracer: Option<Tracer>
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Machine::new()
+    }
+}
+
+impl Machine {
+
+    pub fn new() -> Self {
+        Machine {
+            cpu: Cpu::new(),
+            gpu: Gpu::new(),
+            keypad: Keypad::new(),
+            rom: Vec::new(),
+            faults: FaultPolicy::default(),
+            fault: None,
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            frame: 0,
+            tracer: None
+        }
+    }
+
+    pub fn load(&mut self, rom: &[u8]) {
+        self.rom = rom.to_vec();
+        self.reset();
+    }
+
+    /// Resets the machine and reloads the current ROM.
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        self.gpu.reset();
+        self.cpu.load(&self.rom);
+        self.fault = None;
+        self.frame = 0;
+    }
+
+    pub fn set_fault_policy(&mut self, faults: FaultPolicy) {
+        self.faults = faults;
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.cpu.set_quirks(quirks);
+        self.gpu.quirks = quirks;
+    }
+
+    pub fn set_instructions_per_frame(&mut self, instructions: u32) {
+        self.instructions_per_frame = instructions;
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.gpu.quirks
+    }
+
+    pub fn instructions_per_frame(&self) -> u32 {
+        self.instructions_per_frame
+    }
+
+    /// Traces executed instructions to <tracer>, replacing any current
+    /// tracer without finishing it.
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Stops tracing, flushing the trace.
+    pub fn finish_trace(&mut self) -> Result<(), String> {
+        match self.tracer.take() {
+            Some(tracer) => tracer.finish(),
+            None => Ok(())
+        }
+    }
+
+    /// The number of frames run since the last reset.
+    pub fn frame_count(&self) -> u32 {
+        self.frame
+    }
+
+    /// The ROM loaded by `load`.
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    /// The screen as plain data, ready for a frontend to present.
+    pub fn framebuffer(&self) -> Framebuffer<'_> {
+        self.gpu.framebuffer()
+    }
+
+    pub fn halted(&self) -> bool {
+        self.cpu.halted()
+    }
+
+    /// The most recent fault, whatever action was taken for it.
+    pub fn fault(&self) -> Option<CpuFault> {
+        self.fault
+    }
+
+    /// Serializes the cpu, gpu and keypad, along with the frontend's frame
+    /// clock <phase>. Configuration such as quirks, the palette and the
+    /// instructions per frame isn't saved.
+    pub fn save_state(&self, phase: Duration) -> Vec<u8> {
+        let mut state = Writer::new();
+        state.header(state::MAGIC, state::VERSION);
+        self.cpu.save(&mut state);
+        self.gpu.save(&mut state);
+        self.keypad.save(&mut state);
+        state.u32(phase.as_micros() as u32);
+        state.finish()
+    }
+
+    /// Restores a state written by `save_state`, returning the frame clock
+    /// phase to resume from. The machine is left untouched on error.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<Duration, String> {
+        Machine::new().restore(data)?;
+        let phase = self.restore(data)?;
+        self.fault = None;
+        Ok(phase)
+    }
+
+    fn restore(&mut self, data: &[u8]) -> Result<Duration, String> {
+        let mut state = Reader::new(data);
+        state.header(state::MAGIC, state::VERSION, "save state")?;
+        self.cpu.restore(&mut state)?;
+        self.gpu.restore(&mut state)?;
+        self.keypad.restore(&mut state)?;
+        let phase = Duration::from_micros(state.u32()? as u64);
+        state.finish()?;
+        Ok(phase)
+    }
+
+    /// Runs a single 60 Hz frame: <instructions_per_frame> cycles followed
+    /// by one tick of the delay and sound timers.
+    ///
+    /// Returns the fault that stopped the frame early, if the fault policy
+    /// asked to break on it.
+    pub fn frame(&mut self) -> Result<(), CpuFault> {
+        for _ in 0..self.instructions_per_frame {
+            self.cycle()?;
+        }
+        self.end_frame();
+        Ok(())
+    }
+
+    /// Ticks the timers, for callers that run the cycles of a frame one at
+    /// a time.
+    pub fn end_frame(&mut self) {
+        self.cpu.tick();
+        self.frame += 1;
+    }
+
+    /// Runs a single instruction, applying the fault policy. Returns the
+    /// fault if the policy asked to break on it.
+    pub fn cycle(&mut self) -> Result<(), CpuFault> {
+        let traced = self.traced();
+        let before = *self.cpu.v();
+        let mut ctx = CpuContext {
+            gpu: &mut self.gpu,
+            keypad: &mut self.keypad
+        };
+        if let Err(fault) = self.cpu.cycle(&mut ctx) {
+            self.fault = Some(fault);
+            match self.faults.action(&fault) {
+                FaultAction::Halt => self.cpu.halt(),
+                FaultAction::Ignore => (),
+                FaultAction::Break => return Err(fault)
+            }
+        }
+        if let (Some((pc, instruction)), Some(tracer)) = (traced, self.tracer.as_mut()) {
+            let record = Record::new(self.frame, pc, instruction, &before, self.cpu.v(), self.cpu.i());
+            tracer.trace(&record);
+        }
+        Ok(())
+    }
+
+    /// The address and instruction about to run, if the tracer wants them.
+    fn traced(&self) -> Option<(u16, Instruction)> {
+        let tracer = self.tracer.as_ref()?;
+        if self.cpu.halted() {
+            return None;
+        }
+        let pc = self.cpu.pc();
+        let instruction = self.cpu.instruction_at(pc).ok()?;
+        tracer.filter.matches(self.frame, pc, &instruction).then_some((pc, instruction))
+    }
+
+}
@@ -1,12 +1,289 @@
-#[macro_use] mod log;
-mod cpu;
-mod gpu;
-mod timer;
+mod options;
+#[cfg(feature = "window")]
 mod chip;
-mod keypad;
+#[cfg(feature = "window")]
+mod keymap;
+#[cfg(feature = "window")]
+mod overlay;
 
+use std::io::{self, BufReader};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process;
+use chip8::{asm, console, dap, disasm, headless, image, json};
+use chip8::console::Console;
+use chip8::machine::Machine;
+use chip8::debugger::{Debugger, Trigger};
+use chip8::octo::Program;
+use chip8::headless::Status;
+use chip8::movie::{Movie, Player, Recorder};
+use chip8::rng::Vip;
+use chip8::trace::{self, Tracer};
+use chip8::diff::{self, Run, Side};
+use options::{Command, Options, Platform, USAGE};
+#[cfg(feature = "window")]
 use chip::Chip;
+#[cfg(feature = "window")]
+use keymap::Keymap;
 
 fn main() {
-    Chip::execute().unwrap();
-}
\ No newline at end of file
+    let mut options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("chip8: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+    if let Err(e) = options.load_user_config() {
+        eprintln!("chip8: {}", e);
+        process::exit(1);
+    }
+    let result = match options.command {
+        Command::Window => run(&options),
+        Command::Headless => run_headless(&options),
+        Command::Diff => run_diff(&options),
+        Command::Dap => run_dap(&options),
+        Command::Disasm => run_disasm(&options),
+        Command::Asm => run_asm(&options)
+    };
+    match result {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            eprintln!("chip8: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn machine(options: &Options, program: &Program) -> Result<Machine, String> {
+    let mut machine = Machine::new();
+    machine.set_quirks(options.platform.quirks());
+    machine.set_instructions_per_frame(options.instructions_per_frame);
+    machine.gpu.palette = options.palette;
+    if let Some(path) = &options.vip_rng {
+        machine.cpu.set_rng(Box::new(Vip::load(path)?));
+    }
+    if let Some(seed) = options.seed {
+        machine.cpu.seed(seed);
+    }
+    if let Some(path) = &options.trace {
+        let format = options.trace_format.unwrap_or_else(|| trace::Format::from_path(path));
+        machine.set_tracer(Tracer::create(path, format, options.trace_filter.clone())?);
+    }
+    machine.load(&program.rom);
+    Ok(machine)
+}
+
+/// A debugger stopping at the program's `:breakpoint`s.
+fn debugger(program: &Program) -> Debugger {
+    let mut debugger = Debugger::new();
+    for (_, addr) in program.breakpoints.iter() {
+        debugger.add(Trigger::Breakpoint { addr: *addr, condition: None });
+    }
+    debugger
+}
+
+#[cfg(feature = "window")]
+fn run(options: &Options) -> Result<i32, String> {
+    let program = options.read_program()?;
+    let mut machine = machine(options, &program)?;
+    let player = match &options.replay {
+        Some(path) => Some(Player::start(&mut machine, Movie::load(path)?)?),
+        None => None
+    };
+    let title = options.rom.file_name()
+        .map_or(String::from("chip-8"), |name| name.to_string_lossy().into_owned());
+    let keymap = match &options.keymap {
+        Some(path) => Keymap::load(path, &title)?,
+        None => Keymap::load_user(&title)?
+    };
+    let mut chip = Chip::new(machine);
+    chip.set_debugger(debugger(&program), program.monitors);
+    chip.set_paused(options.paused);
+    chip.set_overlay(options.debug_panel);
+    if options.console {
+        chip.attach_console();
+    }
+    chip.set_keymap(keymap);
+    chip.set_state_path(options.state_path());
+    chip.set_rewind_budget(options.rewind);
+    chip.set_beeper(chip8::audio::beeper(options.tone));
+    chip.set_patterns(options.platform == Platform::Xochip);
+    if let Some(player) = player {
+        chip.replay(player);
+    }
+    if let Some(path) = &options.record {
+        chip.record(path.clone(), options.seed.unwrap_or_else(rand::random));
+    }
+    Chip::execute(chip, &title, options.scale).map_err(|e| e.to_string())?;
+    Ok(0)
+}
+
+#[cfg(not(feature = "window"))]
+fn run(_options: &Options) -> Result<i32, String> {
+    Err(String::from("built without window support, use 'chip8 headless'"))
+}
+
+/// Starts <machine> from the state given with --state, if any.
+fn load_state(machine: &mut Machine, options: &Options) -> Result<(), String> {
+    if let Some(path) = &options.state {
+        let state = std::fs::read(path)
+            .map_err(|e| format!("can't read save state '{}': {}", path.display(), e))?;
+        machine.load_state(&state).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+fn run_headless(options: &Options) -> Result<i32, String> {
+    let program = options.read_program()?;
+    let mut machine = machine(options, &program)?;
+    load_state(&mut machine, options)?;
+    if options.console {
+        let mut console = Console::new();
+        console.set_monitors(program.monitors.clone());
+        let stdin = io::stdin();
+        console::run(&mut console, &mut debugger(&program), &mut machine, &mut stdin.lock(), &mut io::stdout(), options.frames)
+            .map_err(|e| format!("console: {}", e))?;
+        machine.finish_trace()?;
+        return Ok(0);
+    }
+    let status = match (&options.record, &options.replay) {
+        (Some(path), _) => {
+            let seed = options.seed.unwrap_or_else(rand::random);
+            let mut recorder = match options.state {
+                Some(_) => Recorder::resume(&mut machine, seed),
+                None => Recorder::start(&mut machine, seed)
+            };
+            let status = headless::run(&mut machine, options.frames, &options.presses, Some(&mut recorder));
+            recorder.movie().save(path)?;
+            status
+        },
+        (None, Some(path)) => {
+            let mut player = Player::start(&mut machine, Movie::load(path)?)?;
+            headless::replay(&mut machine, &mut player).map_err(|e| format!("{}: {}", path.display(), e))?
+        },
+        (None, None) => headless::run(&mut machine, options.frames, &options.presses, None)
+    };
+    machine.finish_trace()?;
+    if let Status::Faulted(fault) = status {
+        eprintln!("chip8: {} at {:#06x}", fault, machine.cpu.pc());
+    }
+    match &options.dump {
+        Some(path) => headless::dump(&machine, path, options.format)?,
+        None => print!("{}", image::ascii(&machine.framebuffer()))
+    }
+    Ok(status.code())
+}
+
+fn run_diff(options: &Options) -> Result<i32, String> {
+    let movie = match &options.replay {
+        Some(path) => Some(Movie::load(path)?),
+        None => None
+    };
+    let inputs = match &movie {
+        Some(movie) => movie.inputs.clone(),
+        None => headless::inputs(&options.presses, options.frames)
+    };
+    let run = |options: &Options| -> Result<Side, String> {
+        let mut machine = machine(options, &options.read_program()?)?;
+        load_state(&mut machine, options)?;
+        if let Some(movie) = &movie {
+            movie.check(&machine)?;
+            machine.set_instructions_per_frame(movie.instructions_per_frame);
+            movie.restart(&mut machine)?;
+        }
+        Ok(Side::Run(Box::new(Run::new(machine, inputs.clone()))))
+    };
+    let mut a = run(options)?;
+    let mut b = match (options.against, &options.reference) {
+        (Some(platform), _) => {
+            println!("a: {}\nb: {}", options.platform.name(), platform.name());
+            run(&Options { platform, trace: None, ..options.clone() })?
+        },
+        (None, Some(path)) => {
+            println!("a: {}\nb: {}", options.platform.name(), path.display());
+            Side::Trace(trace::read(path)?.into_iter())
+        },
+        (None, None) => unreachable!("options require --against or --reference")
+    };
+    let divergence = diff::diff(&mut a, &mut b, options.context);
+    if let Side::Run(run) = &mut a {
+        run.machine.finish_trace()?;
+    }
+    match divergence {
+        Some(divergence) => {
+            print!("\n{}", divergence);
+            Ok(4)
+        },
+        None => {
+            println!("\nno differences");
+            Ok(0)
+        }
+    }
+}
+
+fn run_dap(options: &Options) -> Result<i32, String> {
+    let defaults = options.clone();
+    let launcher = Box::new(move |arguments: &json::Value| {
+        let mut options = defaults.clone();
+        if let Some(program) = arguments.get("program").as_str() {
+            options.rom = PathBuf::from(program);
+        }
+        if let Some(name) = arguments.get("platform").as_str() {
+            options.platform = Platform::parse(name).ok_or(format!("unknown platform '{}'", name))?;
+        }
+        if options.rom.as_os_str().is_empty() {
+            return Err(String::from("launch needs a program"));
+        }
+        let mut machine = machine(&options, &options.read_program()?)?;
+        load_state(&mut machine, &options)?;
+        Ok(machine)
+    });
+    match options.port {
+        Some(port) => {
+            let listener = TcpListener::bind(("127.0.0.1", port))
+                .map_err(|e| format!("can't listen on port {}: {}", port, e))?;
+            eprintln!("listening on 127.0.0.1:{}", port);
+            let (stream, _) = listener.accept().map_err(|e| e.to_string())?;
+            let input = stream.try_clone().map_err(|e| e.to_string())?;
+            dap::Server::new(launcher, Box::new(stream)).serve(Box::new(BufReader::new(input)))?;
+        },
+        None => {
+            let server = dap::Server::new(launcher, Box::new(io::stdout()));
+            server.serve(Box::new(BufReader::new(io::stdin())))?;
+        }
+    }
+    Ok(0)
+}
+
+fn run_disasm(options: &Options) -> Result<i32, String> {
+    let listing = disasm::disassemble(&options.read_program()?.rom, options.syntax);
+    match &options.dump {
+        Some(path) => std::fs::write(path, listing)
+            .map_err(|e| format!("can't write '{}': {}", path.display(), e))?,
+        None => print!("{}", listing)
+    }
+    Ok(0)
+}
+
+fn run_asm(options: &Options) -> Result<i32, String> {
+    let assembly = asm::assemble_file(&options.rom, options.platform.extension())?;
+    let max = options.platform.max_rom_size();
+    if assembly.rom.len() > max {
+        return Err(format!("the ROM is {} bytes, but at most {} fit in memory", assembly.rom.len(), max));
+    }
+    let path = options.dump.clone().unwrap_or_else(|| options.rom.with_extension("ch8"));
+    std::fs::write(&path, &assembly.rom)
+        .map_err(|e| format!("can't write '{}': {}", path.display(), e))?;
+    if let Some(map) = &options.map {
+        let mut entries = assembly.map;
+        entries.relative_to(map.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or_else(|| Path::new(".")));
+        std::fs::write(map, entries.to_string())
+            .map_err(|e| format!("can't write '{}': {}", map.display(), e))?;
+    }
+    Ok(0)
+}
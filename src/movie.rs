@@ -0,0 +1,304 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::machine::Machine;
+use crate::quirks::Quirks;
+use crate::fault::CpuFault;
+use crate::state::{Reader, Writer};
+
+/// Identifies a movie file.
+const MAGIC: &[u8; 4] = b"C8MV";
+
+/// The movie format version.
+pub const VERSION: u16 = 3;
+
+/// Frames between the state hashes checked on replay.
+pub const HASH_INTERVAL: u32 = 60;
+
+/// A recording of the keypad, frame by frame, along with everything else
+/// needed to replay it exactly: the ROM, configuration, RNG seed and the
+/// state recording started from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Movie {
+    /// A hash of the ROM the movie was recorded with.
+    pub rom: u64,
+    pub quirks: Quirks,
+    pub instructions_per_frame: u32,
+    /// The name of the random number generator.
+    pub rng: String,
+    pub seed: u64,
+    /// The save state recording started from, or None for a freshly reset
+    /// machine.
+    pub start: Option<Vec<u8>>,
+    /// The keypad bits held during each frame.
+    pub inputs: Vec<u16>,
+    /// A hash of the machine state after every `HASH_INTERVAL` frames.
+    pub hashes: Vec<u64>
+}
+
+impl Movie {
+
+    pub fn parse(data: &[u8]) -> Result<Movie, String> {
+        let mut reader = Reader::new(data);
+        reader.header(MAGIC, VERSION, "movie")?;
+        let rom = reader.u64()?;
+        let mut quirks = Quirks::default();
+        quirks.restore(&mut reader)?;
+        let instructions_per_frame = reader.u32()?;
+        let len = reader.u8()? as usize;
+        let rng = String::from_utf8_lossy(reader.bytes(len)?).into_owned();
+        let seed = reader.u64()?;
+        let start = match reader.u32()? as usize {
+            0 => None,
+            len => Some(reader.bytes(len)?.to_vec())
+        };
+        let frames = reader.u32()?;
+        let inputs = (0..frames).map(|_| reader.u16()).collect::<Result<_, _>>()?;
+        let hashes = (0..frames / HASH_INTERVAL).map(|_| reader.u64()).collect::<Result<_, _>>()?;
+        reader.finish()?;
+        Ok(Movie { rom, quirks, instructions_per_frame, rng, seed, start, inputs, hashes })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.header(MAGIC, VERSION);
+        writer.u64(self.rom);
+        self.quirks.save(&mut writer);
+        writer.u32(self.instructions_per_frame);
+        writer.u8(self.rng.len() as u8);
+        writer.bytes(self.rng.as_bytes());
+        writer.u64(self.seed);
+        match &self.start {
+            Some(state) => {
+                writer.u32(state.len() as u32);
+                writer.bytes(state);
+            },
+            None => writer.u32(0)
+        }
+        writer.u32(self.inputs.len() as u32);
+        for &input in self.inputs.iter() {
+            writer.u16(input);
+        }
+        for &hash in self.hashes.iter() {
+            writer.u64(hash);
+        }
+        writer.finish()
+    }
+
+    pub fn load(path: &Path) -> Result<Movie, String> {
+        let data = fs::read(path)
+            .map_err(|e| format!("can't read movie '{}': {}", path.display(), e))?;
+        Movie::parse(&data).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_bytes())
+            .map_err(|e| format!("can't write movie '{}': {}", path.display(), e))
+    }
+
+    /// Checks that <machine> has the ROM and random number generator the
+    /// movie was recorded with.
+    pub fn check(&self, machine: &Machine) -> Result<(), String> {
+        if hash(machine.rom()) != self.rom {
+            return Err(String::from("the movie was recorded with a different ROM"));
+        }
+        if machine.cpu.rng().name() != self.rng {
+            return Err(format!("the movie was recorded with the {} random number generator", self.rng));
+        }
+        Ok(())
+    }
+
+    /// Puts <machine> in the state recording started from, leaving its
+    /// configuration alone.
+    pub fn restart(&self, machine: &mut Machine) -> Result<(), String> {
+        match &self.start {
+            Some(state) => { machine.load_state(state)?; },
+            None => machine.reset()
+        }
+        machine.cpu.seed(self.seed);
+        Ok(())
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.inputs.len() as u32
+    }
+
+}
+
+/// Records a movie by running the machine a frame at a time.
+pub struct Recorder {
+    movie: Movie
+}
+
+impl Recorder {
+
+    /// Resets <machine> and starts recording from power on.
+    pub fn start(machine: &mut Machine, seed: u64) -> Self {
+        machine.reset();
+        Recorder::record(machine, seed, None)
+    }
+
+    /// Starts recording from the current state of <machine>.
+    pub fn resume(machine: &mut Machine, seed: u64) -> Self {
+        let state = machine.save_state(Duration::from_secs(0));
+        Recorder::record(machine, seed, Some(state))
+    }
+
+    fn record(machine: &mut Machine, seed: u64, start: Option<Vec<u8>>) -> Self {
+        machine.cpu.seed(seed);
+        Recorder {
+            movie: Movie {
+                rom: hash(machine.rom()),
+                quirks: machine.quirks(),
+                instructions_per_frame: machine.instructions_per_frame(),
+                rng: String::from(machine.cpu.rng().name()),
+                seed,
+                start,
+                inputs: Vec::new(),
+                hashes: Vec::new()
+            }
+        }
+    }
+
+    /// Runs a frame with the keypad as it is, recording it.
+    pub fn frame(&mut self, machine: &mut Machine) -> Result<(), CpuFault> {
+        self.movie.inputs.push(machine.keypad.bits());
+        let result = machine.frame();
+        if self.movie.frames().is_multiple_of(HASH_INTERVAL) {
+            self.movie.hashes.push(state_hash(machine));
+        }
+        result
+    }
+
+    /// Forgets the most recent frame, after the machine was rewound to the
+    /// state before it.
+    pub fn rewind(&mut self) {
+        self.movie.inputs.pop();
+        self.movie.hashes.truncate((self.movie.frames() / HASH_INTERVAL) as usize);
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+}
+
+/// Plays a movie back, feeding its inputs to the machine.
+pub struct Player {
+    movie: Movie,
+    frame: u32
+}
+
+impl Player {
+
+    /// Configures <machine> as the movie was recorded and puts it in the
+    /// starting state. Fails if <machine> has a different ROM loaded.
+    pub fn start(machine: &mut Machine, movie: Movie) -> Result<Self, String> {
+        movie.check(machine)?;
+        machine.set_quirks(movie.quirks);
+        machine.set_instructions_per_frame(movie.instructions_per_frame);
+        movie.restart(machine)?;
+        Ok(Player { movie, frame: 0 })
+    }
+
+    /// Runs the next frame of the movie. Returns false once the movie has
+    /// ended, or an error if the machine faulted or desynchronized from the
+    /// recording.
+    pub fn frame(&mut self, machine: &mut Machine) -> Result<bool, String> {
+        let input = match self.movie.inputs.get(self.frame as usize) {
+            Some(&input) => input,
+            None => return Ok(false)
+        };
+        machine.keypad.set_bits(input);
+        machine.frame().map_err(|fault| format!("{} at frame {}", fault, self.frame))?;
+        self.frame += 1;
+        if self.frame.is_multiple_of(HASH_INTERVAL) {
+            let expected = self.movie.hashes[(self.frame / HASH_INTERVAL - 1) as usize];
+            if state_hash(machine) != expected {
+                return Err(format!("desync by frame {}", self.frame));
+            }
+        }
+        Ok(true)
+    }
+
+    /// The number of frames played so far.
+    pub fn position(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn finished(&self) -> bool {
+        self.frame >= self.movie.frames()
+    }
+
+}
+
+fn state_hash(machine: &Machine) -> u64 {
+    hash(&machine.save_state(Duration::from_secs(0)))
+}
+
+/// 64-bit FNV-1a.
+fn hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0200: rnd v0, 3f   0202: rnd v1, 1f   0204: ld v2, 4
+    // 0206: sknp v2      0208: drw v0, v1, 1   020a: jp 0200
+    const ROM: [u8; 12] = [0xc0, 0x3f, 0xc1, 0x1f, 0x62, 0x04, 0xe2, 0xa1, 0xd0, 0x11, 0x12, 0x00];
+
+    fn record(seed: u64) -> (Movie, Vec<u8>) {
+        let mut machine = Machine::new();
+        machine.load(&ROM);
+        let mut recorder = Recorder::start(&mut machine, seed);
+        for frame in 0..150 {
+            machine.keypad.set(4, frame % 7 < 3);
+            recorder.frame(&mut machine).unwrap();
+        }
+        (recorder.movie().clone(), machine.framebuffer().pixels.to_vec())
+    }
+
+    #[test]
+    fn replay() {
+        let (movie, pixels) = record(42);
+        assert_eq!(movie.hashes.len(), 2);
+        let movie = Movie::parse(&movie.to_bytes()).unwrap();
+
+        let mut machine = Machine::new();
+        machine.load(&ROM);
+        let mut player = Player::start(&mut machine, movie).unwrap();
+        while player.frame(&mut machine).unwrap() {}
+        assert_eq!(player.position(), 150);
+        assert_eq!(machine.framebuffer().pixels, &pixels[..]);
+    }
+
+    #[test]
+    fn desync() {
+        let (mut movie, _) = record(42);
+        movie.seed = 43;
+        let mut machine = Machine::new();
+        machine.load(&ROM);
+        let mut player = Player::start(&mut machine, movie).unwrap();
+        let result = loop {
+            match player.frame(&mut machine) {
+                Ok(true) => (),
+                result => break result
+            }
+        };
+        assert_eq!(result, Err(String::from("desync by frame 60")));
+    }
+
+    #[test]
+    fn wrong_rom() {
+        let (movie, _) = record(42);
+        let mut machine = Machine::new();
+        machine.load(&ROM[..10]);
+        assert!(Player::start(&mut machine, movie).is_err());
+    }
+
+}
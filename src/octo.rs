@@ -0,0 +1,1081 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use crate::machine::Machine;
+use crate::sourcemap::SourceMap;
+
+/// Where ROMs are loaded.
+const START: usize = 0x200;
+
+/// How deeply macros may expand inside each other, which also catches a
+/// macro using itself.
+const MAX_DEPTH: u8 = 16;
+
+/// A compiled program, with the debugging aids its source asked for.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Program {
+    pub rom: Vec<u8>,
+    pub map: SourceMap,
+    /// The `:breakpoint`s, by name and address.
+    pub breakpoints: Vec<(String, u16)>,
+    pub monitors: Vec<Monitor>
+}
+
+/// Memory a `:monitor` directive asks to be shown while debugging.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Monitor {
+    /// The address as written in the source, usually a label.
+    pub name: String,
+    pub addr: u16,
+    pub format: Format
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Format {
+    /// This many bytes, in hex.
+    Bytes(u16),
+    /// A format string, where `%i`, `%x`, `%b` and `%c` show a byte in
+    /// decimal, hex, binary or as a character. A digit after the `%` reads
+    /// that many bytes as one big-endian number, so `%2i` shows a 16-bit
+    /// number.
+    Text(String)
+}
+
+impl Monitor {
+
+    /// The monitored memory, as `<name>: <values>`.
+    pub fn show(&self, machine: &Machine) -> String {
+        let memory = machine.cpu.memory();
+        let byte = |offset: usize| memory[(self.addr as usize + offset) % memory.len()];
+        let mut text = format!("{}: ", self.name);
+        match &self.format {
+            Format::Bytes(len) => {
+                let bytes: Vec<String> = (0..*len as usize).map(|n| format!("{:02x}", byte(n))).collect();
+                text.push_str(&bytes.join(" "));
+            },
+            Format::Text(format) => {
+                let mut offset = 0;
+                let mut chars = format.chars().peekable();
+                while let Some(c) = chars.next() {
+                    if c != '%' {
+                        text.push(c);
+                        continue;
+                    }
+                    let width = chars.next_if(char::is_ascii_digit).and_then(|digit| digit.to_digit(10)).unwrap_or(1) as usize;
+                    let value = (offset..offset + width).fold(0u64, |value, n| value << 8 | byte(n) as u64);
+                    match chars.next() {
+                        Some('i') => write!(text, "{}", value).unwrap(),
+                        Some('x') => write!(text, "{:x}", value).unwrap(),
+                        Some('b') => write!(text, "{:0width$b}", value, width = width * 8).unwrap(),
+                        Some('c') => text.push(value as u8 as char),
+                        Some(c) => {
+                            text.push('%');
+                            text.push(c);
+                            continue;
+                        },
+                        None => {
+                            text.push('%');
+                            break;
+                        }
+                    }
+                    offset += width;
+                }
+            }
+        }
+        text
+    }
+
+}
+
+/// Whether <path> names Octo source, by its `.8o` extension.
+pub fn is_source(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "8o")
+}
+
+/// Compiles the Octo source file at <path>.
+pub fn compile_file(path: &Path) -> Result<Program, String> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| format!("can't read '{}': {}", path.display(), e))?;
+    compile(&source, path)
+}
+
+/// Compiles Octo <source>, which <path> names in errors and the source
+/// map. Execution starts at `: main`; unless that's the first thing in the
+/// program, 0x200 holds a jump to it. The first error stops compilation,
+/// and is reported as `<file>:<line>: <message>`.
+pub fn compile(source: &str, path: &Path) -> Result<Program, String> {
+    let mut compiler = Compiler {
+        path,
+        tokens: tokens(source),
+        line: 1,
+        rom: Vec::new(),
+        here: START,
+        started: false,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+        then: None,
+        next: None,
+        program: Program::default()
+    };
+    compiler.tokens.reverse();
+    compiler.run().map_err(|e| format!("{}:{}: {}", path.display(), compiler.line, e))?;
+    compiler.program.rom = compiler.rom;
+    Ok(compiler.program)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Token {
+    text: String,
+    line: u32,
+    /// How many macro expansions produced the token.
+    depth: u8
+}
+
+/// Splits source into words, leaving `"` quoted strings whole and
+/// dropping `#` comments, which may start mid-word. Braces and parentheses
+/// are words of their own.
+fn tokens(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (n, line) in source.lines().enumerate() {
+        let mut chars = line.chars().peekable();
+        let mut token = |text: String| tokens.push(Token { text, line: n as u32 + 1, depth: 0 });
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c == '#' {
+                break;
+            } else if c == '"' {
+                let mut text = String::new();
+                text.push(chars.next().unwrap());
+                for c in chars.by_ref() {
+                    text.push(c);
+                    if c == '"' {
+                        break;
+                    }
+                }
+                token(text);
+            } else if "{}()".contains(c) {
+                chars.next();
+                token(c.to_string());
+            } else {
+                let mut text = String::new();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '#' && !"{}()".contains(*c)) {
+                    text.push(c);
+                }
+                token(text);
+            }
+        }
+    }
+    tokens
+}
+
+#[derive(Clone, Debug)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>
+}
+
+/// How to fill in an address once its label is defined.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Fixup {
+    /// The low 12 bits of the instruction at the address.
+    Nnn,
+    /// The 16 bits at the address.
+    Word,
+    /// The byte after the address gets <nibble> and the label's top 4 bits.
+    High(u8),
+    /// The byte after the address gets the label's low 8 bits.
+    Low
+}
+
+/// An open `begin`, `else` or `loop`, with the lines they start on.
+#[derive(Clone, Debug)]
+enum Block {
+    /// The jump past the body when the condition is false.
+    Begin { jump: usize, line: u32 },
+    /// The jump from the end of the `begin` part past the `else` part.
+    Else { jump: usize, line: u32 },
+    /// The loop's start, and the jumps out of it from `while`s.
+    Loop { start: usize, exits: Vec<usize>, line: u32 }
+}
+
+/// A condition as the instructions that test it: any needed before the
+/// skip, then the skip to use when the next instruction should run if it
+/// holds, and the one to use when it should run if it doesn't.
+struct Condition {
+    prelude: Vec<u16>,
+    skip_unless: u16,
+    skip_if: u16
+}
+
+struct Compiler<'a> {
+    path: &'a Path,
+    /// The tokens left to compile, in reverse order.
+    tokens: Vec<Token>,
+    /// The line being compiled.
+    line: u32,
+    rom: Vec<u8>,
+    here: usize,
+    /// Whether anything has been placed yet, which settles whether 0x200
+    /// needs a jump to main.
+    started: bool,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    /// Addresses to fill in at the end, with the label and its line.
+    fixups: Vec<(usize, Fixup, String, u32)>,
+    blocks: Vec<Block>,
+    /// Where the instruction guarded by `then` starts.
+    then: Option<usize>,
+    /// The name `:next` gives the next instruction's second byte.
+    next: Option<String>,
+    program: Program
+}
+
+impl<'a> Compiler<'a> {
+
+    fn run(&mut self) -> Result<(), String> {
+        while let Some(token) = self.tokens.pop() {
+            self.line = token.line;
+            let then = self.then.take();
+            self.statement(token)?;
+            // statements like :const and macro uses don't place anything,
+            // and leave the guard waiting for an instruction. One written
+            // as two bytes is placed a byte at a time.
+            match then {
+                Some(start) if self.here == start || self.here == start + 1 => self.then = Some(start),
+                Some(start) if self.here == start + 2 => (),
+                // the skip steps over both words of `i := long`
+                Some(start) if self.here == start + 4 && self.rom[start - START..start - START + 2] == [0xf0, 0x00] => (),
+                Some(_) => {
+                    return Err(String::from("'then' must be followed by a single instruction"));
+                },
+                None => ()
+            }
+        }
+        if let Some(block) = self.blocks.pop() {
+            let (word, line) = match block {
+                Block::Begin { line, .. } => ("begin", line),
+                Block::Else { line, .. } => ("else", line),
+                Block::Loop { line, .. } => ("loop", line)
+            };
+            self.line = line;
+            return Err(format!("'{}' is missing its '{}'", word, if word == "loop" { "again" } else { "end" }));
+        }
+        if !self.labels.contains_key("main") {
+            return Err(String::from("the program has no ': main'"));
+        }
+        for (addr, fixup, name, line) in std::mem::take(&mut self.fixups) {
+            self.line = line;
+            let value = match self.labels.get(&name) {
+                Some(&value) => value,
+                None => return Err(format!("undefined name '{}'", name))
+            };
+            let offset = addr - START;
+            match fixup {
+                Fixup::Nnn | Fixup::High(_) | Fixup::Low if value > 0xfff => return Err(format!("'{}' at {:#x} is out of reach of a 12-bit address", name, value)),
+                Fixup::Nnn => {
+                    self.rom[offset] = self.rom[offset] & 0xf0 | (value >> 8) as u8;
+                    self.rom[offset + 1] = value as u8;
+                },
+                Fixup::Word => self.rom[offset..offset + 2].copy_from_slice(&value.to_be_bytes()),
+                Fixup::High(nibble) => self.rom[offset + 1] = nibble << 4 | (value >> 8 & 0xf) as u8,
+                Fixup::Low => self.rom[offset + 1] = value as u8
+            }
+        }
+        Ok(())
+    }
+
+    fn token(&mut self) -> Result<Token, String> {
+        self.tokens.pop().ok_or_else(|| String::from("unexpected end of file"))
+    }
+
+    fn word(&mut self) -> Result<String, String> {
+        self.token().map(|token| token.text)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.word()? {
+            word if word == expected => Ok(()),
+            word => Err(format!("expected '{}', got '{}'", expected, word))
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.last().map(|token| token.text.as_str())
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), String> {
+        let word = token.text.as_str();
+        match word {
+            ":const" | ":alias" | ":macro" | ":calc" | ":proto" | ":monitor" | ":" => (),
+            _ if self.macros.contains_key(word) => (),
+            _ => self.start(false)?
+        }
+        match word {
+            ":" => {
+                let name = self.name()?;
+                self.start(name == "main")?;
+                self.label(name, self.here)
+            },
+            ":const" => {
+                let name = self.name()?;
+                let value = self.word()?;
+                let value = self.number(&value)?.ok_or(format!("undefined name '{}'", value))?;
+                self.constant(name, value)
+            },
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.word()?;
+                let register = self.register(&register)?;
+                self.aliases.insert(name, register);
+                Ok(())
+            },
+            ":macro" => {
+                let name = self.name()?;
+                let mut params = Vec::new();
+                loop {
+                    match self.word()? {
+                        open if open == "{" => break,
+                        param => params.push(param)
+                    }
+                }
+                let body = self.braces()?;
+                self.macros.insert(name, Macro { params, body });
+                Ok(())
+            },
+            ":calc" => {
+                let name = self.name()?;
+                self.expect("{")?;
+                let expression = self.braces()?;
+                let value = self.calc(&expression)?;
+                self.constant(name, value)
+            },
+            ":byte" => {
+                let value = match self.peek() {
+                    Some("{") => {
+                        self.token()?;
+                        let expression = self.braces()?;
+                        self.calc(&expression)?
+                    },
+                    _ => {
+                        let value = self.word()?;
+                        self.defined(&value)?
+                    }
+                };
+                let byte = byte(value)?;
+                self.emit(&[byte])
+            },
+            ":pointer" => {
+                let addr = self.address(self.here, Fixup::Word, 0xffff)?;
+                self.emit(&addr.to_be_bytes())
+            },
+            ":org" => {
+                let value = self.word()?;
+                self.here = range(self.defined(&value)?, START as f64, 0xffff as f64)? as usize;
+                Ok(())
+            },
+            ":call" => self.address_instruction(0x2000),
+            ":unpack" => {
+                let nibble = self.word()?;
+                let nibble = range(self.defined(&nibble)?, 0.0, 15.0)? as u8;
+                let value = self.address_token()?;
+                let known = self.lookup(&value, self.here, Fixup::High(nibble));
+                let addr = match known {
+                    Some(addr) => range(addr, 0.0, 0xfff as f64)? as u16,
+                    None => 0
+                };
+                self.instruction(0x6000 | (nibble as u16) << 4 | addr >> 8)?;
+                if known.is_none() {
+                    self.fixups.push((self.here, Fixup::Low, value, self.line));
+                }
+                self.instruction(0x6100 | addr & 0xff)
+            },
+            ":next" => {
+                self.next = Some(self.name()?);
+                Ok(())
+            },
+            ":breakpoint" => {
+                let name = self.word()?;
+                self.program.breakpoints.push((name, self.here as u16));
+                Ok(())
+            },
+            ":monitor" => {
+                let name = self.word()?;
+                let addr = range(self.defined(&name)?, 0.0, 0xffff as f64)? as u16;
+                let format = self.word()?;
+                let format = match format.strip_prefix('"').and_then(|text| text.strip_suffix('"')) {
+                    Some(text) => Format::Text(String::from(text)),
+                    None => Format::Bytes(range(self.defined(&format)?, 1.0, 0xffff as f64)? as u16)
+                };
+                self.program.monitors.push(Monitor { name, addr, format });
+                Ok(())
+            },
+            ":proto" => self.word().map(|_| ()),
+            ";" | "return" => self.instruction(0x00ee),
+            "clear" => self.instruction(0x00e0),
+            "scroll-left" => self.instruction(0x00fc),
+            "scroll-right" => self.instruction(0x00fb),
+            "exit" => self.instruction(0x00fd),
+            "lores" => self.instruction(0x00fe),
+            "hires" => self.instruction(0x00ff),
+            "audio" => self.instruction(0xf002),
+            "scroll-down" | "scroll-up" => {
+                let n = self.nibble()?;
+                self.instruction(if word == "scroll-down" { 0x00c0 } else { 0x00d0 } | n)
+            },
+            "plane" => {
+                let n = self.nibble()?;
+                self.instruction(0xf001 | n << 8)
+            },
+            "jump" => self.address_instruction(0x1000),
+            "jump0" => self.address_instruction(0xb000),
+            "native" => self.address_instruction(0x0000),
+            "bcd" => self.vx_instruction(0xf033),
+            "saveflags" => self.vx_instruction(0xf075),
+            "loadflags" => self.vx_instruction(0xf085),
+            "save" | "load" => {
+                let x = self.vx()?;
+                match self.peek() {
+                    Some("-") => {
+                        self.token()?;
+                        let y = self.vx()?;
+                        self.instruction(if word == "save" { 0x5002 } else { 0x5003 } | x << 8 | y << 4)
+                    },
+                    _ => self.instruction(if word == "save" { 0xf055 } else { 0xf065 } | x << 8)
+                }
+            },
+            "sprite" => {
+                let x = self.vx()?;
+                let y = self.vx()?;
+                let n = self.nibble()?;
+                self.instruction(0xd000 | x << 8 | y << 4 | n)
+            },
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let opcode = match word {
+                    "delay" => 0xf015,
+                    "buzzer" => 0xf018,
+                    _ => 0xf03a
+                };
+                self.vx_instruction(opcode)
+            },
+            "i" => match self.word()?.as_str() {
+                ":=" => match self.peek() {
+                    Some("hex") | Some("bighex") => {
+                        let opcode = if self.word()? == "hex" { 0xf029 } else { 0xf030 };
+                        self.vx_instruction(opcode)
+                    },
+                    Some("long") => {
+                        self.token()?;
+                        // the address is the word after the f000
+                        let addr = self.address(self.here + 2, Fixup::Word, 0xffff)?;
+                        self.instruction(0xf000)?;
+                        self.emit(&addr.to_be_bytes())
+                    },
+                    _ => self.address_instruction(0xa000)
+                },
+                "+=" => self.vx_instruction(0xf01e),
+                operator => Err(format!("expected ':=' or '+=' after i, got '{}'", operator))
+            },
+            "if" => {
+                let condition = self.condition()?;
+                for opcode in condition.prelude.iter() {
+                    self.instruction(*opcode)?;
+                }
+                match self.word()?.as_str() {
+                    "then" => {
+                        self.instruction(condition.skip_unless)?;
+                        self.then = Some(self.here);
+                        Ok(())
+                    },
+                    "begin" => {
+                        self.instruction(condition.skip_if)?;
+                        let jump = self.here;
+                        self.instruction(0x1000)?;
+                        self.blocks.push(Block::Begin { jump, line: self.line });
+                        Ok(())
+                    },
+                    word => Err(format!("expected 'then' or 'begin', got '{}'", word))
+                }
+            },
+            "else" => match self.blocks.pop() {
+                Some(Block::Begin { jump: past, .. }) => {
+                    let jump = self.here;
+                    self.instruction(0x1000)?;
+                    self.patch(past, self.here);
+                    self.blocks.push(Block::Else { jump, line: self.line });
+                    Ok(())
+                },
+                _ => Err(String::from("'else' without 'begin'"))
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::Begin { jump, .. }) | Some(Block::Else { jump, .. }) => {
+                    self.patch(jump, self.here);
+                    Ok(())
+                },
+                _ => Err(String::from("'end' without 'begin'"))
+            },
+            "loop" => {
+                self.blocks.push(Block::Loop { start: self.here, exits: Vec::new(), line: self.line });
+                Ok(())
+            },
+            "while" => {
+                let condition = self.condition()?;
+                for opcode in condition.prelude.iter() {
+                    self.instruction(*opcode)?;
+                }
+                self.instruction(condition.skip_if)?;
+                let jump = self.here;
+                let exits = self.blocks.iter_mut().rev().find_map(|block| match block {
+                    Block::Loop { exits, .. } => Some(exits),
+                    _ => None
+                });
+                match exits {
+                    Some(exits) => exits.push(jump),
+                    None => return Err(String::from("'while' outside a loop"))
+                }
+                self.instruction(0x1000)
+            },
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, exits, .. }) => {
+                    self.instruction(0x1000 | start as u16)?;
+                    for exit in exits {
+                        self.patch(exit, self.here);
+                    }
+                    Ok(())
+                },
+                _ => Err(String::from("'again' without 'loop'"))
+            },
+            _ if self.is_register(word) => {
+                let x = self.register(word)? as u16;
+                self.assignment(x)
+            },
+            _ if self.macros.contains_key(word) => self.expand(&token),
+            // other names are subroutines, which may come later
+            _ if is_name(word) && !self.constants.contains_key(word) => {
+                self.tokens.push(token);
+                self.address_instruction(0x2000)
+            },
+            _ => match self.number(word)? {
+                Some(value) => {
+                    let byte = byte(value)?;
+                    self.emit(&[byte])
+                },
+                None => Err(format!("unexpected '{}'", word))
+            }
+        }
+    }
+
+    /// Compiles `v<x> <operator> <operand>`.
+    fn assignment(&mut self, x: u16) -> Result<(), String> {
+        let operator = self.word()?;
+        let operand = self.word()?;
+        let y = match self.is_register(&operand) {
+            true => Some(self.register(&operand)? as u16),
+            false => None
+        };
+        let xy = |opcode: u16, y: u16| opcode | x << 8 | y << 4;
+        let opcode = match (operator.as_str(), y, operand.as_str()) {
+            (":=", Some(y), _) => xy(0x8000, y),
+            ("|=", Some(y), _) => xy(0x8001, y),
+            ("&=", Some(y), _) => xy(0x8002, y),
+            ("^=", Some(y), _) => xy(0x8003, y),
+            ("+=", Some(y), _) => xy(0x8004, y),
+            ("-=", Some(y), _) => xy(0x8005, y),
+            (">>=", Some(y), _) => xy(0x8006, y),
+            ("=-", Some(y), _) => xy(0x8007, y),
+            ("<<=", Some(y), _) => xy(0x800e, y),
+            (":=", None, "key") => 0xf00a | x << 8,
+            (":=", None, "delay") => 0xf007 | x << 8,
+            (":=", None, "random") => {
+                let mask = self.word()?;
+                let mask = byte(self.defined(&mask)?)?;
+                0xc000 | x << 8 | mask as u16
+            },
+            (":=", None, _) => 0x6000 | x << 8 | byte(self.defined(&operand)?)? as u16,
+            ("+=", None, _) => 0x7000 | x << 8 | byte(self.defined(&operand)?)? as u16,
+            ("-=", None, _) => 0x7000 | x << 8 | byte(-self.defined(&operand)?)? as u16,
+            (_, None, _) if matches!(operator.as_str(), "|=" | "&=" | "^=" | ">>=" | "=-" | "<<=") => {
+                return Err(format!("{} needs a register, got '{}'", operator, operand));
+            },
+            _ => return Err(format!("unknown operator '{}'", operator))
+        };
+        self.instruction(opcode)
+    }
+
+    /// Parses `v<x> <comparison> <operand>` or `v<x> key`.
+    fn condition(&mut self) -> Result<Condition, String> {
+        let x = self.vx()?;
+        let comparison = self.word()?;
+        let skips = |skip_unless: u16, skip_if: u16| Condition { prelude: Vec::new(), skip_unless, skip_if };
+        match comparison.as_str() {
+            "key" => return Ok(skips(0xe0a1 | x << 8, 0xe09e | x << 8)),
+            "-key" => return Ok(skips(0xe09e | x << 8, 0xe0a1 | x << 8)),
+            _ => ()
+        }
+        let operand = self.word()?;
+        let y = match self.is_register(&operand) {
+            true => Some(self.register(&operand)? as u16),
+            false => None
+        };
+        let load = match y {
+            Some(y) => 0x8f00 | y << 4,
+            None => 0x6f00 | byte(self.defined(&operand)?)? as u16
+        };
+        let (equal, not_equal) = match y {
+            Some(y) => (0x5000 | x << 8 | y << 4, 0x9000 | x << 8 | y << 4),
+            None => (0x3000 | x << 8 | load & 0xff, 0x4000 | x << 8 | load & 0xff)
+        };
+        // the rest compare by subtracting in vf, whose carry is set when
+        // the subtraction doesn't borrow
+        let (subtract, carry) = match comparison.as_str() {
+            "==" => return Ok(skips(not_equal, equal)),
+            "!=" => return Ok(skips(equal, not_equal)),
+            // vf := v<x> - operand, carry when v<x> >= operand
+            "<" => (0x8f07 | x << 4, false),
+            ">=" => (0x8f07 | x << 4, true),
+            // vf := operand - v<x>, carry when operand >= v<x>
+            ">" => (0x8f05 | x << 4, false),
+            "<=" => (0x8f05 | x << 4, true),
+            _ => return Err(format!("unknown comparison '{}'", comparison))
+        };
+        let (skip_unless, skip_if) = match carry {
+            true => (0x3f00, 0x4f00),
+            false => (0x4f00, 0x3f00)
+        };
+        Ok(Condition { prelude: vec![load, subtract], skip_unless, skip_if })
+    }
+
+    /// Places the statements of macro <token> with its parameters replaced
+    /// by the words after it.
+    fn expand(&mut self, token: &Token) -> Result<(), String> {
+        if token.depth >= MAX_DEPTH {
+            return Err(format!("macro '{}' nests too deeply", token.text));
+        }
+        let definition = self.macros[&token.text].clone();
+        let mut arguments = HashMap::new();
+        for param in definition.params.iter() {
+            let argument = self.token()
+                .map_err(|_| format!("macro '{}' takes {} arguments", token.text, definition.params.len()))?;
+            arguments.insert(param, argument.text);
+        }
+        for body in definition.body.iter().rev() {
+            let text = arguments.get(&body.text).cloned().unwrap_or_else(|| body.text.clone());
+            self.tokens.push(Token { text, line: token.line, depth: token.depth + 1 });
+        }
+        Ok(())
+    }
+
+    /// The tokens up to the `}` matching one just read.
+    fn braces(&mut self) -> Result<Vec<Token>, String> {
+        let line = self.line;
+        let mut depth = 0;
+        let mut body = Vec::new();
+        loop {
+            let token = self.tokens.pop().ok_or_else(|| {
+                self.line = line;
+                String::from("'{' is missing its '}'")
+            })?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok(body),
+                "}" => depth -= 1,
+                _ => ()
+            }
+            body.push(token);
+        }
+    }
+
+    /// Places anything before main's jump, the first time something is
+    /// placed, unless it's main itself.
+    fn start(&mut self, main: bool) -> Result<(), String> {
+        if !self.started {
+            self.started = true;
+            if !main {
+                self.fixups.push((START, Fixup::Nnn, String::from("main"), self.line));
+                self.emit(&[0x10, 0x00])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let end = self.here + bytes.len();
+        if end > 0x10000 {
+            return Err(String::from("the program doesn't fit in memory"));
+        }
+        if self.rom.len() < end - START {
+            self.rom.resize(end - START, 0);
+        }
+        self.rom[self.here - START..end - START].copy_from_slice(bytes);
+        self.here = end;
+        Ok(())
+    }
+
+    fn instruction(&mut self, opcode: u16) -> Result<(), String> {
+        if let Some(name) = self.next.take() {
+            self.label(name, self.here + 1)?;
+        }
+        self.program.map.add(self.here as u16, self.path, self.line);
+        self.emit(&opcode.to_be_bytes())
+    }
+
+    fn vx_instruction(&mut self, opcode: u16) -> Result<(), String> {
+        let x = self.vx()?;
+        self.instruction(opcode | x << 8)
+    }
+
+    /// An instruction taking a 12-bit address, which may be a label
+    /// defined later.
+    fn address_instruction(&mut self, opcode: u16) -> Result<(), String> {
+        let addr = self.address(self.here, Fixup::Nnn, 0xfff)?;
+        self.instruction(opcode | addr)
+    }
+
+    /// The address the next token names, or 0 with a fixup of <at> if it's
+    /// a label yet to be defined.
+    fn address(&mut self, at: usize, fixup: Fixup, max: u16) -> Result<u16, String> {
+        let token = self.address_token()?;
+        match self.lookup(&token, at, fixup) {
+            Some(addr) => range(addr, 0.0, max as f64).map(|addr| addr as u16),
+            None => Ok(0)
+        }
+    }
+
+    fn address_token(&mut self) -> Result<String, String> {
+        match self.word()? {
+            word if word == "{" => {
+                let expression = self.braces()?;
+                let value = self.calc(&expression)?;
+                Ok(range(value, 0.0, 0xffff as f64)?.to_string())
+            },
+            word => Ok(word)
+        }
+    }
+
+    /// The value of <name>, or None having recorded a fixup of <at> if it
+    /// could be a label defined later.
+    fn lookup(&mut self, name: &str, at: usize, fixup: Fixup) -> Option<f64> {
+        match self.number(name) {
+            Ok(Some(value)) => Some(value),
+            _ => {
+                self.fixups.push((at, fixup, String::from(name), self.line));
+                None
+            }
+        }
+    }
+
+    fn patch(&mut self, jump: usize, target: usize) {
+        let opcode = 0x1000 | target as u16 & 0xfff;
+        self.rom[jump - START..jump - START + 2].copy_from_slice(&opcode.to_be_bytes());
+    }
+
+    fn label(&mut self, name: String, addr: usize) -> Result<(), String> {
+        if self.labels.contains_key(&name) || self.constants.contains_key(&name) {
+            return Err(format!("'{}' is already defined", name));
+        }
+        self.labels.insert(name, addr as u16);
+        Ok(())
+    }
+
+    fn constant(&mut self, name: String, value: f64) -> Result<(), String> {
+        if self.labels.contains_key(&name) {
+            return Err(format!("'{}' is already a label", name));
+        }
+        self.constants.insert(name, value);
+        Ok(())
+    }
+
+    /// A name for a label, constant, alias or macro.
+    fn name(&mut self) -> Result<String, String> {
+        let name = self.word()?;
+        match is_name(&name) && !self.is_register(&name) {
+            true => Ok(name),
+            false => Err(format!("invalid name '{}'", name))
+        }
+    }
+
+    fn is_register(&self, word: &str) -> bool {
+        self.register(word).is_ok()
+    }
+
+    fn register(&self, word: &str) -> Result<u8, String> {
+        if let Some(&x) = self.aliases.get(word) {
+            return Ok(x);
+        }
+        match word.as_bytes() {
+            [b'v' | b'V', digit] => (*digit as char).to_digit(16).map(|x| x as u8),
+            _ => None
+        }.ok_or(format!("expected a register, got '{}'", word))
+    }
+
+    fn vx(&mut self) -> Result<u16, String> {
+        let word = self.word()?;
+        self.register(&word).map(u16::from)
+    }
+
+    fn nibble(&mut self) -> Result<u16, String> {
+        let word = self.word()?;
+        range(self.defined(&word)?, 0.0, 15.0).map(|n| n as u16)
+    }
+
+    /// The value of a number, constant or label already defined.
+    fn defined(&self, word: &str) -> Result<f64, String> {
+        self.number(word)?.ok_or(format!("undefined name '{}'", word))
+    }
+
+    /// The value of a number, constant or label, or None if <word> isn't
+    /// defined yet.
+    fn number(&self, word: &str) -> Result<Option<f64>, String> {
+        if let Some(&value) = self.constants.get(word) {
+            return Ok(Some(value));
+        }
+        if let Some(&addr) = self.labels.get(word) {
+            return Ok(Some(addr as f64));
+        }
+        let (negative, digits) = match word.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, word)
+        };
+        if !digits.starts_with(|c: char| c.is_ascii_digit()) {
+            return Ok(None);
+        }
+        let value = match digits.get(..2) {
+            Some("0x") | Some("0X") => i64::from_str_radix(&digits[2..], 16),
+            Some("0b") | Some("0B") => i64::from_str_radix(&digits[2..], 2),
+            _ => digits.parse()
+        }.map_err(|_| format!("invalid number '{}'", word))?;
+        Ok(Some(if negative { -value } else { value } as f64))
+    }
+
+    /// Evaluates a `:calc` expression. As in Octo, binary operators have
+    /// equal precedence and group from the right, so `2 * 3 + 1` is 8.
+    fn calc(&self, tokens: &[Token]) -> Result<f64, String> {
+        let mut at = 0;
+        let value = self.expression(tokens, &mut at)?;
+        match tokens.get(at) {
+            Some(token) => Err(format!("unexpected '{}' in :calc", token.text)),
+            None => Ok(value)
+        }
+    }
+
+    fn expression(&self, tokens: &[Token], at: &mut usize) -> Result<f64, String> {
+        let left = self.term(tokens, at)?;
+        let operator = match tokens.get(*at) {
+            Some(token) if token.text != ")" => token.text.as_str(),
+            _ => return Ok(left)
+        };
+        *at += 1;
+        let right = self.expression(tokens, at)?;
+        let (a, b) = (left as i64, right as i64);
+        let truth = |test: bool| if test { 1.0 } else { 0.0 };
+        Ok(match operator {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => a.checked_shl(b as u32).unwrap_or(0) as f64,
+            ">>" => a.checked_shr(b as u32).unwrap_or(0) as f64,
+            "<" => truth(left < right),
+            ">" => truth(left > right),
+            "<=" => truth(left <= right),
+            ">=" => truth(left >= right),
+            "==" => truth(left == right),
+            "!=" => truth(left != right),
+            _ => return Err(format!("unknown operator '{}' in :calc", operator))
+        })
+    }
+
+    fn term(&self, tokens: &[Token], at: &mut usize) -> Result<f64, String> {
+        let token = tokens.get(*at).ok_or("expected a value in :calc")?;
+        *at += 1;
+        let mut unary = |f: fn(f64) -> f64| -> Result<f64, String> { self.term(tokens, at).map(f) };
+        match token.text.as_str() {
+            "(" => {
+                let value = self.expression(tokens, at)?;
+                match tokens.get(*at) {
+                    Some(token) if token.text == ")" => {
+                        *at += 1;
+                        Ok(value)
+                    },
+                    _ => Err(String::from("expected ')' in :calc"))
+                }
+            },
+            "-" => unary(|x| -x),
+            "~" => unary(|x| !(x as i64) as f64),
+            "!" => unary(|x| if x == 0.0 { 1.0 } else { 0.0 }),
+            "sin" => unary(f64::sin),
+            "cos" => unary(f64::cos),
+            "tan" => unary(f64::tan),
+            "exp" => unary(f64::exp),
+            "log" => unary(f64::ln),
+            "abs" => unary(f64::abs),
+            "sqrt" => unary(f64::sqrt),
+            "sign" => unary(f64::signum),
+            "ceil" => unary(f64::ceil),
+            "floor" => unary(f64::floor),
+            "@" => {
+                let addr = self.term(tokens, at)? as usize;
+                Ok(addr.checked_sub(START).and_then(|offset| self.rom.get(offset)).map_or(0.0, |&byte| byte as f64))
+            },
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            word => self.defined(word)
+        }
+    }
+
+}
+
+/// Whether <word> could name something, rather than being a number or
+/// punctuation.
+fn is_name(word: &str) -> bool {
+    word.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') &&
+        word.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn range(value: f64, min: f64, max: f64) -> Result<f64, String> {
+    let value = value.trunc();
+    match (min..=max).contains(&value) {
+        true => Ok(value),
+        false => Err(format!("{} is out of range ({} to {:#x})", value, min, max as i64))
+    }
+}
+
+/// A byte, which may be written as a negative number.
+fn byte(value: f64) -> Result<u8, String> {
+    range(value, -128.0, 255.0).map(|value| value as i64 as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::{self, Syntax};
+
+    fn rom(source: &str) -> Result<Vec<u8>, String> {
+        compile(source, Path::new("test.8o")).map(|program| program.rom)
+    }
+
+    #[test]
+    fn program() {
+        let source = "
+:alias x v1
+:const SIZE 3
+:macro draw X Y { i := pattern sprite X Y SIZE }
+:calc DOUBLE { SIZE * 2 + 1 }   # right to left, so 9
+
+: pattern 0x3c 0x42 0xff
+: main
+    x := DOUBLE
+    draw x v2
+    x -= 1
+    if x != 0 then jump main
+    step
+    :breakpoint done
+    :next target v3 := 0
+: step ;
+:monitor pattern \"%i %x\"
+";
+        let program = compile(source, Path::new("test.8o")).unwrap();
+        assert_eq!(program.rom, [
+            0x12, 0x05, 0x3c, 0x42, 0xff, 0x61, 0x09, 0xa2, 0x02, 0xd1, 0x23, 0x71, 0xff,
+            0x31, 0x00, 0x12, 0x05, 0x22, 0x15, 0x63, 0x00, 0x00, 0xee
+        ]);
+        assert_eq!(program.breakpoints, [(String::from("done"), 0x213)]);
+        assert_eq!(program.map.location(0x209).unwrap().line, 10);
+        let mut machine = Machine::new();
+        machine.load(&program.rom);
+        assert_eq!(program.monitors[0].show(&machine), "pattern: 60 42");
+    }
+
+    #[test]
+    fn control_flow() {
+        let source = "
+: main
+    loop
+        if v0 == v1 begin
+            v0 += 1
+        else
+            v1 := key
+        end
+        while v2 < 8
+        if v3 -key then v3 := 1
+    again
+";
+        assert_eq!(rom(source), Ok(vec![
+            0x50, 0x10, 0x12, 0x08, 0x70, 0x01, 0x12, 0x0a, 0xf1, 0x0a, 0x6f, 0x08, 0x8f, 0x27,
+            0x3f, 0x00, 0x12, 0x18, 0xe3, 0x9e, 0x63, 0x01, 0x12, 0x00
+        ]));
+    }
+
+    #[test]
+    fn comparisons() {
+        // vf := operand, subtract, then skip unless the carry says so
+        assert_eq!(rom(": main if v1 > v2 then v0 := 1"), Ok(vec![0x8f, 0x20, 0x8f, 0x15, 0x4f, 0x00, 0x60, 0x01]));
+        assert_eq!(rom(": main if v1 <= 4 then v0 := 1"), Ok(vec![0x6f, 0x04, 0x8f, 0x15, 0x3f, 0x00, 0x60, 0x01]));
+        assert_eq!(rom(": main if v1 >= v2 then v0 := 1"), Ok(vec![0x8f, 0x20, 0x8f, 0x17, 0x3f, 0x00, 0x60, 0x01]));
+    }
+
+    #[test]
+    fn long_addresses() {
+        let source = ": main i := long data :unpack 0xa data :pointer data :org 0x323 : data 1";
+        let mut expected = vec![0xf0, 0x00, 0x03, 0x23, 0x60, 0xa3, 0x61, 0x23, 0x03, 0x23];
+        expected.resize(0x323 - 0x200, 0);
+        expected.push(1);
+        assert_eq!(rom(source), Ok(expected));
+    }
+
+    #[test]
+    fn guards() {
+        // the skip steps over both words of a long load
+        assert_eq!(rom(": main if v0 == 1 then i := long 0x1234"), Ok(vec![0x40, 0x01, 0xf0, 0x00, 0x12, 0x34]));
+        // instructions without words are listed as two bytes
+        assert_eq!(rom(": main if v0 == 1 then 0x01 0x23 v1 := 2"), Ok(vec![0x40, 0x01, 0x01, 0x23, 0x61, 0x02]));
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(rom(": main
+  v0 := 5# note
+  v1 := 6 #note"), Ok(vec![0x60, 0x05, 0x61, 0x06]));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |source| rom(source).unwrap_err();
+        assert_eq!(error(": main\n  v0 := 300"), "test.8o:2: 300 is out of range (-128 to 0xff)");
+        assert_eq!(error(": main\n  jump nowhere\n"), "test.8o:2: undefined name 'nowhere'");
+        assert_eq!(error(": main\n\n  loop\n"), "test.8o:3: 'loop' is missing its 'again'");
+        assert_eq!(error(": main if v0 == 1 then 0x00 v0 := 1"), "test.8o:1: 'then' must be followed by a single instruction");
+        assert_eq!(error(": start ;"), "test.8o:1: the program has no ': main'");
+        assert_eq!(error(": main\n  end"), "test.8o:2: 'end' without 'begin'");
+        assert_eq!(error(":macro m { m }\n: main m"), "test.8o:2: macro 'm' nests too deeply");
+        assert_eq!(error(": main : main"), "test.8o:1: 'main' is already defined");
+    }
+
+    #[test]
+    fn round_trip() {
+        let rom = [
+            0xa2, 0x0e, 0x22, 0x0a, 0x30, 0x01, 0x12, 0x04, 0x12, 0x08, 0xd0, 0x02, 0x00, 0xee,
+            0x3c, 0x42, 0xff
+        ];
+        let listing = disasm::disassemble(&rom, Syntax::Octo);
+        assert_eq!(self::rom(&listing), Ok(rom.to_vec()), "{}", listing);
+    }
+
+}
@@ -0,0 +1,718 @@
+use std::path::{Path, PathBuf};
+use chip8::quirks::Quirks;
+use chip8::gpu::PALETTE;
+use chip8::machine::DEFAULT_INSTRUCTIONS_PER_FRAME;
+use chip8::headless::Press;
+use chip8::image::Format;
+use chip8::audio::{Tone, Waveform};
+use chip8::rewind;
+use chip8::trace::{self, Filter};
+use chip8::diff;
+use chip8::instruction::{Class, Extension};
+use chip8::disasm::Syntax;
+use chip8::octo::{self, Program};
+
+pub const USAGE: &str = "\
+usage: chip8 [options] <rom>
+       chip8 headless [options] <rom>
+       chip8 diff [options] (--against <platform> | --reference <trace>) <rom>
+       chip8 dap [options] [--port <n>] [<rom>]
+       chip8 disasm [--syntax <name>] [-o <file>] <rom>
+       chip8 asm [-p <platform>] [-o <file>] [--map <file>] <source>
+
+options:
+  -p, --platform <name>   vip, schip or xochip (default: schip)
+  -i, --ipf <n>           instructions per 60 Hz frame (default: 10)
+  -s, --scale <n>         window pixels per CHIP-8 pixel (default: 10)
+      --palette <colors>  mono, amber, green, or up to 4 comma-separated
+                          rrggbb colors for planes 0-3
+      --paused            start paused (F1 resumes, F6 steps)
+      --debug-panel       start with the debugger panel shown (F3 toggles
+                          it)
+      --console           take gdb-style debugger commands from stdin, such
+                          as 'break 0x2a4', 'step' and 'regs' ('help' lists
+                          them)
+      --tone <hz>         beeper frequency (default: 440); xochip plays
+                          the ROM's audio pattern instead
+      --volume <n>        beeper volume from 0 to 100 (default: 25)
+      --waveform <name>   square, triangle, sawtooth or sine
+  -k, --keymap <file>     load key bindings from <file> instead of
+                          ~/.config/chip8/keymap.cfg
+      --state <file>      save state written by F5 and read by F9 (default:
+                          the ROM path with a .state extension); headless
+                          runs start from it when given
+      --rewind <mib>      memory kept for rewinding with backspace, 0 to
+                          disable (default: 16)
+      --seed <n>          seed the random number generator, in decimal or
+                          0x-prefixed hex, making runs repeatable
+      --vip-rng <file>    generate random numbers as the COSMAC VIP did,
+                          using the interpreter dump in <file>
+  --seed and --vip-rng default to the seed and vip-rng lines of
+  ~/.config/chip8/chip8.cfg, if it has them
+      --record <file>     record a movie of the keypad to <file>
+      --replay <file>     replay the movie in <file>, checking it stays in
+                          sync with the recording
+      --trace <file>      write a record of each executed instruction to
+                          <file>
+      --trace-format <name>
+                          text or binary (default: binary for .bin files)
+      --trace-pc <a>-<b>  only trace instructions between hex addresses
+                          <a> and <b>
+      --trace-class <names>
+                          only trace the comma-separated classes flow, alu,
+                          memory, display, input, timer, audio or invalid
+      --trace-frames <a>-<b>
+                          only trace frames <a> to <b>, counting from 0
+  -h, --help              print this message
+
+headless options:
+  -f, --frames <n>        frames to run for (default: 600), or with
+                          --console, the most a command runs before giving
+                          up
+      --press <f>:<k>[:<n>]
+                          hold key <k> for <n> frames (default: 6) from
+                          frame <f>, may be repeated
+      --script <file>     read presses from <file>, separated by whitespace
+  -o, --dump <file>       write the screen to <file> instead of stdout
+      --format <name>     png, pbm or ascii (default: from the extension)
+
+diff options:
+      --against <name>    compare with a run on another platform
+      --reference <file>  compare with a text or binary trace, such as one
+                          written by --trace
+      --context <n>       instructions shown either side of the first
+                          difference (default: 8)
+  --frames, --press and --script also apply to diff, and --replay runs
+  both sides from a movie's starting state and inputs
+
+dap options:
+      --port <n>          serve the Debug Adapter Protocol on localhost
+                          port <n> instead of stdin and stdout
+  the ROM comes from the launch request's program, or <rom> if it has none
+
+disasm options:
+      --syntax <name>     cowgod or octo (default: cowgod)
+  -o <file> writes the listing to <file> instead of stdout
+
+asm options:
+      --map <file>        write the source line of each instruction to
+                          <file>, so chip8 dap can debug by line (it reads
+                          the ROM path with a .map extension by default)
+  -o <file> names the ROM (default: <source> with a .ch8 extension), and
+  -p limits the instructions accepted to the platform's
+
+A <rom> ending in .8o is Octo source, compiled when it's loaded. Its
+:breakpoint directives stop the debugger, and its :monitor directives name
+memory shown when it stops and by the console's monitors command.
+
+headless exit status is 0 if the ROM is still running, 5 if it exited and
+3 if it faulted. diff exit status is 0 if the runs match and 4 if they
+diverge. Errors exit with 1, and a bad command line with 2.";
+
+const DEFAULT_SCALE: u32 = 10;
+const DEFAULT_FRAMES: u32 = 600;
+
+const HEADLESS: &[&str] = &[
+    "-f", "--frames", "--press", "--script", "-o", "--dump", "--format"
+];
+
+const DIFF: &[&str] = &["--against", "--reference", "--context"];
+
+const DAP: &[&str] = &["--port"];
+
+/// Options that make no sense when an editor drives the debugger.
+const NOT_DAP: &[&str] = &[
+    "-f", "--frames", "--press", "--script", "-o", "--dump", "--format", "--record", "--replay", "--console"
+];
+
+/// Options that make no sense when comparing runs.
+const NOT_DIFF: &[&str] = &["-o", "--dump", "--format", "--record", "--console"];
+
+/// The only options a listing depends on.
+const DISASM: &[&str] = &["--syntax", "-o", "--dump", "-h", "--help"];
+
+/// The only options assembling depends on.
+const ASM: &[&str] = &["-p", "--platform", "-o", "--dump", "--map", "-h", "--help"];
+
+const AMBER: [u32; 4] = [0x1a0f00, 0xffb000, 0xb37b00, 0x664600];
+const GREEN: [u32; 4] = [0x001a00, 0x33ff33, 0x22aa22, 0x115511];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Platform {
+    Vip,
+    Schip,
+    Xochip
+}
+
+impl Platform {
+
+    pub fn parse(name: &str) -> Option<Platform> {
+        match name {
+            "vip" | "chip8" | "chip-8" => Some(Platform::Vip),
+            "schip" | "superchip" => Some(Platform::Schip),
+            "xochip" | "xo-chip" => Some(Platform::Xochip),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Vip => "vip",
+            Platform::Schip => "schip",
+            Platform::Xochip => "xochip"
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Platform::Vip => Quirks::vip(),
+            Platform::Schip => Quirks::schip(),
+            Platform::Xochip => Quirks::xochip()
+        }
+    }
+
+    /// The largest ROM that fits between 0x200 and the end of the
+    /// platform's address space.
+    pub fn max_rom_size(&self) -> usize {
+        self.quirks().memory - 0x200
+    }
+
+    /// The instructions the platform runs.
+    pub fn extension(&self) -> Extension {
+        match self {
+            Platform::Vip => Extension::Chip8,
+            Platform::Schip => Extension::Schip,
+            Platform::Xochip => Extension::Xochip
+        }
+    }
+
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    Window,
+    Headless,
+    Diff,
+    Dap,
+    Disasm,
+    Asm
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub command: Command,
+    pub rom: PathBuf,
+    pub platform: Platform,
+    pub instructions_per_frame: u32,
+    pub scale: u32,
+    pub palette: [u32; 4],
+    pub paused: bool,
+    pub debug_panel: bool,
+    pub console: bool,
+    pub tone: Tone,
+    pub keymap: Option<PathBuf>,
+    pub state: Option<PathBuf>,
+    pub rewind: usize,
+    pub seed: Option<u64>,
+    pub vip_rng: Option<PathBuf>,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub trace: Option<PathBuf>,
+    pub trace_format: Option<trace::Format>,
+    pub trace_filter: Filter,
+    pub frames: u32,
+    pub presses: Vec<Press>,
+    pub dump: Option<PathBuf>,
+    pub format: Option<Format>,
+    pub against: Option<Platform>,
+    pub reference: Option<PathBuf>,
+    pub context: usize,
+    pub port: Option<u16>,
+    pub syntax: Syntax,
+    pub map: Option<PathBuf>,
+    pub help: bool
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            command: Command::Window,
+            rom: PathBuf::new(),
+            platform: Platform::Schip,
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            scale: DEFAULT_SCALE,
+            palette: PALETTE,
+            paused: false,
+            debug_panel: false,
+            console: false,
+            tone: Tone::default(),
+            keymap: None,
+            state: None,
+            rewind: rewind::DEFAULT_BUDGET,
+            seed: None,
+            vip_rng: None,
+            record: None,
+            replay: None,
+            trace: None,
+            trace_format: None,
+            trace_filter: Filter::default(),
+            frames: DEFAULT_FRAMES,
+            presses: Vec::new(),
+            dump: None,
+            format: None,
+            against: None,
+            reference: None,
+            context: diff::DEFAULT_CONTEXT,
+            port: None,
+            syntax: Syntax::Cowgod,
+            map: None,
+            help: false
+        }
+    }
+}
+
+impl Options {
+
+    /// Parses the command line arguments, excluding the program name.
+    pub fn parse<I>(args: I) -> Result<Options, String>
+        where I: IntoIterator<Item = String> {
+        let mut options = Options::default();
+        let mut rom = None;
+        let mut args = args.into_iter().peekable();
+        match args.peek().map(String::as_str) {
+            Some("headless") => options.command = Command::Headless,
+            Some("diff") => options.command = Command::Diff,
+            Some("dap") => options.command = Command::Dap,
+            Some("disasm") => options.command = Command::Disasm,
+            Some("asm") => options.command = Command::Asm,
+            _ => ()
+        }
+        if options.command != Command::Window {
+            args.next();
+        }
+        while let Some(arg) = args.next() {
+            if options.command == Command::Disasm && arg.starts_with('-') && !DISASM.contains(&arg.as_str()) {
+                return Err(format!("{} doesn't apply to chip8 disasm", arg));
+            }
+            if options.command != Command::Disasm && arg == "--syntax" {
+                return Err(format!("{} only applies to chip8 disasm", arg));
+            }
+            if options.command == Command::Asm && arg.starts_with('-') && !ASM.contains(&arg.as_str()) {
+                return Err(format!("{} doesn't apply to chip8 asm", arg));
+            }
+            if options.command != Command::Asm && arg == "--map" {
+                return Err(format!("{} only applies to chip8 asm", arg));
+            }
+            if options.command == Command::Window && HEADLESS.contains(&arg.as_str()) {
+                return Err(format!("{} only applies to headless runs", arg));
+            }
+            if options.command != Command::Diff && DIFF.contains(&arg.as_str()) {
+                return Err(format!("{} only applies to chip8 diff", arg));
+            }
+            if options.command == Command::Diff && NOT_DIFF.contains(&arg.as_str()) {
+                return Err(format!("{} doesn't apply to chip8 diff", arg));
+            }
+            if options.command != Command::Dap && DAP.contains(&arg.as_str()) {
+                return Err(format!("{} only applies to chip8 dap", arg));
+            }
+            if options.command == Command::Dap && NOT_DAP.contains(&arg.as_str()) {
+                return Err(format!("{} doesn't apply to chip8 dap", arg));
+            }
+            let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+            match arg.as_str() {
+                "-h" | "--help" => options.help = true,
+                "-p" | "--platform" => {
+                    let name = value()?;
+                    options.platform = Platform::parse(&name)
+                        .ok_or(format!("unknown platform '{}'", name))?;
+                },
+                "-i" | "--ipf" => options.instructions_per_frame = number(&value()?)?,
+                "-s" | "--scale" => options.scale = number(&value()?)?,
+                "--palette" => options.palette = palette(&value()?)?,
+                "--paused" => options.paused = true,
+                "--debug-panel" => options.debug_panel = true,
+                "--console" => options.console = true,
+                "--tone" => options.tone.frequency = number(&value()?)? as f32,
+                "--volume" => options.tone.volume = volume(&value()?)?,
+                "--waveform" => {
+                    let name = value()?;
+                    options.tone.waveform = Waveform::parse(&name)
+                        .ok_or(format!("unknown waveform '{}'", name))?;
+                },
+                "-k" | "--keymap" => options.keymap = Some(PathBuf::from(value()?)),
+                "--state" => options.state = Some(PathBuf::from(value()?)),
+                "--rewind" => {
+                    let value = value()?;
+                    let mib: usize = value.parse()
+                        .map_err(|_| format!("expected a size in MiB, got '{}'", value))?;
+                    options.rewind = mib.checked_mul(1024 * 1024)
+                        .ok_or(format!("rewind size {} MiB is too large", mib))?;
+                },
+                "--seed" => options.seed = Some(seed(&value()?)?),
+                "--vip-rng" => options.vip_rng = Some(PathBuf::from(value()?)),
+                "--record" => options.record = Some(PathBuf::from(value()?)),
+                "--replay" => options.replay = Some(PathBuf::from(value()?)),
+                "--trace" => options.trace = Some(PathBuf::from(value()?)),
+                "--trace-format" => {
+                    let name = value()?;
+                    options.trace_format = Some(trace::Format::parse(&name)
+                        .ok_or(format!("unknown trace format '{}'", name))?);
+                },
+                "--trace-pc" => options.trace_filter.addresses = Some(range(&value()?, address)?),
+                "--trace-class" => {
+                    for name in value()?.split(',') {
+                        let class = Class::parse(name.trim())
+                            .ok_or(format!("unknown instruction class '{}'", name))?;
+                        options.trace_filter.classes.push(class);
+                    }
+                },
+                "--trace-frames" => {
+                    options.trace_filter.frames = Some(range(&value()?, |n| n.parse().ok())?);
+                },
+                "-f" | "--frames" => options.frames = number(&value()?)?,
+                "--press" => options.presses.push(Press::parse(&value()?)?),
+                "--script" => {
+                    let path = value()?;
+                    let text = std::fs::read_to_string(&path)
+                        .map_err(|e| format!("can't read script '{}': {}", path, e))?;
+                    let presses = Press::parse_script(&text)
+                        .map_err(|e| format!("{}:{}", path, e))?;
+                    options.presses.extend(presses);
+                },
+                "-o" | "--dump" => options.dump = Some(PathBuf::from(value()?)),
+                "--format" => {
+                    let name = value()?;
+                    options.format = Some(Format::parse(&name)
+                        .ok_or(format!("unknown format '{}'", name))?);
+                },
+                "--against" => {
+                    let name = value()?;
+                    options.against = Some(Platform::parse(&name)
+                        .ok_or(format!("unknown platform '{}'", name))?);
+                },
+                "--reference" => options.reference = Some(PathBuf::from(value()?)),
+                "--context" => options.context = number(&value()?)? as usize,
+                "--syntax" => {
+                    let name = value()?;
+                    options.syntax = Syntax::parse(&name)
+                        .ok_or(format!("unknown syntax '{}'", name))?;
+                },
+                "--map" => options.map = Some(PathBuf::from(value()?)),
+                "--port" => {
+                    let value = value()?;
+                    options.port = Some(value.parse().map_err(|_| format!("invalid port '{}'", value))?);
+                },
+                _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+                _ if rom.is_some() => return Err(format!("unexpected argument '{}'", arg)),
+                _ => rom = Some(PathBuf::from(arg))
+            }
+        }
+        if options.record.is_some() && options.replay.is_some() {
+            return Err(String::from("--record and --replay can't be used together"));
+        }
+        if options.console && (options.record.is_some() || options.replay.is_some()) {
+            return Err(String::from("--console can't be used with --record or --replay"));
+        }
+        if options.command == Command::Diff && !options.help &&
+            options.against.is_some() == options.reference.is_some() {
+            return Err(String::from("diff needs one of --against or --reference"));
+        }
+        match rom {
+            Some(rom) => options.rom = rom,
+            None if options.help || options.command == Command::Dap => (),
+            None => return Err(String::from("no ROM given"))
+        }
+        Ok(options)
+    }
+
+    /// Fills in options not given on the command line from `chip8/chip8.cfg`
+    /// in the user's config directory, if there is one.
+    pub fn load_user_config(&mut self) -> Result<(), String> {
+        let path = match config_path("chip8.cfg") {
+            Some(path) if path.exists() => path,
+            _ => return Ok(())
+        };
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| format!("can't read config '{}': {}", path.display(), e))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        self.apply_config(&text, dir)
+            .map_err(|e| format!("{}:{}", path.display(), e))
+    }
+
+    /// Applies config file <text>, made of `<option> = <value>` lines and
+    /// `#` comments, to the options not already set. Relative paths are
+    /// resolved against <dir>. Errors are prefixed with their line number.
+    fn apply_config(&mut self, text: &str, dir: &Path) -> Result<(), String> {
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |e: String| format!("{}: {}", n + 1, e);
+            let (key, value) = line.split_once('=')
+                .ok_or_else(|| error(format!("expected '<option> = <value>', got '{}'", line)))?;
+            let value = value.trim();
+            match key.trim() {
+                "seed" => {
+                    let seed = seed(value).map_err(error)?;
+                    self.seed.get_or_insert(seed);
+                },
+                "vip-rng" => {
+                    self.vip_rng.get_or_insert_with(|| dir.join(value));
+                },
+                key => return Err(error(format!("unknown option '{}'", key)))
+            }
+        }
+        Ok(())
+    }
+
+    /// The save state file used by the quick-save and quick-load hotkeys.
+    #[cfg(feature = "window")]
+    pub fn state_path(&self) -> PathBuf {
+        self.state.clone().unwrap_or_else(|| self.rom.with_extension("state"))
+    }
+
+    /// Reads the ROM, compiling it first if it's Octo source, and checks
+    /// that it fits in the platform's memory. A listing isn't tied to a
+    /// platform, so disasm takes anything that fits in XO-CHIP's 64K.
+    pub fn read_program(&self) -> Result<Program, String> {
+        let program = match octo::is_source(&self.rom) {
+            true => octo::compile_file(&self.rom)?,
+            false => {
+                let rom = std::fs::read(&self.rom)
+                    .map_err(|e| format!("can't read ROM '{}': {}", self.rom.display(), e))?;
+                Program { rom, ..Program::default() }
+            }
+        };
+        let max = match self.command {
+            Command::Disasm => Platform::Xochip.max_rom_size(),
+            _ => self.platform.max_rom_size()
+        };
+        if program.rom.len() > max {
+            return Err(format!("ROM '{}' is {} bytes, but at most {} fit in memory",
+                self.rom.display(), program.rom.len(), max));
+        }
+        Ok(program)
+    }
+
+}
+
+/// The path of <name> in the `chip8` directory of the user's config
+/// directory, if they have one.
+pub fn config_path(name: &str) -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config.join("chip8").join(name))
+}
+
+fn number(value: &str) -> Result<u32, String> {
+    match value.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("expected a positive number, got '{}'", value))
+    }
+}
+
+fn seed(value: &str) -> Result<u64, String> {
+    let seed = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse()
+    };
+    seed.map_err(|_| format!("expected a seed, got '{}'", value))
+}
+
+fn address(value: &str) -> Option<u16> {
+    u16::from_str_radix(value.trim_start_matches("0x"), 16).ok()
+}
+
+/// Parses an inclusive range `<a>-<b>`, reading each end with <parse>.
+fn range<T: PartialOrd>(value: &str, parse: fn(&str) -> Option<T>) -> Result<(T, T), String> {
+    let ends = value.split_once('-').and_then(|(start, end)| Some((parse(start)?, parse(end)?)));
+    match ends {
+        Some((start, end)) if start <= end => Ok((start, end)),
+        _ => Err(format!("expected a range <from>-<to>, got '{}'", value))
+    }
+}
+
+fn volume(value: &str) -> Result<f32, String> {
+    match value.parse::<u32>() {
+        Ok(n) if n <= 100 => Ok(n as f32 / 100.0),
+        _ => Err(format!("expected a volume from 0 to 100, got '{}'", value))
+    }
+}
+
+fn palette(value: &str) -> Result<[u32; 4], String> {
+    match value {
+        "mono" => return Ok(PALETTE),
+        "amber" => return Ok(AMBER),
+        "green" => return Ok(GREEN),
+        _ => ()
+    }
+    let mut palette = PALETTE;
+    let colors: Vec<&str> = value.split(',').collect();
+    if colors.len() > palette.len() {
+        return Err(format!("expected at most 4 colors, got '{}'", value));
+    }
+    for (i, color) in colors.iter().enumerate() {
+        let hex = color.trim().trim_start_matches('#');
+        palette[i] = match u32::from_str_radix(hex, 16) {
+            Ok(rgb) if hex.len() == 6 => rgb,
+            _ => return Err(format!("invalid color '{}'", color))
+        };
+    }
+    Ok(palette)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, String> {
+        Options::parse(args.split_whitespace().map(String::from))
+    }
+
+    fn error(args: &str) -> String {
+        parse(args).unwrap_err()
+    }
+
+    #[test]
+    fn window() {
+        let options = parse("-p vip -i 20 --palette amber --paused --seed 0x2a --rewind 4 pong.ch8").unwrap();
+        assert_eq!(options.command, Command::Window);
+        assert_eq!(options.rom, PathBuf::from("pong.ch8"));
+        assert_eq!(options.platform, Platform::Vip);
+        assert_eq!(options.instructions_per_frame, 20);
+        assert_eq!(options.palette, AMBER);
+        assert!(options.paused);
+        assert_eq!(options.seed, Some(0x2a));
+        assert_eq!(options.rewind, 4 * 1024 * 1024);
+        assert_eq!(parse("pong.ch8").unwrap(), Options { rom: PathBuf::from("pong.ch8"), ..Options::default() });
+    }
+
+    #[test]
+    fn headless() {
+        let options = parse("headless -f 30 --press 10:5:2 --press 20:a -o pong.png pong.ch8").unwrap();
+        assert_eq!(options.command, Command::Headless);
+        assert_eq!(options.frames, 30);
+        assert_eq!(options.presses, [
+            Press { frame: 10, key: 0x5, frames: 2 },
+            Press { frame: 20, key: 0xa, frames: 6 }
+        ]);
+        assert_eq!(options.dump, Some(PathBuf::from("pong.png")));
+    }
+
+    #[test]
+    fn diff() {
+        let options = parse("diff --against vip --context 4 --frames 60 pong.ch8").unwrap();
+        assert_eq!(options.command, Command::Diff);
+        assert_eq!(options.against, Some(Platform::Vip));
+        assert_eq!(options.context, 4);
+        assert_eq!(options.frames, 60);
+        let options = parse("diff --reference pong.trace pong.ch8").unwrap();
+        assert_eq!(options.reference, Some(PathBuf::from("pong.trace")));
+    }
+
+    #[test]
+    fn dap() {
+        let options = parse("dap --port 4711").unwrap();
+        assert_eq!(options.command, Command::Dap);
+        assert_eq!(options.port, Some(4711));
+        assert_eq!(options.rom, PathBuf::new());
+    }
+
+    #[test]
+    fn disasm() {
+        let options = parse("disasm --syntax octo -o pong.8o pong.ch8").unwrap();
+        assert_eq!(options.command, Command::Disasm);
+        assert_eq!(options.syntax, Syntax::Octo);
+        assert_eq!(options.dump, Some(PathBuf::from("pong.8o")));
+    }
+
+    #[test]
+    fn asm() {
+        let options = parse("asm -p xochip --map pong.map pong.8o").unwrap();
+        assert_eq!(options.command, Command::Asm);
+        assert_eq!(options.platform, Platform::Xochip);
+        assert_eq!(options.map, Some(PathBuf::from("pong.map")));
+        assert_eq!(options.rom, PathBuf::from("pong.8o"));
+    }
+
+    #[test]
+    fn rom_size() {
+        let path = std::env::temp_dir().join(format!("chip8-options-{}.ch8", std::process::id()));
+        std::fs::write(&path, vec![0; 0x2000]).unwrap();
+        let path = path.to_str().unwrap();
+        let disasm = parse(&format!("disasm {}", path)).unwrap().read_program().map(|program| program.rom.len());
+        let run = parse(&format!("-p schip {}", path)).unwrap().read_program().map(|program| program.rom.len());
+        let xochip = parse(&format!("-p xochip {}", path)).unwrap().read_program().map(|program| program.rom.len());
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(disasm, Ok(0x2000));
+        assert_eq!(run, Err(format!("ROM '{}' is 8192 bytes, but at most 3584 fit in memory", path)));
+        assert_eq!(xochip, Ok(0x2000));
+    }
+
+    #[test]
+    fn help() {
+        assert!(parse("--help").unwrap().help);
+        assert!(parse("diff -h").unwrap().help);
+    }
+
+    #[test]
+    fn rejected() {
+        assert_eq!(error("--frames 10 pong.ch8"), "--frames only applies to headless runs");
+        assert_eq!(error("headless --against vip pong.ch8"), "--against only applies to chip8 diff");
+        assert_eq!(error("diff --against vip --console pong.ch8"), "--console doesn't apply to chip8 diff");
+        assert_eq!(error("--port 4711 pong.ch8"), "--port only applies to chip8 dap");
+        assert_eq!(error("dap --replay pong.movie"), "--replay doesn't apply to chip8 dap");
+        assert_eq!(error("disasm -p vip pong.ch8"), "-p doesn't apply to chip8 disasm");
+        assert_eq!(error("--syntax octo pong.ch8"), "--syntax only applies to chip8 disasm");
+        assert_eq!(error("asm --paused pong.8o"), "--paused doesn't apply to chip8 asm");
+        assert_eq!(error("--map pong.map pong.ch8"), "--map only applies to chip8 asm");
+        assert_eq!(error("--record a --replay b pong.ch8"), "--record and --replay can't be used together");
+        assert_eq!(error("--console --record a pong.ch8"), "--console can't be used with --record or --replay");
+        assert_eq!(error("diff pong.ch8"), "diff needs one of --against or --reference");
+        assert_eq!(error("diff --against vip --reference a pong.ch8"), "diff needs one of --against or --reference");
+    }
+
+    #[test]
+    fn config() {
+        let text = "
+            # repeatable runs
+            seed = 42
+            vip-rng = vip.bin
+        ";
+        let mut options = parse("pong.ch8").unwrap();
+        options.apply_config(text, Path::new("/home/user/.config/chip8")).unwrap();
+        assert_eq!(options.seed, Some(42));
+        assert_eq!(options.vip_rng, Some(PathBuf::from("/home/user/.config/chip8/vip.bin")));
+        // the command line takes precedence
+        let mut options = parse("--seed 0x10 --vip-rng /tmp/vip.bin pong.ch8").unwrap();
+        options.apply_config(text, Path::new("/home/user/.config/chip8")).unwrap();
+        assert_eq!(options.seed, Some(0x10));
+        assert_eq!(options.vip_rng, Some(PathBuf::from("/tmp/vip.bin")));
+        let mut options = Options::default();
+        assert_eq!(options.apply_config("\nseed = x", Path::new("")),
+            Err(String::from("2: expected a seed, got 'x'")));
+        assert_eq!(options.apply_config("ipf = 20", Path::new("")),
+            Err(String::from("1: unknown option 'ipf'")));
+        assert_eq!(options.apply_config("seed", Path::new("")),
+            Err(String::from("1: expected '<option> = <value>', got 'seed'")));
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(error(""), "no ROM given");
+        assert_eq!(error("headless"), "no ROM given");
+        assert_eq!(error("--fast pong.ch8"), "unknown option '--fast'");
+        assert_eq!(error("pong.ch8 brix.ch8"), "unexpected argument 'brix.ch8'");
+        assert_eq!(error("pong.ch8 --ipf"), "missing value for --ipf");
+        assert_eq!(error("-p nes pong.ch8"), "unknown platform 'nes'");
+        assert_eq!(error("-i 0 pong.ch8"), "expected a positive number, got '0'");
+        assert_eq!(error("--volume 101 pong.ch8"), "expected a volume from 0 to 100, got '101'");
+        assert_eq!(error("--palette 123456,abc pong.ch8"), "invalid color 'abc'");
+        assert_eq!(error("--trace-pc 300-200 pong.ch8"), "expected a range <from>-<to>, got '300-200'");
+        assert_eq!(error("--rewind 16M pong.ch8"), "expected a size in MiB, got '16M'");
+        assert_eq!(error("--rewind 18446744073709551615 pong.ch8"),
+            "rewind size 18446744073709551615 MiB is too large");
+    }
+
+}
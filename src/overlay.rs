@@ -0,0 +1,259 @@
+use chip8::machine::Machine;
+use chip8::debugger;
+
+use coffee::graphics::{Color, Frame, Mesh, Rectangle, Shape};
+
+/// Characters across the panel.
+const COLUMNS: usize = 40;
+/// Lines down the panel, enough for every view.
+const LINES: usize = 26;
+
+/// Glyphs are 3x5 pixels, in a 4x6 cell.
+const CELL_WIDTH: usize = 4;
+const CELL_HEIGHT: usize = 6;
+
+/// Instructions shown before the current one in the disassembly.
+const DISASSEMBLY_BEFORE: u16 = 4;
+const DISASSEMBLY_LINES: usize = 10;
+/// Rows of 8 bytes shown in the memory view, starting a row before <i>.
+const MEMORY_ROWS: u16 = 7;
+
+const BACKGROUND: u32 = 0x202020;
+const TEXT: u32 = 0xc0c0c0;
+const HIGHLIGHT: u32 = 0x404880;
+
+/// A line of the panel, with the columns to highlight, if any.
+#[derive(Debug, PartialEq)]
+struct Line {
+    text: String,
+    highlight: Option<(usize, usize)>
+}
+
+impl Line {
+
+    fn new(text: String) -> Self {
+        Line { text, highlight: None }
+    }
+
+}
+
+/// The width of the panel in a window <height> pixels tall.
+pub fn width(height: f32) -> f32 {
+    (COLUMNS * CELL_WIDTH) as f32 * scale(height)
+}
+
+/// The size of a font pixel, as large as fits all the lines.
+fn scale(height: f32) -> f32 {
+    (height / (LINES * CELL_HEIGHT) as f32).floor().max(1.0)
+}
+
+/// Draws the panel down the right hand side of <frame>.
+pub fn draw(machine: &Machine, frame: &mut Frame) {
+    let scale = scale(frame.height());
+    let left = frame.width() - width(frame.height());
+    let mut mesh = Mesh::new();
+    let panel = Rectangle { x: left, y: 0.0, width: width(frame.height()), height: frame.height() };
+    mesh.fill(Shape::Rectangle(panel), Color::from_rgb_u32(BACKGROUND));
+    for (row, line) in lines(machine).iter().enumerate() {
+        let top = (row * CELL_HEIGHT) as f32 * scale;
+        if let Some((start, end)) = line.highlight {
+            let highlight = Rectangle {
+                x: left + (start * CELL_WIDTH) as f32 * scale,
+                y: top,
+                width: ((end - start) * CELL_WIDTH) as f32 * scale,
+                height: CELL_HEIGHT as f32 * scale
+            };
+            mesh.fill(Shape::Rectangle(highlight), Color::from_rgb_u32(HIGHLIGHT));
+        }
+        for (column, c) in line.text.chars().enumerate() {
+            let glyph = glyph(c);
+            for (y, bits) in glyph.iter().enumerate() {
+                for x in 0..3 {
+                    if bits & (0b100 >> x) == 0 {
+                        continue;
+                    }
+                    let pixel = Rectangle {
+                        x: left + ((column * CELL_WIDTH + x + 1) as f32) * scale,
+                        y: top + ((y + 1) as f32) * scale,
+                        width: scale,
+                        height: scale
+                    };
+                    mesh.fill(Shape::Rectangle(pixel), Color::from_rgb_u32(TEXT));
+                }
+            }
+        }
+    }
+    mesh.draw(&mut frame.as_target());
+}
+
+/// The registers, call stack, disassembly around <pc> and memory around
+/// <i>.
+fn lines(machine: &Machine) -> Vec<Line> {
+    let cpu = &machine.cpu;
+    let mut lines = Vec::new();
+    for (name, registers) in [("v0-7", &cpu.v()[..8]), ("v8-f", &cpu.v()[8..])] {
+        let values: Vec<String> = registers.iter().map(|v| format!("{:02x}", v)).collect();
+        lines.push(Line::new(format!("{} {}", name, values.join(" "))));
+    }
+    lines.push(Line::new(format!("i {:04x}  pc {:04x}  sp {:x}  dt {:02x}  st {:02x}",
+        cpu.i(), cpu.pc(), cpu.sp(), cpu.dt(), cpu.st())));
+
+    lines.push(Line::new(String::from("stack")));
+    let stack = cpu.stack();
+    for row in 0..2 {
+        let entries: Vec<String> = (row * 8..row * 8 + 8)
+            .map(|n| stack.get(n).map_or(String::from("----"), |addr| format!("{:04x}", addr)))
+            .collect();
+        lines.push(Line::new(entries.join(" ")));
+    }
+
+    lines.push(Line::new(String::new()));
+    let mut addr = cpu.pc().saturating_sub(DISASSEMBLY_BEFORE * 2);
+    for _ in 0..DISASSEMBLY_LINES {
+        let marker = if addr == cpu.pc() { '>' } else { ' ' };
+        let mut line = match cpu.instruction_at(addr) {
+            Ok(instruction) => {
+                let opcode: String = instruction.to_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+                let text = format!("{}{:04x} {:<8} {}", marker, addr, opcode, instruction);
+                addr = addr.wrapping_add(instruction.size());
+                Line::new(text)
+            },
+            Err(_) => {
+                addr = addr.wrapping_add(2);
+                Line::new(format!("{}{:04x} ????", marker, addr.wrapping_sub(2)))
+            }
+        };
+        if marker == '>' {
+            line.highlight = Some((0, COLUMNS));
+        }
+        lines.push(line);
+    }
+
+    lines.push(Line::new(String::new()));
+    let (start, len) = match cpu.instruction_at(cpu.pc()).ok()
+        .and_then(|instruction| debugger::access(machine, &instruction)) {
+        Some((addr, len, _)) => (addr as usize, len as usize),
+        None => (cpu.i() as usize, 1)
+    };
+    let memory = cpu.memory();
+    let first = (cpu.i() & !7).saturating_sub(8);
+    for row in 0..MEMORY_ROWS {
+        let base = first as usize + row as usize * 8;
+        if base >= memory.len() {
+            break;
+        }
+        let bytes: Vec<String> = memory[base..(base + 8).min(memory.len())].iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let mut line = Line::new(format!("{:04x} {}", base, bytes.join(" ")));
+        // highlight the bytes of this row that lie within start..start + len
+        let from = start.max(base);
+        let to = (start + len).min(base + bytes.len());
+        if from < to {
+            line.highlight = Some((5 + (from - base) * 3, 5 + (to - base) * 3 - 1));
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// The 3x5 pixel glyph for <c>, one row per byte with the leftmost pixel
+/// in bit 2. Letters are lowercase only; anything unknown draws as `?`.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_lowercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'a' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'b' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'c' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'd' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'e' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'f' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'g' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'h' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'i' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'j' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'k' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'l' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'm' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'n' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'o' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'p' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'r' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        's' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        't' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'u' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'v' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'w' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'x' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '[' => [0b110, 0b100, 0b100, 0b100, 0b110],
+        ']' => [0b011, 0b001, 0b001, 0b001, 0b011],
+        '(' => [0b010, 0b100, 0b100, 0b100, 0b010],
+        ')' => [0b010, 0b001, 0b001, 0b001, 0b010],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        '<' => [0b001, 0b010, 0b100, 0b010, 0b001],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        _ => [0b111, 0b001, 0b011, 0b000, 0b010]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::instruction::Instruction;
+
+    #[test]
+    fn glyphs() {
+        // every mnemonic can be drawn
+        let unknown = glyph('?');
+        for opcode in 0..=0xffff {
+            let text = Instruction::decode(opcode).to_string();
+            assert!(text.chars().all(|c| glyph(c) != unknown), "{}", text);
+        }
+    }
+
+    #[test]
+    fn views() {
+        // 0200: ld i, 0x300   0202: call 0206   0206: ld b, v0
+        let mut machine = Machine::new();
+        machine.load(&[0xa3, 0x00, 0x22, 0x06, 0x00, 0x00, 0xf0, 0x33]);
+        machine.cycle().unwrap();
+        machine.cycle().unwrap();
+        let lines = lines(&machine);
+        assert!(lines.iter().all(|line| line.text.len() <= COLUMNS));
+        assert!(lines.len() <= LINES);
+        assert_eq!(lines[2].text, "i 0300  pc 0206  sp 1  dt 00  st 00");
+        assert!(lines[4].text.starts_with("0204 ---- "));
+
+        let current: Vec<&Line> = lines.iter().filter(|line| line.highlight == Some((0, COLUMNS))).collect();
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0].text, ">0206 f033     ld b, v0");
+
+        // ld b highlights the 3 bytes it writes, in the second memory row
+        let memory = &lines[lines.len() - 7..];
+        assert_eq!(memory[1].text, "0300 00 00 00 00 00 00 00 00");
+        assert_eq!(memory[1].highlight, Some((5, 13)));
+        assert_eq!(memory.iter().filter(|line| line.highlight.is_some()).count(), 1);
+    }
+
+}
@@ -0,0 +1,102 @@
+use crate::state::{Reader, Writer};
+
+/// Behaviours that differ between CHIP-8 platforms. ROMs written for one
+/// platform often rely on its interpretation, so the interpreter consults
+/// these instead of hardcoding one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quirks {
+    /// `shr` and `shl` shift <vy> into <vx> rather than shifting <vx> in place.
+    pub shift_vy: bool,
+    /// `ld [i], vx` and `ld vx, [i]` leave <i> pointing past the last register.
+    pub load_store_i: bool,
+    /// `or`, `and` and `xor` reset <vf> to 0.
+    pub logic_vf: bool,
+    /// `jp v0, nnn` is decoded as BXNN and jumps to <nnn> + <vx>.
+    pub jump_vx: bool,
+    /// Sprites are clipped at the screen edges instead of wrapping around.
+    pub clip: bool,
+    /// Arithmetic writes <vf> after the result, so the flag wins when X is F.
+    pub vf_last: bool,
+    /// `drw vx, vy, 0` draws a 16x16 sprite rather than nothing.
+    pub sprite16: bool,
+    /// The size of the address space. Accessing memory past its end faults.
+    pub memory: usize
+}
+
+impl Quirks {
+
+    /// The original COSMAC VIP interpreter.
+    pub fn vip() -> Self {
+        Quirks {
+            shift_vy: true,
+            load_store_i: true,
+            logic_vf: true,
+            jump_vx: false,
+            clip: true,
+            vf_last: true,
+            sprite16: false,
+            memory: 0x1000
+        }
+    }
+
+    /// SUPER-CHIP 1.1 on the HP48.
+    pub fn schip() -> Self {
+        Quirks {
+            shift_vy: false,
+            load_store_i: false,
+            logic_vf: false,
+            jump_vx: true,
+            clip: true,
+            vf_last: true,
+            sprite16: true,
+            memory: 0x1000
+        }
+    }
+
+    /// XO-CHIP as implemented by Octo.
+    pub fn xochip() -> Self {
+        Quirks {
+            shift_vy: true,
+            load_store_i: true,
+            logic_vf: false,
+            jump_vx: false,
+            clip: false,
+            vf_last: true,
+            sprite16: true,
+            memory: 0x10000
+        }
+    }
+
+    pub fn save(&self, state: &mut Writer) {
+        state.bool(self.shift_vy);
+        state.bool(self.load_store_i);
+        state.bool(self.logic_vf);
+        state.bool(self.jump_vx);
+        state.bool(self.clip);
+        state.bool(self.vf_last);
+        state.bool(self.sprite16);
+        state.u32(self.memory as u32);
+    }
+
+    pub fn restore(&mut self, state: &mut Reader) -> Result<(), String> {
+        self.shift_vy = state.bool()?;
+        self.load_store_i = state.bool()?;
+        self.logic_vf = state.bool()?;
+        self.jump_vx = state.bool()?;
+        self.clip = state.bool()?;
+        self.vf_last = state.bool()?;
+        self.sprite16 = state.bool()?;
+        self.memory = match state.u32()? {
+            size @ 0x200..=0x10000 => size as usize,
+            size => return Err(format!("invalid address space size {:#x}", size))
+        };
+        Ok(())
+    }
+
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::schip()
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::VecDeque;
+
+/// The default memory budget for rewind history, in bytes.
+pub const DEFAULT_BUDGET: usize = 16 * 1024 * 1024;
+
+/// A ring buffer of per-frame save states, kept within a memory budget.
+///
+/// Only the newest state is kept whole. Each older state is stored as the
+/// difference from the state after it, so stepping backwards undoes one
+/// delta at a time, and the oldest delta can be dropped when over budget
+/// without breaking the chain. Consecutive frames rarely touch more than a
+/// few bytes of memory and vram, so each delta is usually tiny.
+pub struct Rewind {
+    budget: usize,
+    current: Vec<u8>,
+    deltas: VecDeque<Vec<u8>>,
+    size: usize
+}
+
+impl Rewind {
+
+    pub fn new(budget: usize) -> Self {
+        Rewind {
+            budget,
+            current: Vec::new(),
+            deltas: VecDeque::new(),
+            size: 0
+        }
+    }
+
+    /// Records <state> as the newest frame.
+    pub fn push(&mut self, state: Vec<u8>) {
+        if !self.current.is_empty() {
+            let delta = diff(&state, &self.current);
+            self.size += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.size += state.len();
+        self.size -= self.current.len();
+        self.current = state;
+        while self.size > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.size -= delta.len(),
+                None => break
+            }
+        }
+    }
+
+    /// Steps back one frame, returning the state before the newest one, or
+    /// None once the history is exhausted.
+    pub fn pop(&mut self) -> Option<&[u8]> {
+        let delta = self.deltas.pop_back()?;
+        self.size -= delta.len();
+        patch(&mut self.current, &delta);
+        Some(&self.current)
+    }
+
+    /// The number of frames that can be stepped back.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /// The memory used by the history, in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+}
+
+/// Encodes the bytes that differ between <from> and <to>, which must be the
+/// same length, as runs of `<skip> <len> <bytes>` with varint counts.
+fn diff(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    let mut i = 0;
+    while i < to.len() {
+        let start = i;
+        while i < to.len() && from[i] == to[i] {
+            i += 1;
+        }
+        if i == to.len() {
+            break;
+        }
+        let skip = i - start;
+        let start = i;
+        while i < to.len() && from[i] != to[i] {
+            i += 1;
+        }
+        varint(&mut delta, skip);
+        varint(&mut delta, i - start);
+        delta.extend_from_slice(&to[start..i]);
+    }
+    delta
+}
+
+/// Applies a delta from `diff` to <data>.
+fn patch(data: &mut [u8], delta: &[u8]) {
+    let mut offset = 0;
+    let mut rest = delta;
+    while !rest.is_empty() {
+        let skip = read_varint(&mut rest);
+        let len = read_varint(&mut rest);
+        offset += skip;
+        data[offset..offset + len].copy_from_slice(&rest[..len]);
+        rest = &rest[len..];
+        offset += len;
+    }
+}
+
+fn varint(data: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        data.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some((&byte, rest)) = data.split_first() {
+        *data = rest;
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delta() {
+        let from = vec![0u8; 300];
+        let mut to = from.clone();
+        to[0] = 1;
+        to[200] = 2;
+        to[201] = 3;
+        let delta = diff(&from, &to);
+        assert_eq!(delta, [0, 1, 1, 0xc7, 0x01, 2, 2, 3]);
+        let mut data = from.clone();
+        patch(&mut data, &delta);
+        assert_eq!(data, to);
+        assert!(diff(&to, &to).is_empty());
+    }
+
+    #[test]
+    fn pop() {
+        let mut rewind = Rewind::new(DEFAULT_BUDGET);
+        assert_eq!(rewind.pop(), None);
+        for frame in 0..4u8 {
+            rewind.push(vec![frame, 0, frame * 2]);
+        }
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.pop(), Some(&[2, 0, 4][..]));
+        assert_eq!(rewind.pop(), Some(&[1, 0, 2][..]));
+        rewind.push(vec![9, 9, 9]);
+        assert_eq!(rewind.pop(), Some(&[1, 0, 2][..]));
+        assert_eq!(rewind.pop(), Some(&[0, 0, 0][..]));
+        assert_eq!(rewind.pop(), None);
+        assert_eq!(rewind.size(), 3);
+    }
+
+    #[test]
+    fn budget() {
+        let mut rewind = Rewind::new(30);
+        for frame in 0..10u8 {
+            rewind.push(vec![frame; 8]);
+        }
+        assert!(rewind.size() <= 30);
+        assert_eq!(rewind.len(), 2);
+        assert_eq!(rewind.pop(), Some(&[8; 8][..]));
+        assert_eq!(rewind.pop(), Some(&[7; 8][..]));
+        assert_eq!(rewind.pop(), None);
+    }
+
+}
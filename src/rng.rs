@@ -0,0 +1,177 @@
+use std::fs;
+use std::path::Path;
+
+/// A source of random bytes for `rnd`.
+///
+/// Generators are seedable, so runs can be repeated, and expose their whole
+/// state as a single word for save states.
+pub trait Random {
+    /// A short name identifying the algorithm, recorded in movies.
+    fn name(&self) -> &'static str;
+
+    /// Restarts the sequence from <seed>.
+    fn seed(&mut self, seed: u64);
+
+    fn byte(&mut self) -> u8;
+
+    /// Called once per 60 Hz frame, for generators that depend on timing.
+    fn tick(&mut self) {}
+
+    fn state(&self) -> u64;
+
+    fn set_state(&mut self, state: u64);
+}
+
+/// SplitMix64, the default generator.
+///
+/// Chosen over `rand`'s generators because its sequence for a given seed is
+/// fixed forever, which recorded movies rely on.
+pub struct SplitMix {
+    state: u64
+}
+
+impl SplitMix {
+
+    pub fn new(seed: u64) -> Self {
+        SplitMix { state: seed }
+    }
+
+    /// Seeds from the operating system, for runs that needn't be repeatable.
+    pub fn from_entropy() -> Self {
+        SplitMix::new(rand::random())
+    }
+
+}
+
+impl Random for SplitMix {
+
+    fn name(&self) -> &'static str {
+        "splitmix"
+    }
+
+    fn seed(&mut self, seed: u64) {
+        self.state = seed;
+    }
+
+    fn byte(&mut self) -> u8 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        (z ^ (z >> 31)) as u8
+    }
+
+    fn state(&self) -> u64 {
+        self.state
+    }
+
+    fn set_state(&mut self, state: u64) {
+        self.state = state;
+    }
+
+}
+
+/// The size of the COSMAC VIP CHIP-8 interpreter.
+const VIP_INTERPRETER_SIZE: usize = 0x200;
+
+/// The pseudo-random algorithm of the original COSMAC VIP interpreter.
+///
+/// The interpreter keeps its seed in register R9, which its 60 Hz interrupt
+/// routine increments. `rnd` increments R9 again, adds the byte at
+/// 0x0100 + R9.0 (part of the interpreter's own code) to R9.1, rotates the
+/// sum right through the carry, adds the unrotated sum back and stores the
+/// result in R9.1. R9.1 is the random byte.
+///
+/// The interpreter isn't distributed with the emulator, so the code page
+/// the algorithm reads from is taken from a dump of it.
+pub struct Vip {
+    r9: u16,
+    page: [u8; 0x100]
+}
+
+impl Vip {
+
+    /// Reads the code page from <interpreter>, the 512 bytes of the VIP
+    /// CHIP-8 interpreter as loaded at address 0.
+    pub fn new(interpreter: &[u8]) -> Result<Self, String> {
+        if interpreter.len() != VIP_INTERPRETER_SIZE {
+            return Err(format!("expected the {} byte VIP interpreter, got {} bytes",
+                VIP_INTERPRETER_SIZE, interpreter.len()));
+        }
+        let mut page = [0; 0x100];
+        page.copy_from_slice(&interpreter[0x100..]);
+        Ok(Vip { r9: 0, page })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let interpreter = fs::read(path)
+            .map_err(|e| format!("can't read VIP interpreter '{}': {}", path.display(), e))?;
+        Vip::new(&interpreter).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+}
+
+impl Random for Vip {
+
+    fn name(&self) -> &'static str {
+        "vip"
+    }
+
+    fn seed(&mut self, seed: u64) {
+        self.r9 = seed as u16;
+    }
+
+    fn byte(&mut self) -> u8 {
+        self.r9 = self.r9.wrapping_add(1);
+        let [high, low] = self.r9.to_be_bytes();
+        let (sum, carry) = high.overflowing_add(self.page[low as usize]);
+        let rotated = (sum >> 1) | ((carry as u8) << 7);
+        let high = rotated.wrapping_add(sum);
+        self.r9 = u16::from_be_bytes([high, low]);
+        high
+    }
+
+    fn tick(&mut self) {
+        self.r9 = self.r9.wrapping_add(1);
+    }
+
+    fn state(&self) -> u64 {
+        self.r9 as u64
+    }
+
+    fn set_state(&mut self, state: u64) {
+        self.r9 = state as u16;
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splitmix() {
+        // the low bytes of SplitMix64's reference output for seed 0
+        let mut rng = SplitMix::new(0);
+        let bytes: Vec<u8> = (0..4).map(|_| rng.byte()).collect();
+        assert_eq!(bytes, [0xaf, 0xf4, 0x4f, 0xec]);
+    }
+
+    #[test]
+    fn vip() {
+        let interpreter: Vec<u8> = (0..0x200).map(|n| n as u8).collect();
+        let mut rng = Vip::new(&interpreter).unwrap();
+        let bytes: Vec<u8> = (0..3).map(|_| rng.byte()).collect();
+        assert_eq!(bytes, [0x01, 0x04, 0x0a]);
+        assert_eq!(rng.state(), 0x0a03);
+
+        // the sum carries into bit 7 when rotated
+        rng.seed(0xff00);
+        assert_eq!(rng.byte(), 0x80);
+        rng.tick();
+        assert_eq!(rng.state(), 0x8002);
+
+        assert!(Vip::new(&interpreter[..0x100]).is_err());
+    }
+
+}
@@ -0,0 +1,141 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A line of a source file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    pub file: PathBuf,
+    /// The line number, counting from 1.
+    pub line: u32
+}
+
+/// Which source line each instruction of a ROM was assembled from, so a
+/// debugger can show source and set breakpoints by line.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SourceMap {
+    entries: Vec<(u16, Location)>
+}
+
+impl SourceMap {
+
+    pub fn new() -> Self {
+        SourceMap { entries: Vec::new() }
+    }
+
+    /// Records that the instruction at <addr> came from <line> of <file>.
+    pub fn add(&mut self, addr: u16, file: &Path, line: u32) {
+        self.entries.push((addr, Location { file: file.to_path_buf(), line }));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Parses lines of `<addr> <file>:<line>`, such as
+    /// `0x0204 game.asm:12`. Blank lines and lines starting with `#` are
+    /// skipped.
+    pub fn parse(text: &str) -> Result<SourceMap, String> {
+        let mut map = SourceMap::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = line.split_once(' ').and_then(|(addr, location)| {
+                let addr = u16::from_str_radix(addr.strip_prefix("0x")?, 16).ok()?;
+                let (file, line) = location.trim().rsplit_once(':')?;
+                Some((addr, Location { file: PathBuf::from(file), line: line.parse().ok()? }))
+            });
+            match entry {
+                Some(entry) => map.entries.push(entry),
+                None => return Err(format!("{}: expected <addr> <file>:<line>, got '{}'", n + 1, line))
+            }
+        }
+        Ok(map)
+    }
+
+    /// Reads a source map, taking relative source paths as relative to
+    /// the map.
+    pub fn load(path: &Path) -> Result<SourceMap, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("can't read source map '{}': {}", path.display(), e))?;
+        let mut map = SourceMap::parse(&text).map_err(|e| format!("{}:{}", path.display(), e))?;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        for (_, location) in map.entries.iter_mut() {
+            location.file = base.join(&location.file);
+        }
+        Ok(map)
+    }
+
+    /// Rewrites source paths relative to <dir>, where the map will be
+    /// saved, so that `load` finds them again. Sources outside <dir> get
+    /// absolute paths.
+    pub fn relative_to(&mut self, dir: &Path) {
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        for (_, location) in self.entries.iter_mut() {
+            if let Ok(file) = location.file.canonicalize() {
+                location.file = file.strip_prefix(&dir).map_or(file.clone(), Path::to_path_buf);
+            }
+        }
+    }
+
+    /// The source line the instruction at <addr> came from.
+    pub fn location(&self, addr: u16) -> Option<&Location> {
+        self.entries.iter().find(|(start, _)| *start == addr).map(|(_, location)| location)
+    }
+
+    /// The first line at or after <line> of <file> that produced an
+    /// instruction, and that instruction's address, so a breakpoint on a
+    /// blank line or comment lands on the code that follows it.
+    pub fn address(&self, file: &Path, line: u32) -> Option<(u32, u16)> {
+        self.entries.iter()
+            .filter(|(_, location)| location.line >= line && same_file(&location.file, file))
+            .map(|(addr, location)| (location.line, *addr))
+            .min()
+    }
+
+}
+
+/// Whether <a> and <b> name the same file, falling back to comparing the
+/// paths when either doesn't exist.
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b
+    }
+}
+
+/// The format read by `parse`.
+impl fmt::Display for SourceMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (addr, location) in self.entries.iter() {
+            writeln!(f, "{:#06x} {}:{}", addr, location.file.display(), location.line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines() {
+        let game = Path::new("game.asm");
+        let mut map = SourceMap::new();
+        map.add(0x200, game, 3);
+        map.add(0x202, game, 4);
+        map.add(0x204, Path::new("lib.asm"), 1);
+        map.add(0x206, game, 7);
+
+        assert_eq!(SourceMap::parse(&map.to_string()), Ok(map.clone()));
+        assert_eq!(map.location(0x206), Some(&Location { file: PathBuf::from("game.asm"), line: 7 }));
+        assert_eq!(map.location(0x208), None);
+        assert_eq!(map.address(game, 4), Some((4, 0x202)));
+        assert_eq!(map.address(game, 5), Some((7, 0x206)));
+        assert_eq!(map.address(game, 8), None);
+        assert!(SourceMap::parse("0x0200 game.asm").is_err());
+    }
+
+}
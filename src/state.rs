@@ -0,0 +1,207 @@
+/// Identifies a save state file.
+pub const MAGIC: &[u8; 4] = b"C8ST";
+
+/// The save state format version. Bump it whenever the layout of any
+/// component's state changes, so old states are rejected rather than
+/// misread.
+pub const VERSION: u16 = 3;
+
+/// Serializes machine state and other little-endian binary formats.
+pub struct Writer {
+    data: Vec<u8>
+}
+
+impl Default for Writer {
+    fn default() -> Self {
+        Writer::new()
+    }
+}
+
+impl Writer {
+
+    pub fn new() -> Self {
+        Writer { data: Vec::new() }
+    }
+
+    /// Starts a file with <magic> and the format <version>.
+    pub fn header(&mut self, magic: &[u8; 4], version: u16) {
+        self.bytes(magic);
+        self.u16(version);
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+
+}
+
+/// Deserializes data written by `Writer`.
+pub struct Reader<'a> {
+    data: &'a [u8]
+}
+
+impl<'a> Reader<'a> {
+
+    pub fn new(data: &'a [u8]) -> Self {
+        Reader { data }
+    }
+
+    /// Checks that the file starts with <magic> and the format <version>.
+    /// <kind> names the file in errors.
+    pub fn header(&mut self, magic: &[u8; 4], version: u16, kind: &str) -> Result<(), String> {
+        if self.bytes(magic.len()).ok() != Some(&magic[..]) {
+            return Err(format!("not a {}", kind));
+        }
+        match self.u16()? {
+            v if v == version => Ok(()),
+            v => Err(format!("unsupported {} version {} (expected {})", kind, v, version))
+        }
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(format!("invalid flag {:#04x}", value))
+        }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < len {
+            return Err(String::from("unexpected end of file"));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    /// Whether the whole file has been read.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Checks that the whole file was read.
+    pub fn finish(self) -> Result<(), String> {
+        match self.data.len() {
+            0 => Ok(()),
+            n => Err(format!("{} unexpected bytes at the end of file", n))
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::machine::Machine;
+
+    #[test]
+    fn header() {
+        let mut writer = Writer::new();
+        writer.header(MAGIC, VERSION);
+        let data = writer.finish();
+        assert_eq!(Reader::new(&data).header(MAGIC, VERSION, "save state"), Ok(()));
+        assert_eq!(Reader::new(b"C8S").header(MAGIC, VERSION, "save state"),
+            Err(String::from("not a save state")));
+        assert_eq!(Reader::new(b"C8ST\x01\x00").header(MAGIC, VERSION, "save state"),
+            Err(String::from("unsupported save state version 1 (expected 3)")));
+    }
+
+    #[test]
+    fn values() {
+        let mut writer = Writer::new();
+        writer.u8(0x12);
+        writer.u16(0x3456);
+        writer.u32(0x789a_bcde);
+        writer.u64(0x0123_4567_89ab_cdef);
+        writer.bool(true);
+        writer.bytes(&[1, 2, 3]);
+        let data = writer.finish();
+
+        let mut reader = Reader::new(&data);
+        assert_eq!(reader.u8(), Ok(0x12));
+        assert_eq!(reader.u16(), Ok(0x3456));
+        assert_eq!(reader.u32(), Ok(0x789a_bcde));
+        assert_eq!(reader.u64(), Ok(0x0123_4567_89ab_cdef));
+        assert_eq!(reader.bool(), Ok(true));
+        assert_eq!(reader.bytes(3), Ok(&[1, 2, 3][..]));
+        assert_eq!(reader.u8(), Err(String::from("unexpected end of file")));
+        assert_eq!(reader.finish(), Ok(()));
+    }
+
+    #[test]
+    fn round_trip() {
+        // 0200: ld v0, 0    0202: ld f, v0    0204: drw v0, v0, 5
+        // 0206: add v0, 1   0208: ld dt, v0   020a: jp 0204
+        let rom = [0x60, 0x00, 0xf0, 0x29, 0xd0, 0x05, 0x70, 0x01, 0xf0, 0x15, 0x12, 0x04];
+        let mut machine = Machine::new();
+        machine.load(&rom);
+        machine.set_instructions_per_frame(3);
+        for _ in 0..5 {
+            machine.frame().unwrap();
+        }
+        machine.keypad.set(7, true);
+        let phase = Duration::from_millis(7);
+        let state = machine.save_state(phase);
+        let pixels = machine.framebuffer().pixels.to_vec();
+        let pc = machine.cpu.pc();
+
+        for _ in 0..5 {
+            machine.frame().unwrap();
+        }
+        machine.keypad.set(7, false);
+        assert_ne!(machine.framebuffer().pixels, &pixels[..]);
+
+        assert_eq!(machine.load_state(&state), Ok(phase));
+        assert_eq!(machine.framebuffer().pixels, &pixels[..]);
+        assert_eq!(machine.cpu.pc(), pc);
+        assert!(machine.keypad.get(7));
+        assert_eq!(machine.save_state(phase), state);
+    }
+
+}
@@ -1,38 +1,84 @@
 use std::time::{Instant, Duration};
 
+/// The rate at which frames are scheduled and the delay and sound timers
+/// count down.
+pub const FRAME_RATE: u32 = 60;
+
+/// The most frames a single tick will catch up on after a stall.
+const MAX_FRAMES: u32 = 8;
+
+/// A drift-free frame clock.
+///
+/// Elapsed time accumulates across ticks, and only whole periods are
+/// consumed, so the remainder carries over instead of being lost.
 pub struct Timer {
-    frequency: Duration,
+    period: Duration,
     clock: Instant,
-    state: bool
+    accumulator: Duration
 }
 
 impl Timer {
 
-    pub fn new(frequency_ns: u32) -> Self {
+    pub fn new(frequency_hz: u32) -> Self {
         Timer {
-            frequency: Duration::new(0, frequency_ns),
+            period: Duration::from_secs(1) / frequency_hz,
             clock: Instant::now(),
-            state: false
+            accumulator: Duration::from_secs(0)
         }
     }
 
     pub fn reset(&mut self) {
-        self.state = false;
         self.clock = Instant::now();
+        self.accumulator = Duration::from_secs(0);
+    }
+
+    /// Time accumulated towards the next frame.
+    pub fn phase(&self) -> Duration {
+        self.accumulator
+    }
+
+    /// Resumes from <phase>, as returned by `phase`.
+    pub fn set_phase(&mut self, phase: Duration) {
+        self.clock = Instant::now();
+        self.accumulator = phase.min(self.period);
     }
 
-    pub fn active(&self) -> bool {
-        self.state
+    /// Returns the number of frames due since the last tick.
+    pub fn tick(&mut self) -> u32 {
+        let now = Instant::now();
+        let elapsed = now - self.clock;
+        self.clock = now;
+        self.advance(elapsed)
     }
 
-    pub fn tick(&mut self) {
-        self.state = if self.clock.elapsed() >= self.frequency {
-            self.clock = Instant::now();
-            true
+    fn advance(&mut self, elapsed: Duration) -> u32 {
+        self.accumulator += elapsed;
+        let mut frames = 0;
+        while self.accumulator >= self.period {
+            self.accumulator -= self.period;
+            frames += 1;
         }
-        else {
-            false
+        if frames > MAX_FRAMES {
+            frames = MAX_FRAMES;
         }
+        frames
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advance() {
+        let mut timer = Timer::new(FRAME_RATE);
+        let period = Duration::from_secs(1) / FRAME_RATE;
+        assert_eq!(timer.advance(period / 2), 0);
+        assert_eq!(timer.advance(period / 2), 1);
+        assert_eq!(timer.advance(period * 3 + period / 4), 3);
+        assert_eq!(timer.phase(), period / 4);
+        assert_eq!(timer.advance(period * 100), MAX_FRAMES);
+    }
+
+}
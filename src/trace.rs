@@ -0,0 +1,344 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::instruction::{Class, Instruction};
+use crate::state::{Reader, Writer};
+
+/// Identifies a binary trace file.
+const MAGIC: &[u8; 4] = b"C8TR";
+
+/// The binary trace format version.
+pub const VERSION: u16 = 1;
+
+/// How trace records are written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// One line per instruction.
+    Text,
+    /// A header followed by fixed-order little-endian records.
+    Binary
+}
+
+impl Format {
+
+    pub fn parse(name: &str) -> Option<Format> {
+        match name {
+            "text" | "txt" => Some(Format::Text),
+            "binary" | "bin" => Some(Format::Binary),
+            _ => None
+        }
+    }
+
+    /// Binary for `.bin` files, otherwise text.
+    pub fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("bin") => Format::Binary,
+            _ => Format::Text
+        }
+    }
+
+}
+
+/// A register changed by an instruction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Delta {
+    pub register: u8,
+    pub old: u8,
+    pub new: u8
+}
+
+/// One executed instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    /// The frame the instruction ran in, counting from 0.
+    pub frame: u32,
+    pub pc: u16,
+    pub instruction: Instruction,
+    /// The V registers the instruction changed.
+    pub deltas: Vec<Delta>,
+    /// <i> after the instruction.
+    pub i: u16
+}
+
+impl Record {
+
+    /// Builds a record from the registers before and after <instruction>.
+    pub fn new(frame: u32, pc: u16, instruction: Instruction, before: &[u8; 16], after: &[u8; 16], i: u16) -> Self {
+        let deltas = (0..16)
+            .filter(|&r| before[r] != after[r])
+            .map(|r| Delta { register: r as u8, old: before[r], new: after[r] })
+            .collect();
+        Record { frame, pc, instruction, deltas, i }
+    }
+
+    /// Parses a line of a text trace. The mnemonic is ignored in favour of
+    /// the opcode, so traces from other emulators only need to match the
+    /// columns, not the assembly syntax.
+    pub fn parse(line: &str) -> Result<Record, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() < 4 {
+            return Err(String::from("expected <frame> <pc> <opcode> ... i=<i>"));
+        }
+        let frame = words[0].parse().map_err(|_| format!("invalid frame '{}'", words[0]))?;
+        let pc = hex(words[1]).ok_or(format!("invalid address '{}'", words[1]))?;
+        let word = |at: usize| words[2].get(at..at + 4).and_then(|word| u16::from_str_radix(word, 16).ok());
+        let instruction = match (words[2].len(), word(0).map(Instruction::decode)) {
+            (8, Some(Instruction::LdILong(_))) => word(4).map(Instruction::LdILong),
+            (4, instruction) => instruction,
+            _ => None
+        }.ok_or(format!("invalid opcode '{}'", words[2]))?;
+        let last = words[words.len() - 1];
+        let i = last.strip_prefix("i=").and_then(hex).ok_or(format!("expected i=<i>, got '{}'", last))?;
+        let mut deltas = Vec::new();
+        for word in words[3..words.len() - 1].iter().rev() {
+            match delta(word) {
+                Some(delta) => deltas.insert(0, delta),
+                None => break
+            }
+        }
+        Ok(Record { frame, pc, instruction, deltas, i })
+    }
+
+    fn save(&self, writer: &mut Writer) {
+        writer.u32(self.frame);
+        writer.u16(self.pc);
+        writer.u16(self.instruction.encode());
+        if let Instruction::LdILong(nnnn) = self.instruction {
+            writer.u16(nnnn);
+        }
+        writer.u16(self.i);
+        writer.u8(self.deltas.len() as u8);
+        for delta in self.deltas.iter() {
+            writer.bytes(&[delta.register, delta.old, delta.new]);
+        }
+    }
+
+    fn restore(reader: &mut Reader) -> Result<Record, String> {
+        let frame = reader.u32()?;
+        let pc = reader.u16()?;
+        let instruction = match Instruction::decode(reader.u16()?) {
+            Instruction::LdILong(_) => Instruction::LdILong(reader.u16()?),
+            instruction => instruction
+        };
+        let i = reader.u16()?;
+        let count = reader.u8()?;
+        let mut deltas = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let bytes = reader.bytes(3)?;
+            deltas.push(Delta { register: bytes[0], old: bytes[1], new: bytes[2] });
+        }
+        Ok(Record { frame, pc, instruction, deltas, i })
+    }
+
+}
+
+/// `<frame> <pc> <opcode> <mnemonic> <deltas> i=<i>`, for example
+/// `    12 0x0204 7001      add v0, 0x01         v0=04->05 i=0x0050`.
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let opcode: String = self.instruction.to_bytes().iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let mnemonic = self.instruction.to_string();
+        write!(f, "{:>6} {:#06x} {:<8}  {:<20}", self.frame, self.pc, opcode, mnemonic)?;
+        for delta in self.deltas.iter() {
+            write!(f, " v{:x}={:02x}->{:02x}", delta.register, delta.old, delta.new)?;
+        }
+        write!(f, " i={:#06x}", self.i)
+    }
+}
+
+/// Parses a binary trace.
+pub fn parse(data: &[u8]) -> Result<Vec<Record>, String> {
+    let mut reader = Reader::new(data);
+    reader.header(MAGIC, VERSION, "trace")?;
+    let mut records = Vec::new();
+    while !reader.is_empty() {
+        records.push(Record::restore(&mut reader)?);
+    }
+    Ok(records)
+}
+
+/// Reads a trace in either format.
+pub fn read(path: &Path) -> Result<Vec<Record>, String> {
+    let data = fs::read(path)
+        .map_err(|e| format!("can't read trace '{}': {}", path.display(), e))?;
+    if data.starts_with(MAGIC) {
+        return parse(&data).map_err(|e| format!("{}: {}", path.display(), e));
+    }
+    let text = String::from_utf8_lossy(&data);
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(n, line)| Record::parse(line).map_err(|e| format!("{}:{}: {}", path.display(), n + 1, e)))
+        .collect()
+}
+
+fn hex(word: &str) -> Option<u16> {
+    u16::from_str_radix(word.strip_prefix("0x")?, 16).ok()
+}
+
+/// Parses `v<x>=<old>-><new>`.
+fn delta(word: &str) -> Option<Delta> {
+    let (register, values) = word.strip_prefix('v')?.split_once('=')?;
+    let (old, new) = values.split_once("->")?;
+    Some(Delta {
+        register: u8::from_str_radix(register, 16).ok().filter(|&r| r < 16)?,
+        old: u8::from_str_radix(old, 16).ok()?,
+        new: u8::from_str_radix(new, 16).ok()?
+    })
+}
+
+/// Which instructions to trace. Every set condition must match.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Filter {
+    /// Addresses to trace, inclusive.
+    pub addresses: Option<(u16, u16)>,
+    /// Classes of instruction to trace, or all if empty.
+    pub classes: Vec<Class>,
+    /// Frames to trace, inclusive.
+    pub frames: Option<(u32, u32)>
+}
+
+impl Filter {
+
+    pub fn matches(&self, frame: u32, pc: u16, instruction: &Instruction) -> bool {
+        self.addresses.is_none_or(|(start, end)| (start..=end).contains(&pc)) &&
+        self.frames.is_none_or(|(start, end)| (start..=end).contains(&frame)) &&
+        (self.classes.is_empty() || self.classes.contains(&instruction.class()))
+    }
+
+}
+
+/// Writes trace records to a file.
+pub struct Tracer {
+    out: Box<dyn Write>,
+    format: Format,
+    pub filter: Filter,
+    error: Option<io::Error>
+}
+
+impl Tracer {
+
+    pub fn new(mut out: Box<dyn Write>, format: Format, filter: Filter) -> Self {
+        let mut error = None;
+        if format == Format::Binary {
+            let mut writer = Writer::new();
+            writer.header(MAGIC, VERSION);
+            error = out.write_all(&writer.finish()).err();
+        }
+        Tracer { out, format, filter, error }
+    }
+
+    pub fn create(path: &Path, format: Format, filter: Filter) -> Result<Self, String> {
+        let file = File::create(path)
+            .map_err(|e| format!("can't create trace '{}': {}", path.display(), e))?;
+        Ok(Tracer::new(Box::new(BufWriter::new(file)), format, filter))
+    }
+
+    /// Writes <record>. After a write error, records are dropped and the
+    /// error is returned from `finish`.
+    pub fn trace(&mut self, record: &Record) {
+        if self.error.is_some() {
+            return;
+        }
+        let result = match self.format {
+            Format::Text => writeln!(self.out, "{}", record),
+            Format::Binary => {
+                let mut writer = Writer::new();
+                record.save(&mut writer);
+                self.out.write_all(&writer.finish())
+            }
+        };
+        self.error = result.err();
+    }
+
+    /// Flushes the trace, reporting the first write error.
+    pub fn finish(mut self) -> Result<(), String> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush()
+        }.map_err(|e| format!("can't write trace: {}", e))
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// A sink the test can read back after the tracer is done with it.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn records() -> Vec<Record> {
+        let mut before = [0; 16];
+        before[0] = 4;
+        let mut after = before;
+        after[0] = 5;
+        vec![
+            Record::new(12, 0x204, Instruction::AddVxKk { x: 0, kk: 1 }, &before, &after, 0x50),
+            Record::new(12, 0x206, Instruction::LdILong(0x1234), &after, &after, 0x1234)
+        ]
+    }
+
+    #[test]
+    fn text() {
+        let lines: Vec<String> = records().iter().map(|record| record.to_string()).collect();
+        assert_eq!(lines, [
+            "    12 0x0204 7001      add v0, 0x01         v0=04->05 i=0x0050",
+            "    12 0x0206 f0001234  ld i, long 0x1234    i=0x1234"
+        ]);
+    }
+
+    #[test]
+    fn parse_text() {
+        for record in records() {
+            assert_eq!(Record::parse(&record.to_string()), Ok(record));
+        }
+        assert!(Record::parse("12 0x0204 70 add v0, 0x01 i=0x0050").is_err());
+        assert!(Record::parse("12 0x0204 7001 add v0, 0x01").is_err());
+    }
+
+    #[test]
+    fn binary() {
+        let out = Shared::default();
+        let mut tracer = Tracer::new(Box::new(out.clone()), Format::Binary, Filter::default());
+        for record in records().iter() {
+            tracer.trace(record);
+        }
+        tracer.finish().unwrap();
+        assert_eq!(parse(&out.0.borrow()), Ok(records()));
+    }
+
+    #[test]
+    fn filter() {
+        let add = Instruction::AddVxKk { x: 0, kk: 1 };
+        let drw = Instruction::Drw { x: 0, y: 1, n: 5 };
+        let filter = Filter {
+            addresses: Some((0x200, 0x2ff)),
+            classes: vec![Class::Display],
+            frames: Some((10, 20))
+        };
+        assert!(filter.matches(10, 0x200, &drw));
+        assert!(!filter.matches(10, 0x200, &add));
+        assert!(!filter.matches(21, 0x200, &drw));
+        assert!(!filter.matches(10, 0x300, &drw));
+        assert!(Filter::default().matches(0, 0, &add));
+    }
+
+}